@@ -1,5 +1,8 @@
 use async_trait::async_trait;
-use async_trait_experiments::{box_future, DynamicFuture, RecyclableFutureAllocator};
+use async_trait_experiments::{
+    box_future, DynamicFuture, LocalRecyclableFutureAllocator, RecyclableFutureAllocator,
+};
+use async_trait_experiments_macros::recyclable_async;
 use std::{
     future::Future,
     pin::Pin,
@@ -104,6 +107,50 @@ impl DynamicFutureAsyncTraitAdder for DynamicRecyclableFutureAsyncTraitAdderImpl
     }
 }
 
+#[derive(Default)]
+pub struct LocalRecyclableFutureAsyncTraitAdderImpl {
+    state: AdderState,
+    add_obj_recycler: LocalRecyclableFutureAllocator,
+}
+
+impl LocalRecyclableFutureAsyncTraitAdderImpl {
+    pub fn current(&self) -> u32 {
+        self.state.current
+    }
+}
+
+impl DynamicFutureAsyncTraitAdder for LocalRecyclableFutureAsyncTraitAdderImpl {
+    fn add_obj<'a>(&'a mut self, a: u32, b: u32) -> DynamicFuture<'a, u32> {
+        let state = &mut self.state;
+
+        self.add_obj_recycler.allocate(async move {
+            let mut storage = [0u32; 64];
+            let result = a + b;
+            Yielder::new(NR_YIELDS).await;
+            state.current = result;
+            storage[4] = result;
+            storage[4]
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct MacroAdderState {
+    pub current: u32,
+}
+
+#[recyclable_async(pub MacroRecyclableFutureAsyncTraitAdderImpl, impl DynamicFutureAsyncTraitAdder)]
+impl MacroAdderState {
+    async fn add_obj(&mut self, a: u32, b: u32) -> u32 {
+        let mut storage = [0u32; 64];
+        let result = a + b;
+        Yielder::new(NR_YIELDS).await;
+        self.current = result;
+        storage[4] = result;
+        storage[4]
+    }
+}
+
 #[derive(Default)]
 pub struct DynamicBoxedFutureAsyncTraitAdderImpl {
     state: AdderState,
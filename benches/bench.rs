@@ -1,4 +1,10 @@
 //! Benchmarks for asynchronous Mutex implementations
+//!
+//! The adder benches compare the atomic refcount of `RecyclableFutureAllocator`
+//! with the non-atomic refcount of `LocalRecyclableFutureAllocator`. The other
+//! recyclable benchmarks can be run with
+//! `RUSTFLAGS="--cfg async_trait_experiments_single_threaded"` in order to
+//! compare atomic and non-atomic refcounts.
 
 #[cfg(not(target_env = "msvc"))]
 use jemallocator::Jemalloc;
@@ -7,12 +13,19 @@ use jemallocator::Jemalloc;
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
-use criterion::{criterion_group, criterion_main, Benchmark, Criterion};
+use async_trait_experiments::{DynamicFutureInline, RecyclableFutureAllocator};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
 mod adder;
 use adder::{
     AsyncTraitAdder, AsyncTraitAdderImpl, BoxPinFutureTraitAdder, BoxPinFutureTraitAdderImpl,
     DynamicBoxedFutureAsyncTraitAdderImpl, DynamicFutureAsyncTraitAdder,
-    DynamicRecyclableFutureAsyncTraitAdderImpl, NoTraitAdder,
+    DynamicRecyclableFutureAsyncTraitAdderImpl, LocalRecyclableFutureAsyncTraitAdderImpl,
+    MacroRecyclableFutureAsyncTraitAdderImpl, NoTraitAdder, Yielder,
 };
 mod stream;
 use stream::{
@@ -24,150 +37,251 @@ use stream::{
 
 const ADDER_ITERATIONS: usize = 50;
 const STREAM_ITERATIONS: u32 = 50;
+const POLL_ITERATIONS: usize = 50;
 
 fn adder_benches(c: &mut Criterion) {
-    c.bench(
-        "adder benches",
-        Benchmark::new("no trait", |b| {
-            b.iter(|| {
-                let mut adder = NoTraitAdder::default();
-                futures::executor::block_on(async {
-                    for _ in 0..ADDER_ITERATIONS {
-                        assert_eq!(25, adder.add_obj(5, 20).await);
-                        assert_eq!(25, adder.current);
-                    }
-                });
+    let mut group = c.benchmark_group("adder benches");
+    group.bench_function("no trait", |b| {
+        b.iter(|| {
+            let mut adder = NoTraitAdder::default();
+            futures::executor::block_on(async {
+                for _ in 0..ADDER_ITERATIONS {
+                    assert_eq!(25, adder.add_obj(5, 20).await);
+                    assert_eq!(25, adder.current);
+                }
+            });
+        });
+    });
+    group.bench_function("async-trait", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut adder = AsyncTraitAdderImpl::default();
+                for _ in 0..ADDER_ITERATIONS {
+                    assert_eq!(25, adder.add_obj(5, 20).await);
+                    assert_eq!(25, adder.current);
+                }
+            });
+        });
+    });
+    group.bench_function("Pin<Box<dyn Future>>", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut adder = BoxPinFutureTraitAdderImpl::default();
+                for _ in 0..ADDER_ITERATIONS {
+                    assert_eq!(25, adder.add_obj(5, 20).await);
+                    assert_eq!(25, adder.current);
+                }
             });
-        })
-        .with_function("async-trait", |b| {
-            b.iter(|| {
-                futures::executor::block_on(async {
-                    let mut adder = AsyncTraitAdderImpl::default();
-                    for _ in 0..ADDER_ITERATIONS {
-                        assert_eq!(25, adder.add_obj(5, 20).await);
-                        assert_eq!(25, adder.current);
-                    }
-                });
+        });
+    });
+    group.bench_function("non recyclable DynamicFuture", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut adder = DynamicBoxedFutureAsyncTraitAdderImpl::default();
+                for _ in 0..ADDER_ITERATIONS {
+                    assert_eq!(25, adder.add_obj(5, 20).await);
+                    assert_eq!(25, adder.current());
+                }
             });
-        })
-        .with_function("Pin<Box<dyn Future>>", |b| {
-            b.iter(|| {
-                futures::executor::block_on(async {
-                    let mut adder = BoxPinFutureTraitAdderImpl::default();
-                    for _ in 0..ADDER_ITERATIONS {
-                        assert_eq!(25, adder.add_obj(5, 20).await);
-                        assert_eq!(25, adder.current);
-                    }
-                });
+        });
+    });
+    group.bench_function("recyclable DynamicFuture", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut adder = DynamicRecyclableFutureAsyncTraitAdderImpl::default();
+                for _ in 0..ADDER_ITERATIONS {
+                    assert_eq!(25, adder.add_obj(5, 20).await);
+                    assert_eq!(25, adder.current());
+                }
             });
-        })
-        .with_function("non recyclable DynamicFuture", |b| {
-            b.iter(|| {
-                futures::executor::block_on(async {
-                    let mut adder = DynamicBoxedFutureAsyncTraitAdderImpl::default();
-                    for _ in 0..ADDER_ITERATIONS {
-                        assert_eq!(25, adder.add_obj(5, 20).await);
-                        assert_eq!(25, adder.current());
-                    }
-                });
+        });
+    });
+    group.bench_function("recyclable DynamicFuture with non-atomic refcount", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut adder = LocalRecyclableFutureAsyncTraitAdderImpl::default();
+                for _ in 0..ADDER_ITERATIONS {
+                    assert_eq!(25, adder.add_obj(5, 20).await);
+                    assert_eq!(25, adder.current());
+                }
             });
-        })
-        .with_function("recyclable DynamicFuture", |b| {
-            b.iter(|| {
-                futures::executor::block_on(async {
-                    let mut adder = DynamicRecyclableFutureAsyncTraitAdderImpl::default();
-                    for _ in 0..ADDER_ITERATIONS {
-                        assert_eq!(25, adder.add_obj(5, 20).await);
-                        assert_eq!(25, adder.current());
-                    }
-                });
+        });
+    });
+    group.bench_function("recyclable DynamicFuture via macro", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut adder = MacroRecyclableFutureAsyncTraitAdderImpl::default();
+                for _ in 0..ADDER_ITERATIONS {
+                    assert_eq!(25, adder.add_obj(5, 20).await);
+                    assert_eq!(25, adder.state().current);
+                }
             });
-        }),
-    );
+        });
+    });
+    group.finish();
+}
+
+/// Polls `fut` until it completes, with a waker which does nothing
+fn poll_to_completion<F: Future + Unpin>(mut fut: F) -> F::Output {
+    let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+    loop {
+        if let Poll::Ready(output) = Pin::new(&mut fut).poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+fn poll_benches(c: &mut Criterion) {
+    let mut group = c.benchmark_group("poll benches");
+    group.bench_function("recyclable DynamicFuture", |b| {
+        let mut recycler = RecyclableFutureAllocator::new();
+        b.iter(|| {
+            let fut = recycler.allocate(Yielder::new(POLL_ITERATIONS));
+            poll_to_completion(fut);
+        });
+    });
+    // The future is converted once, and polled `POLL_ITERATIONS + 1` times
+    group.bench_function("recyclable DynamicFutureInline", |b| {
+        let mut recycler = RecyclableFutureAllocator::new();
+        b.iter(|| {
+            let fut = DynamicFutureInline::from(recycler.allocate(Yielder::new(POLL_ITERATIONS)));
+            poll_to_completion(fut);
+        });
+    });
+    group.finish();
 }
 
 fn stream_benches(c: &mut Criterion) {
-    c.bench(
-        "stream_benches",
-        Benchmark::new("no trait", |b| {
-            b.iter(|| {
-                let mut stream = NoTraitStream::new(STREAM_ITERATIONS);
-                futures::executor::block_on(async {
-                    while let Some(_item) = stream.next().await {}
-                });
-            });
-        })
-        .with_function("async-trait", |b| {
-            b.iter(|| {
-                futures::executor::block_on(async {
-                    let mut stream = AsyncTraitStreamImpl::new(STREAM_ITERATIONS);
-                    while let Some(_item) = stream.next().await {}
-                });
+    let mut group = c.benchmark_group("stream_benches");
+    group.bench_function("no trait", |b| {
+        b.iter(|| {
+            let mut stream = NoTraitStream::new(STREAM_ITERATIONS);
+            futures::executor::block_on(async { while let Some(_item) = stream.next().await {} });
+        });
+    });
+    group.bench_function("async-trait", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut stream = AsyncTraitStreamImpl::new(STREAM_ITERATIONS);
+                while let Some(_item) = stream.next().await {}
             });
-        })
-        .with_function("non recyclable DynamicFuture", |b| {
-            b.iter(|| {
-                futures::executor::block_on(async {
-                    let mut stream = DynamicBoxedFutureAsyncTraitStreamImpl::new(STREAM_ITERATIONS);
-                    while let Some(_item) = stream.next().await {}
-                });
+        });
+    });
+    group.bench_function("non recyclable DynamicFuture", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut stream = DynamicBoxedFutureAsyncTraitStreamImpl::new(STREAM_ITERATIONS);
+                while let Some(_item) = stream.next().await {}
             });
-        })
-        .with_function("recyclable DynamicFuture", |b| {
-            b.iter(|| {
-                futures::executor::block_on(async {
-                    let mut stream =
-                        DynamicRecyclableFutureAsyncTraitStreamImpl::new(STREAM_ITERATIONS);
-                    while let Some(_item) = stream.next().await {}
-                });
+        });
+    });
+    group.bench_function("recyclable DynamicFuture", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut stream =
+                    DynamicRecyclableFutureAsyncTraitStreamImpl::new(STREAM_ITERATIONS);
+                while let Some(_item) = stream.next().await {}
             });
-        }),
-    );
+        });
+    });
+    group.finish();
 }
 
 fn nested_stream_benches(c: &mut Criterion) {
-    c.bench(
-        "nested_stream_benches",
-        Benchmark::new("no trait", |b| {
-            b.iter(|| {
-                let mut stream = NoTraitWrappingStream::new(STREAM_ITERATIONS);
-                futures::executor::block_on(async {
-                    while let Some(_item) = stream.next().await {}
-                });
+    let mut group = c.benchmark_group("nested_stream_benches");
+    group.bench_function("no trait", |b| {
+        b.iter(|| {
+            let mut stream = NoTraitWrappingStream::new(STREAM_ITERATIONS);
+            futures::executor::block_on(async { while let Some(_item) = stream.next().await {} });
+        });
+    });
+    group.bench_function("async-trait", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut stream = AsyncTraitWrappingStreamImpl::new(STREAM_ITERATIONS);
+                while let Some(_item) = stream.next().await {}
             });
-        })
-        .with_function("async-trait", |b| {
-            b.iter(|| {
-                futures::executor::block_on(async {
-                    let mut stream = AsyncTraitWrappingStreamImpl::new(STREAM_ITERATIONS);
-                    while let Some(_item) = stream.next().await {}
-                });
+        });
+    });
+    group.bench_function("non recyclable DynamicFuture", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut stream =
+                    DynamicBoxedFutureAsyncTraitWrappingStreamImpl::new(STREAM_ITERATIONS);
+                while let Some(_item) = stream.next().await {}
             });
-        })
-        .with_function("non recyclable DynamicFuture", |b| {
-            b.iter(|| {
-                futures::executor::block_on(async {
-                    let mut stream =
-                        DynamicBoxedFutureAsyncTraitWrappingStreamImpl::new(STREAM_ITERATIONS);
-                    while let Some(_item) = stream.next().await {}
-                });
+        });
+    });
+    group.bench_function("recyclable DynamicFuture", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut stream =
+                    DynamicRecyclableFutureAsyncTraitWrappingStreamImpl::new(STREAM_ITERATIONS);
+                while let Some(_item) = stream.next().await {}
             });
-        })
-        .with_function("recyclable DynamicFuture", |b| {
-            b.iter(|| {
-                futures::executor::block_on(async {
-                    let mut stream =
-                        DynamicRecyclableFutureAsyncTraitWrappingStreamImpl::new(STREAM_ITERATIONS);
-                    while let Some(_item) = stream.next().await {}
-                });
+        });
+    });
+    group.finish();
+}
+
+fn collect_stream_benches(c: &mut Criterion) {
+    let mut group = c.benchmark_group("collect_stream_benches");
+    group.bench_function("no trait", |b| {
+        b.iter(|| {
+            let mut stream = NoTraitStream::new(STREAM_ITERATIONS);
+            let mut items = Vec::with_capacity(stream.size_hint().0);
+            futures::executor::block_on(async {
+                while let Some(item) = stream.next().await {
+                    items.push(item);
+                }
             });
-        }),
-    );
+            black_box(items)
+        });
+    });
+    group.bench_function("async-trait", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut stream = AsyncTraitStreamImpl::new(STREAM_ITERATIONS);
+                let mut items = Vec::with_capacity(stream.size_hint().0);
+                while let Some(item) = stream.next().await {
+                    items.push(item);
+                }
+                black_box(items)
+            })
+        });
+    });
+    group.bench_function("recyclable DynamicFuture", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut stream =
+                    DynamicRecyclableFutureAsyncTraitStreamImpl::new(STREAM_ITERATIONS);
+                let mut items = Vec::with_capacity(stream.size_hint().0);
+                while let Some(item) = stream.next().await {
+                    items.push(item);
+                }
+                black_box(items)
+            })
+        });
+    });
+    group.bench_function("recyclable DynamicFuture without size_hint", |b| {
+        b.iter(|| {
+            futures::executor::block_on(async {
+                let mut stream =
+                    DynamicRecyclableFutureAsyncTraitStreamImpl::new(STREAM_ITERATIONS);
+                let mut items = Vec::new();
+                while let Some(item) = stream.next().await {
+                    items.push(item);
+                }
+                black_box(items)
+            })
+        });
+    });
+    group.finish();
 }
 
 criterion_group! {
     name = bench_group;
     config = Criterion::default();
-    targets = adder_benches, stream_benches, nested_stream_benches
+    targets = adder_benches, poll_benches, stream_benches, nested_stream_benches, collect_stream_benches
 }
 criterion_main!(bench_group);
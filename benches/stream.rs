@@ -19,6 +19,17 @@ impl NoTraitStream {
             Some(self.current)
         }
     }
+
+    /// Returns the bounds on the remaining length of the stream, like
+    /// `Stream::size_hint`
+    pub fn size_hint(&self) -> (usize, Option<usize>) {
+        remaining(self.current)
+    }
+}
+
+/// The size hint of a countdown stream which yields `current` more items
+fn remaining(current: u32) -> (usize, Option<usize>) {
+    (current as usize, Some(current as usize))
 }
 
 pub struct NoTraitWrappingStream {
@@ -40,6 +51,12 @@ impl NoTraitWrappingStream {
 #[async_trait(?Send)]
 pub trait AsyncTraitStream {
     async fn next(&mut self) -> Option<u32>;
+
+    /// Returns the bounds on the remaining length of the stream, like
+    /// `Stream::size_hint`
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, None)
+    }
 }
 
 #[derive(Default)]
@@ -63,6 +80,10 @@ impl AsyncTraitStream for AsyncTraitStreamImpl {
             Some(self.current)
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        remaining(self.current)
+    }
 }
 
 pub struct AsyncTraitWrappingStreamImpl {
@@ -82,10 +103,20 @@ impl AsyncTraitStream for AsyncTraitWrappingStreamImpl {
     async fn next(&mut self) -> Option<u32> {
         self.inner.next().await
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
 }
 
 pub trait DynamicFutureAsyncTraitStream {
     fn next<'a>(&'a mut self) -> DynamicFuture<'a, Option<u32>>;
+
+    /// Returns the bounds on the remaining length of the stream, like
+    /// `Stream::size_hint`
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, None)
+    }
 }
 
 #[derive(Default)]
@@ -121,6 +152,10 @@ impl DynamicFutureAsyncTraitStream for DynamicRecyclableFutureAsyncTraitStreamIm
             }
         })
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        remaining(self.state.current)
+    }
 }
 
 #[derive(Default)]
@@ -149,6 +184,10 @@ impl DynamicFutureAsyncTraitStream for DynamicBoxedFutureAsyncTraitStreamImpl {
             }
         })
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        remaining(self.state.current)
+    }
 }
 
 struct WrappingStreamState {
@@ -178,6 +217,10 @@ impl DynamicFutureAsyncTraitStream for DynamicRecyclableFutureAsyncTraitWrapping
         self.next_recycler
             .allocate(async move { state.inner.next().await })
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.state.inner.size_hint()
+    }
 }
 
 pub struct DynamicBoxedFutureAsyncTraitWrappingStreamImpl {
@@ -200,4 +243,8 @@ impl DynamicFutureAsyncTraitStream for DynamicBoxedFutureAsyncTraitWrappingStrea
 
         box_future(async move { state.inner.next().await })
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.state.inner.size_hint()
+    }
 }
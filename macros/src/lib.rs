@@ -0,0 +1,421 @@
+//! Procedural macros for `async_trait_experiments`.
+//!
+//! The macros are re-exported by `async_trait_experiments` if its `macros`
+//! feature is enabled, and should be used via this re-export.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, parse_quote,
+    visit_mut::{self, VisitMut},
+    Attribute, Error, FnArg, GenericParam, Ident, ImplItem, ImplItemFn, ItemImpl, Lifetime, Path,
+    ReturnType, Token, Type, TypeImplTrait, TypeParamBound, TypeReference, Visibility,
+};
+
+/// The lifetime of the `DynamicFuture`s which are returned by the generated
+/// methods
+const FUTURE_LIFETIME: &str = "'__recycled";
+
+/// The names of the methods which are generated besides the methods for the
+/// `async fn`s
+const GENERATED_METHODS: [&str; 4] = ["new", "state", "state_mut", "into_state"];
+
+/// The arguments of the `recyclable_async` attribute:
+/// `vis Name` or `vis Name, impl Trait`
+struct Args {
+    vis: Visibility,
+    name: Ident,
+    trait_: Option<Path>,
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let vis = input.parse()?;
+        let name = input.parse()?;
+        let trait_ = if input.parse::<Option<Token![,]>>()?.is_some() {
+            input.parse::<Token![impl]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+        Ok(Args { vis, name, trait_ })
+    }
+}
+
+/// Generates a type which returns the futures of the `async fn`s of an
+/// inherent `impl` block as `DynamicFuture`s, whose storage is reused between
+/// calls.
+///
+/// The attribute is applied to the `impl` block of a state type, and takes the
+/// visibility and name of the generated type:
+///
+/// ```text
+/// #[derive(Default)]
+/// struct AdderState {
+///     current: u32,
+/// }
+///
+/// #[recyclable_async(pub Adder)]
+/// impl AdderState {
+///     pub async fn add_obj(&mut self, a: u32, b: u32) -> u32 {
+///         self.current = a + b;
+///         self.current
+///     }
+/// }
+/// ```
+///
+/// The `impl` block is kept as is. The generated type holds the state and a
+/// `RecyclableFutureAllocator` per `async fn`. For each `async fn` it provides
+/// a method of the same name, which calls the `async fn` on the state and
+/// moves the returned future into the allocator for this method:
+///
+/// ```text
+/// pub struct Adder {
+///     state: AdderState,
+///     add_obj_recycler: RecyclableFutureAllocator,
+/// }
+///
+/// impl Adder {
+///     pub fn add_obj<'a>(&'a mut self, a: u32, b: u32) -> DynamicFuture<'a, u32> {
+///         let state = &mut self.state;
+///         self.add_obj_recycler.allocate(state.add_obj(a, b))
+///     }
+/// }
+/// ```
+///
+/// Since the futures only borrow the state, the allocators can be borrowed
+/// separately from them. The generated methods always take `&mut self`, since
+/// they require mutable access to the allocators.
+///
+/// If the attribute names a trait via `#[recyclable_async(pub Adder, impl
+/// AdderTrait)]`, the methods implement this trait for the generated type
+/// instead of being inherent methods. The trait needs to declare them with the
+/// signatures which are generated for them.
+///
+/// `Self` in the signatures of the `async fn`s refers to the state type, and is
+/// replaced by it in the generated methods. Arguments of `impl Trait` types
+/// are captured by the futures, and are therefore required to outlive them.
+///
+/// The generated type provides `new`, `state`, `state_mut` and `into_state`
+/// methods, and implements `Default` if the state does. `async fn`s with these
+/// names are rejected, since their methods would conflict with them.
+#[proc_macro_attribute]
+pub fn recyclable_async(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as Args);
+    let item = parse_macro_input!(input as ItemImpl);
+    match expand(args, item) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(args: Args, item: ItemImpl) -> syn::Result<TokenStream2> {
+    if let Some((_, path, _)) = &item.trait_ {
+        return Err(Error::new_spanned(
+            path,
+            "recyclable_async must be applied to an inherent impl block",
+        ));
+    }
+
+    let Args { vis, name, trait_ } = args;
+    let state_ty = &item.self_ty;
+    let (impl_generics, ty_generics, where_clause) = item.generics.split_for_impl();
+
+    let mut fields = Vec::new();
+    let mut field_inits = Vec::new();
+    let mut methods = Vec::new();
+    for impl_item in &item.items {
+        if let ImplItem::Fn(method) = impl_item {
+            if method.sig.asyncness.is_some() {
+                let ident = &method.sig.ident;
+                if GENERATED_METHODS.iter().any(|name| ident == name) {
+                    return Err(Error::new_spanned(
+                        ident,
+                        format!(
+                            "recyclable_async generates a method named `{}`, which conflicts \
+                             with the method for this async fn",
+                            ident
+                        ),
+                    ));
+                }
+                let recycler = format_ident!("{}_recycler", method.sig.ident);
+                let cfg_attrs = attrs_of(&method.attrs, &["cfg"]);
+                fields.push(quote! {
+                    #(#cfg_attrs)*
+                    #recycler: ::async_trait_experiments::RecyclableFutureAllocator,
+                });
+                field_inits.push(quote! {
+                    #(#cfg_attrs)*
+                    #recycler: ::async_trait_experiments::RecyclableFutureAllocator::new(),
+                });
+                methods.push(recycled_method(
+                    method,
+                    state_ty,
+                    &recycler,
+                    trait_.is_none(),
+                )?);
+            }
+        }
+    }
+
+    let methods = match &trait_ {
+        Some(trait_) => quote! {
+            impl #impl_generics #trait_ for #name #ty_generics #where_clause {
+                #(#methods)*
+            }
+        },
+        None => quote! {
+            impl #impl_generics #name #ty_generics #where_clause {
+                #(#methods)*
+            }
+        },
+    };
+
+    let mut default_generics = item.generics.clone();
+    default_generics
+        .make_where_clause()
+        .predicates
+        .push(parse_quote!(#state_ty: ::core::default::Default));
+    let default_where_clause = &default_generics.where_clause;
+
+    let generics = &item.generics;
+    let struct_doc = format!(
+        "Calls the async methods of `{}`, and reuses the storage of their \
+         futures via a `RecyclableFutureAllocator` per method.",
+        quote!(#state_ty).to_string().replace(' ', "")
+    );
+
+    Ok(quote! {
+        #item
+
+        #[doc = #struct_doc]
+        #vis struct #name #generics #where_clause {
+            state: #state_ty,
+            #(#fields)*
+        }
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            /// Creates a new instance which calls the async methods of `state`
+            #[allow(dead_code)]
+            #vis fn new(state: #state_ty) -> Self {
+                Self {
+                    state,
+                    #(#field_inits)*
+                }
+            }
+
+            /// Returns a reference to the state
+            #[allow(dead_code)]
+            #vis fn state(&self) -> &#state_ty {
+                &self.state
+            }
+
+            /// Returns a mutable reference to the state
+            #[allow(dead_code)]
+            #vis fn state_mut(&mut self) -> &mut #state_ty {
+                &mut self.state
+            }
+
+            /// Returns the state, and frees the storage of the futures
+            #[allow(dead_code)]
+            #vis fn into_state(self) -> #state_ty {
+                self.state
+            }
+        }
+
+        #methods
+
+        impl #impl_generics ::core::default::Default for #name #ty_generics
+            #default_where_clause
+        {
+            fn default() -> Self {
+                Self::new(::core::default::Default::default())
+            }
+        }
+    })
+}
+
+/// Generates the method which calls `method` on the state of type `state_ty`,
+/// and moves the returned future into the allocator in the field `recycler`
+fn recycled_method(
+    method: &ImplItemFn,
+    state_ty: &Type,
+    recycler: &Ident,
+    inherent: bool,
+) -> syn::Result<TokenStream2> {
+    let sig = &method.sig;
+    match sig.receiver() {
+        Some(receiver) if receiver.colon_token.is_none() => match &receiver.reference {
+            Some((_, None)) => {}
+            Some((_, Some(lifetime))) => {
+                return Err(Error::new_spanned(
+                    lifetime,
+                    "recyclable_async does not support explicit lifetimes on self",
+                ))
+            }
+            None => {
+                return Err(Error::new_spanned(
+                    receiver,
+                    "recyclable_async requires async methods to take &self or &mut self",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                sig,
+                "recyclable_async requires async methods to take &self or &mut self",
+            ))
+        }
+    }
+    if let Some(variadic) = &sig.variadic {
+        return Err(Error::new_spanned(
+            variadic,
+            "recyclable_async does not support variadic methods",
+        ));
+    }
+
+    let lifetime = Lifetime::new(FUTURE_LIFETIME, Span::call_site());
+    let mut elided = ElidedLifetimes(lifetime.clone());
+    let mut self_ty = SelfType(state_ty);
+
+    // The future captures all arguments, which therefore need to outlive it
+    let mut generics = sig.generics.clone();
+    let mut outlives = Vec::new();
+    for param in &generics.params {
+        match param {
+            GenericParam::Lifetime(param) => {
+                let param = &param.lifetime;
+                outlives.push(quote!(#param: #lifetime));
+            }
+            GenericParam::Type(param) => {
+                let param = &param.ident;
+                outlives.push(quote!(#param: #lifetime));
+            }
+            GenericParam::Const(_) => {}
+        }
+    }
+    generics.params.insert(0, parse_quote!(#lifetime));
+    let where_clause = generics.make_where_clause();
+    for predicate in outlives {
+        where_clause.predicates.push(parse_quote!(#predicate));
+    }
+    for predicate in where_clause.predicates.iter_mut() {
+        elided.visit_where_predicate_mut(predicate);
+        self_ty.visit_where_predicate_mut(predicate);
+    }
+    let (_, _, where_clause) = generics.split_for_impl();
+    let params = &generics.params;
+
+    let mut arg_names = Vec::new();
+    let mut arg_decls = Vec::new();
+    for (index, arg) in sig.inputs.iter().enumerate() {
+        if let FnArg::Typed(arg) = arg {
+            let arg_name = format_ident!("__arg{}", index);
+            let mut ty: Type = (*arg.ty).clone();
+            elided.visit_type_mut(&mut ty);
+            self_ty.visit_type_mut(&mut ty);
+            ImplTraitBounds(lifetime.clone()).visit_type_mut(&mut ty);
+            arg_decls.push(quote!(#arg_name: #ty));
+            arg_names.push(arg_name);
+        }
+    }
+
+    let output = match &sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => {
+            let mut ty: Type = (**ty).clone();
+            elided.visit_type_mut(&mut ty);
+            self_ty.visit_type_mut(&mut ty);
+            quote!(#ty)
+        }
+    };
+
+    let attrs = attrs_of(&method.attrs, &["doc", "cfg"]);
+    let vis = if inherent {
+        let vis = &method.vis;
+        quote!(#vis)
+    } else {
+        quote!()
+    };
+    let ident = &sig.ident;
+    Ok(quote! {
+        #(#attrs)*
+        #vis fn #ident<#params>(
+            &#lifetime mut self,
+            #(#arg_decls),*
+        ) -> ::async_trait_experiments::DynamicFuture<#lifetime, #output>
+        #where_clause
+        {
+            let state = &mut self.state;
+            self.#recycler.allocate(state.#ident(#(#arg_names),*))
+        }
+    })
+}
+
+/// Returns the attributes out of `attrs` whose name is in `names`
+fn attrs_of<'a>(attrs: &'a [Attribute], names: &[&str]) -> Vec<&'a Attribute> {
+    attrs
+        .iter()
+        .filter(|attr| names.iter().any(|name| attr.path().is_ident(name)))
+        .collect()
+}
+
+/// Replaces elided lifetimes with the lifetime of the returned future
+struct ElidedLifetimes(Lifetime);
+
+impl VisitMut for ElidedLifetimes {
+    fn visit_type_reference_mut(&mut self, ty: &mut TypeReference) {
+        if ty.lifetime.is_none() {
+            ty.lifetime = Some(self.0.clone());
+        }
+        visit_mut::visit_type_reference_mut(self, ty);
+    }
+
+    fn visit_lifetime_mut(&mut self, lifetime: &mut Lifetime) {
+        if lifetime.ident == "_" {
+            *lifetime = self.0.clone();
+        }
+    }
+}
+
+/// Replaces `Self` with the state type, since `Self` refers to the generated
+/// type within the generated methods
+struct SelfType<'a>(&'a Type);
+
+impl VisitMut for SelfType<'_> {
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        if let Type::Path(path) = ty {
+            let is_self = path.qself.is_none()
+                && path.path.leading_colon.is_none()
+                && path.path.segments[0].ident == "Self";
+            if is_self {
+                let state_ty = self.0;
+                let rest = path.path.segments.iter().skip(1);
+                *ty = if path.path.segments.len() == 1 {
+                    state_ty.clone()
+                } else {
+                    parse_quote!(<#state_ty>#(::#rest)*)
+                };
+            }
+        }
+        visit_mut::visit_type_mut(self, ty);
+    }
+}
+
+/// Requires the types of `impl Trait` arguments to outlive the returned future,
+/// which captures the arguments
+struct ImplTraitBounds(Lifetime);
+
+impl VisitMut for ImplTraitBounds {
+    fn visit_type_impl_trait_mut(&mut self, ty: &mut TypeImplTrait) {
+        visit_mut::visit_type_impl_trait_mut(self, ty);
+        let bounded = ty.bounds.iter().any(
+            |bound| matches!(bound, TypeParamBound::Lifetime(lifetime) if *lifetime == self.0),
+        );
+        if !bounded {
+            ty.bounds.push(TypeParamBound::Lifetime(self.0.clone()));
+        }
+    }
+}
@@ -0,0 +1,12 @@
+use async_trait_experiments::recyclable_async;
+
+struct State;
+
+#[recyclable_async(Generated)]
+impl State {
+    async fn consume(self) -> u32 {
+        1
+    }
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+use async_trait_experiments::recyclable_async;
+
+struct State;
+
+trait Named {
+    fn name(&self) -> &'static str;
+}
+
+#[recyclable_async(Generated)]
+impl Named for State {
+    fn name(&self) -> &'static str {
+        "state"
+    }
+}
+
+fn main() {}
@@ -0,0 +1,37 @@
+// The adders of the benchmarks, which use `async_trait_experiments_macros`
+// directly
+#[allow(dead_code)]
+#[path = "../../../benches/adder.rs"]
+mod adder;
+
+use adder::{
+    DynamicFutureAsyncTraitAdder, DynamicRecyclableFutureAsyncTraitAdderImpl,
+    MacroRecyclableFutureAsyncTraitAdderImpl,
+};
+use futures::executor::block_on;
+
+/// Runs a series of additions on `adder`. Returns their results, and whether
+/// all futures were stored in the same storage.
+fn run(adder: &mut dyn DynamicFutureAsyncTraitAdder) -> (Vec<u32>, bool) {
+    let mut storage = None;
+    let mut reused = true;
+    let results = (0..10)
+        .map(|i| {
+            let fut = adder.add_obj(i, 2 * i);
+            reused &= *storage.get_or_insert(fut.ptr()) == fut.ptr();
+            block_on(fut)
+        })
+        .collect();
+    (results, reused)
+}
+
+fn main() {
+    let mut hand_written = DynamicRecyclableFutureAsyncTraitAdderImpl::default();
+    let (expected, reused) = run(&mut hand_written);
+    assert_eq!((0..10).map(|i| 3 * i).collect::<Vec<_>>(), expected);
+    assert!(reused);
+
+    let mut generated = MacroRecyclableFutureAsyncTraitAdderImpl::default();
+    assert_eq!((expected, true), run(&mut generated));
+    assert_eq!(hand_written.current(), generated.state().current);
+}
@@ -0,0 +1,26 @@
+use async_trait_experiments::recyclable_async;
+use futures::executor::block_on;
+
+#[derive(Default)]
+struct SumState {
+    total: u32,
+}
+
+#[recyclable_async(Sum)]
+impl SumState {
+    async fn add_all(&mut self, values: impl IntoIterator<Item = u32>) -> u32 {
+        self.total += values.into_iter().sum::<u32>();
+        self.total
+    }
+
+    async fn map(&self, f: impl Fn(u32) -> u32 + Send) -> u32 {
+        f(self.total)
+    }
+}
+
+fn main() {
+    let mut sum = Sum::default();
+    assert_eq!(6, block_on(sum.add_all(vec![1, 2, 3])));
+    let offset = 4;
+    assert_eq!(10, block_on(sum.map(|total| total + offset)));
+}
@@ -0,0 +1,39 @@
+use async_trait_experiments::recyclable_async;
+use futures::executor::block_on;
+
+#[derive(Default)]
+struct CounterState {
+    count: u32,
+}
+
+#[recyclable_async(pub Counter)]
+impl CounterState {
+    async fn increment(&mut self, by: u32) -> u32 {
+        self.count += by;
+        self.count
+    }
+
+    async fn count(&self) -> u32 {
+        self.count
+    }
+
+    async fn add_len<'s, T: AsRef<str>>(&mut self, name: &'s T) -> &'s str {
+        self.count += name.as_ref().len() as u32;
+        name.as_ref()
+    }
+}
+
+fn main() {
+    let mut counter = Counter::default();
+    assert_eq!(2, block_on(counter.increment(2)));
+    assert_eq!(5, block_on(counter.increment(3)));
+    assert_eq!(5, block_on(counter.count()));
+    let name = String::from("abc");
+    assert_eq!("abc", block_on(counter.add_len(&name)));
+    assert_eq!(8, counter.state().count);
+    counter.state_mut().count = 1;
+    assert_eq!(1, counter.into_state().count);
+
+    let mut counter = Counter::new(CounterState { count: 10 });
+    assert_eq!(11, block_on(counter.increment(1)));
+}
@@ -0,0 +1,47 @@
+use async_trait_experiments::recyclable_async;
+use futures::executor::block_on;
+
+#[derive(Default, Clone, PartialEq, Debug)]
+struct ValueState {
+    value: u32,
+}
+
+trait Value {
+    type Inner;
+}
+
+impl Value for ValueState {
+    type Inner = u32;
+}
+
+#[recyclable_async(Values)]
+impl ValueState {
+    async fn replace(&mut self, other: Self) -> Self {
+        core::mem::replace(self, other)
+    }
+
+    async fn get(&self) -> <Self as Value>::Inner {
+        self.value
+    }
+
+    async fn equals<T>(&self, other: T) -> bool
+    where
+        T: Into<Self>,
+    {
+        *self == other.into()
+    }
+}
+
+impl From<u32> for ValueState {
+    fn from(value: u32) -> Self {
+        ValueState { value }
+    }
+}
+
+fn main() {
+    let mut values = Values::default();
+    let previous = block_on(values.replace(ValueState { value: 3 }));
+    assert_eq!(ValueState { value: 0 }, previous);
+    assert_eq!(3, block_on(values.get()));
+    assert!(block_on(values.equals(3u32)));
+}
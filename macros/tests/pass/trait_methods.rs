@@ -0,0 +1,30 @@
+use async_trait_experiments::{recyclable_async, DynamicFuture};
+use futures::executor::block_on;
+
+trait Adder {
+    fn add<'a>(&'a mut self, a: u32, b: u32) -> DynamicFuture<'a, u32>;
+}
+
+#[derive(Default)]
+struct AdderState {
+    current: u32,
+}
+
+#[recyclable_async(pub RecyclingAdder, impl Adder)]
+impl AdderState {
+    async fn add(&mut self, a: u32, b: u32) -> u32 {
+        self.current = a + b;
+        self.current
+    }
+}
+
+fn add_with(adder: &mut dyn Adder, a: u32, b: u32) -> u32 {
+    block_on(adder.add(a, b))
+}
+
+fn main() {
+    let mut adder = RecyclingAdder::default();
+    assert_eq!(3, add_with(&mut adder, 1, 2));
+    assert_eq!(7, add_with(&mut adder, 3, 4));
+    assert_eq!(7, adder.state().current);
+}
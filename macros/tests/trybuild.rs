@@ -0,0 +1,11 @@
+#[test]
+fn pass() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/pass/*.rs");
+}
+
+#[test]
+fn compile_fail() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/compile-fail/*.rs");
+}
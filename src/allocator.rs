@@ -0,0 +1,61 @@
+//! Abstractions over the memory allocator which provides the storage for
+//! `DynamicFuture`s.
+
+use alloc::alloc::Layout;
+
+/// A memory allocator which provides the storage for `DynamicFuture`s.
+///
+/// This mirrors the (currently unstable) `std::alloc::Allocator` API. Allocators
+/// are stored inside the storage of futures that they allocated, in order to
+/// allow the vtable of the `DynamicFuture` to free the storage once the future
+/// is dropped. Allocators are therefore typically cheap handles to the actual
+/// allocator - e.g. a zero sized type or a reference.
+///
+/// # Safety
+///
+/// Memory which is returned from [`Allocator::allocate`] must be valid for reads
+/// and writes of the requested layout until it is passed to
+/// [`Allocator::deallocate`]. Memory which was allocated by an allocator must be
+/// able to be freed by any clone of it.
+pub unsafe trait Allocator {
+    /// Allocates memory as described by `layout`.
+    ///
+    /// Returns a null pointer if the memory can not be allocated.
+    /// `layout` will never have a size of 0.
+    fn allocate(&self, layout: Layout) -> *mut u8;
+
+    /// Frees memory which had been allocated via [`Allocator::allocate`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by this allocator - or a clone of it - using
+    /// the same `layout`.
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout);
+}
+
+/// The global memory allocator
+///
+/// This allocator forwards all calls to the allocator which is registered as
+/// `#[global_allocator]`, and is the default allocator for all futures.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Global;
+
+unsafe impl Allocator for Global {
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        unsafe { alloc::alloc::alloc(layout) }
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        alloc::alloc::dealloc(ptr, layout)
+    }
+}
+
+unsafe impl<A: Allocator + ?Sized> Allocator for &A {
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        (**self).allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        (**self).deallocate(ptr, layout)
+    }
+}
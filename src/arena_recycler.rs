@@ -0,0 +1,290 @@
+use crate::{
+    box_future,
+    boxed_future::zero_sized_future,
+    storage::{stored_future_vtable, PayloadStorage},
+    DynamicFuture,
+};
+use alloc::{alloc::Layout, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    future::Future,
+};
+
+/// An allocator for `DynamicFuture`s which bump allocates their storage from
+/// an arena.
+///
+/// The arena consists of chunks of `chunk_size` bytes, which are allocated
+/// once the previous chunks are exhausted. Dropping a future does not free its
+/// storage. Instead all storage is reclaimed at once by calling
+/// [`ArenaRecycler::reset`], which makes the chunks available for the next
+/// futures. This fits request scoped work: All futures of one request get
+/// allocated from the arena, and the arena is reset after the request
+/// completed.
+///
+/// The returned futures borrow the allocator, which guarantees that they are
+/// dropped before the allocator is reset or dropped. Futures which are larger
+/// than a chunk get a dedicated chunk. Zero sized futures don't use the arena.
+pub struct ArenaRecycler {
+    chunks: RefCell<Vec<Chunk>>,
+    /// The index of the chunk which futures are currently allocated from
+    current: Cell<usize>,
+    /// The offset of the unused memory of the current chunk
+    offset: Cell<usize>,
+    /// The amount of futures which had been allocated from the arena and are
+    /// not yet dropped
+    outstanding: Cell<usize>,
+    chunk_size: usize,
+}
+
+impl Drop for ArenaRecycler {
+    fn drop(&mut self) {
+        if self.outstanding.get() != 0 {
+            self.leak_chunks();
+        }
+    }
+}
+
+impl ArenaRecycler {
+    /// Creates a new allocator whose arena will be made up of chunks of
+    /// `chunk_size` bytes
+    pub fn new(chunk_size: usize) -> Self {
+        Self {
+            chunks: RefCell::new(Vec::new()),
+            current: Cell::new(0),
+            offset: Cell::new(0),
+            outstanding: Cell::new(0),
+            chunk_size,
+        }
+    }
+
+    /// Returns the size of the chunks of the arena
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Returns the amount of futures which had been allocated from the arena
+    /// and are not yet dropped
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.get()
+    }
+
+    /// Transforms the passed future into a `DynamicFuture`.
+    ///
+    /// The future is moved into the unused memory of the arena. If the arena
+    /// is exhausted, a new chunk is allocated. If this fails, the future is
+    /// boxed.
+    pub fn allocate<'a, F, T>(&'a self, fut: F) -> DynamicFuture<'a, T>
+    where
+        F: Future<Output = T> + 'a,
+    {
+        if core::mem::size_of::<F>() == 0 {
+            // Zero sized futures don't require any storage
+            return zero_sized_future(fut);
+        }
+
+        let slot = match slot_layout::<F>().and_then(|layout| self.bump(layout)) {
+            Some(slot) => slot,
+            None => return box_future(fut),
+        };
+
+        unsafe {
+            core::ptr::write(
+                slot as *mut ArenaSlot,
+                ArenaSlot {
+                    outstanding: &self.outstanding,
+                },
+            );
+            core::ptr::write(
+                <ArenaSlot as PayloadStorage<F>>::payload(slot as *const ()),
+                fut,
+            );
+            self.outstanding.set(self.outstanding.get() + 1);
+            DynamicFuture::new(slot as *const (), stored_future_vtable::<F, T, ArenaSlot>())
+        }
+    }
+
+    /// Reclaims the storage of all futures which had been allocated from the
+    /// arena.
+    ///
+    /// The chunks of the arena are kept, and are reused for the next futures.
+    ///
+    /// Since the futures borrow the allocator, they are all dropped when this
+    /// is called - unless they had been forgotten. The storage of forgotten
+    /// futures must not be reused, since they are pinned. This is checked via
+    /// a debug assertion. In release builds the chunks are leaked instead, and
+    /// new chunks are allocated for the next futures.
+    pub fn reset(&mut self) {
+        debug_assert_eq!(
+            self.outstanding.get(),
+            0,
+            "ArenaRecycler was reset while futures were not dropped"
+        );
+        if self.outstanding.get() != 0 {
+            self.leak_chunks();
+        }
+        self.current.set(0);
+        self.offset.set(0);
+    }
+
+    /// Leaks all chunks, since they contain futures which were never dropped
+    fn leak_chunks(&mut self) {
+        for chunk in self.chunks.get_mut().drain(..) {
+            core::mem::forget(chunk);
+        }
+        self.outstanding.set(0);
+    }
+
+    /// Allocates memory for `layout` from the arena
+    fn bump(&self, layout: Layout) -> Option<*mut u8> {
+        let mut chunks = self.chunks.borrow_mut();
+        while let Some(chunk) = chunks.get(self.current.get()) {
+            if let Some((ptr, end)) = chunk.try_bump(self.offset.get(), layout) {
+                self.offset.set(end);
+                return Some(ptr);
+            }
+            // The remainder of the chunk stays unused until the arena is reset
+            self.current.set(self.current.get() + 1);
+            self.offset.set(0);
+        }
+
+        // All chunks are exhausted
+        let chunk_layout =
+            Layout::from_size_align(self.chunk_size.max(layout.size()), layout.align()).ok()?;
+        let chunk = Chunk::allocate(chunk_layout)?;
+        let (ptr, end) = chunk.try_bump(0, layout)?;
+        chunks.push(chunk);
+        self.current.set(chunks.len() - 1);
+        self.offset.set(end);
+        Some(ptr)
+    }
+}
+
+/// A chunk of memory of the arena
+struct Chunk {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        unsafe {
+            alloc::alloc::dealloc(self.ptr, self.layout);
+        }
+    }
+}
+
+impl Chunk {
+    fn allocate(layout: Layout) -> Option<Chunk> {
+        let ptr = unsafe { alloc::alloc::alloc(layout) };
+        if ptr.is_null() {
+            return None;
+        }
+        Some(Chunk { ptr, layout })
+    }
+
+    /// Returns the memory for `layout` at the first suitably aligned address
+    /// at or after `offset`, and the offset of the end of this memory
+    fn try_bump(&self, offset: usize, layout: Layout) -> Option<(*mut u8, usize)> {
+        let address = (self.ptr as usize).checked_add(offset)?;
+        let padding = address.wrapping_neg() & (layout.align() - 1);
+        let start = offset.checked_add(padding)?;
+        let end = start.checked_add(layout.size())?;
+        if end > self.layout.size() {
+            return None;
+        }
+        Some((unsafe { self.ptr.add(start) }, end))
+    }
+}
+
+/// A header stored in front of each future in the arena.
+///
+/// The pointer of a `DynamicFuture` which is stored in the arena refers to
+/// this header.
+struct ArenaSlot {
+    /// The counter of outstanding futures of the allocator
+    outstanding: *const Cell<usize>,
+}
+
+/// Returns the offset of a payload of type `P` from the start of its slot
+fn payload_offset<P>() -> usize {
+    let align = core::mem::align_of::<P>();
+    (core::mem::size_of::<ArenaSlot>() + align - 1) & !(align - 1)
+}
+
+/// Returns the layout of a slot for a payload of type `P`
+fn slot_layout<P>() -> Option<Layout> {
+    let size = payload_offset::<P>().checked_add(core::mem::size_of::<P>())?;
+    let align = core::mem::align_of::<ArenaSlot>().max(core::mem::align_of::<P>());
+    Layout::from_size_align(size, align).ok()
+}
+
+// Dropping a payload only updates the counter of outstanding futures. The
+// storage is reclaimed by resetting the allocator.
+impl<P> PayloadStorage<P> for ArenaSlot {
+    unsafe fn payload(ptr: *const ()) -> *mut P {
+        (ptr as *const u8).add(payload_offset::<P>()) as *mut P
+    }
+
+    unsafe fn release(ptr: *const ()) {
+        let outstanding = &*(*(ptr as *const ArenaSlot)).outstanding;
+        outstanding.set(outstanding.get() - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{poll_once, yield_now, yield_value};
+    use core::task::Poll;
+
+    /// Allocates and completes a batch of futures, and returns their addresses
+    fn run_batch(arena: &ArenaRecycler) -> Vec<*const ()> {
+        let mut futures: Vec<_> = (0..8)
+            .map(|value| arena.allocate(yield_value(value)))
+            .collect();
+        assert_eq!(8, arena.outstanding());
+        let addresses = futures.iter().map(|fut| fut.ptr()).collect();
+        for (value, fut) in futures.iter_mut().enumerate() {
+            assert!(poll_once(fut).is_pending());
+            assert_eq!(Poll::Ready(value as u32), poll_once(fut));
+        }
+        drop(futures);
+        assert_eq!(0, arena.outstanding());
+        addresses
+    }
+
+    #[test]
+    fn storage_is_reused_after_reset() {
+        let mut arena = ArenaRecycler::new(64);
+        assert_eq!(64, arena.chunk_size());
+        let first = run_batch(&arena);
+        // Dropping the futures does not reclaim their storage
+        let second = run_batch(&arena);
+        assert!(first.iter().all(|address| !second.contains(address)));
+
+        arena.reset();
+        assert_eq!(first, run_batch(&arena));
+    }
+
+    #[repr(align(64))]
+    struct Aligned([u8; 64]);
+
+    #[test]
+    fn oversized_and_over_aligned_futures_are_stored_in_the_arena() {
+        let arena = ArenaRecycler::new(32);
+        let aligned = Aligned([7; 64]);
+        let mut fut = arena.allocate(async move {
+            let aligned = &aligned;
+            yield_now().await;
+            (aligned as *const Aligned as usize % 64, aligned.0[63])
+        });
+        assert_eq!(1, arena.outstanding());
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready((0, 7)), poll_once(&mut fut));
+
+        // Zero sized futures don't use the arena
+        let fut = arena.allocate(futures::future::pending::<()>());
+        assert_eq!(1, arena.outstanding());
+        drop(fut);
+    }
+}
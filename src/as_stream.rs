@@ -0,0 +1,153 @@
+use crate::DynamicFuture;
+use core::{
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures_core::Stream;
+
+/// An object which produces its items through a method which returns a
+/// `DynamicFuture` for the next item.
+///
+/// This is the shape of async traits which act as a stream, and which allocate
+/// the returned futures via a `RecyclableFutureAllocator`. Such objects can
+/// be turned into a `Stream` via `AsStream`.
+pub trait DynamicFutureStream {
+    /// The type of the items which are produced
+    type Item;
+
+    /// Returns a future which resolves to the next item, or to `None` if no
+    /// further items are available.
+    fn next<'a>(&'a mut self) -> DynamicFuture<'a, Option<Self::Item>>;
+}
+
+/// A `Stream` which produces the items of a `DynamicFutureStream`.
+///
+/// The adapter exclusively borrows the `DynamicFutureStream`, and requests the
+/// future for the next item once the future for the previous item resolved.
+#[must_use = "streams do nothing unless polled"]
+pub struct AsStream<'a, S: DynamicFutureStream> {
+    /// The wrapped object. This is a pointer instead of a reference, since
+    /// the future which is in flight holds a mutable borrow of the object.
+    stream: *mut S,
+    /// The future which produces the next item. It borrows from `stream`.
+    pending: Option<DynamicFuture<'a, Option<S::Item>>>,
+    _phantom: PhantomData<&'a mut S>,
+}
+
+impl<'a, S: DynamicFutureStream> AsStream<'a, S> {
+    /// Creates a `Stream` which produces the items of `stream`
+    pub fn new(stream: &'a mut S) -> Self {
+        Self {
+            stream,
+            pending: None,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+// The object is never pinned, and `DynamicFuture` is `Unpin`
+impl<'a, S: DynamicFutureStream> Unpin for AsStream<'a, S> {}
+
+impl<'a, S: DynamicFutureStream> Stream for AsStream<'a, S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let this = self.get_mut();
+        let pending = match &mut this.pending {
+            Some(pending) => pending,
+            None => {
+                // The object is borrowed for `'a` by this adapter, and the
+                // previous future which borrowed it had been dropped. Therefore
+                // the future can borrow the object for the remaining lifetime.
+                let stream = unsafe { &mut *this.stream };
+                this.pending.insert(stream.next())
+            }
+        };
+
+        let item = match pending.poll_unpin(cx) {
+            Poll::Ready(item) => item,
+            Poll::Pending => return Poll::Pending,
+        };
+        // Release the borrow, so that the future for the next item can be
+        // requested
+        this.pending = None;
+        Poll::Ready(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{yield_now, CountingAllocator},
+        RecyclableFutureAllocator,
+    };
+    use futures::{executor::block_on, StreamExt};
+
+    /// Counts up to `end`, and allocates the futures for the items via a
+    /// recycler
+    struct Counter {
+        current: u32,
+        end: u32,
+        recycler: RecyclableFutureAllocator<CountingAllocator>,
+    }
+
+    impl DynamicFutureStream for Counter {
+        type Item = u32;
+
+        fn next<'a>(&'a mut self) -> DynamicFuture<'a, Option<u32>> {
+            let current = &mut self.current;
+            let end = self.end;
+            self.recycler.allocate(async move {
+                yield_now().await;
+                if *current == end {
+                    return None;
+                }
+                *current += 1;
+                Some(*current)
+            })
+        }
+    }
+
+    #[test]
+    fn items_are_produced_via_the_recycled_futures() {
+        let alloc = CountingAllocator::default();
+        let mut counter = Counter {
+            current: 0,
+            end: 10,
+            recycler: RecyclableFutureAllocator::new_in(alloc.clone()),
+        };
+        let items: alloc::vec::Vec<u32> = block_on(AsStream::new(&mut counter).collect());
+        assert_eq!((1..=10).collect::<alloc::vec::Vec<_>>(), items);
+        assert_eq!(1, alloc.allocations());
+
+        // The object can be used again once the adapter is dropped
+        counter.end = 12;
+        let sum = block_on(
+            AsStream::new(&mut counter)
+                .map(|i| i * 2)
+                .fold(0, |sum, i| async move { sum + i }),
+        );
+        assert_eq!(46, sum);
+        assert_eq!(1, alloc.allocations());
+    }
+
+    #[test]
+    fn dropping_the_adapter_drops_the_pending_future() {
+        let alloc = CountingAllocator::default();
+        let mut counter = Counter {
+            current: 0,
+            end: 10,
+            recycler: RecyclableFutureAllocator::new_in(alloc.clone()),
+        };
+        let mut stream = AsStream::new(&mut counter);
+        let mut cx = core::task::Context::from_waker(futures::task::noop_waker_ref());
+        assert!(Pin::new(&mut stream).poll_next(&mut cx).is_pending());
+        drop(stream);
+
+        // The storage is no longer in use
+        assert_eq!(Some(1), block_on(counter.next()));
+        assert_eq!(1, alloc.allocations());
+    }
+}
@@ -0,0 +1,47 @@
+/// Asserts that the future which is produced by an expression does not occupy
+/// more than a given amount of bytes.
+///
+/// The expression is evaluated, and the future it produces is dropped without
+/// being polled. The macro panics if the size of the future exceeds
+/// `max_bytes`. Since the types of `async` blocks and functions can not be
+/// named, the check is performed at runtime - e.g. inside a test which guards
+/// against refactorings that unexpectedly grow a future.
+///
+/// This matters for recycling allocators: A future which grew beyond the
+/// storage of the allocator will silently be boxed instead of reusing the
+/// storage. Note that the recyclable storage also holds a small header in
+/// front of the future, which is not accounted for here.
+#[macro_export]
+macro_rules! assert_future_size {
+    ($fut:expr, $max_bytes:expr $(,)?) => {{
+        fn assert_future<F: ::core::future::Future>(_: &F) {}
+
+        let fut = $fut;
+        assert_future(&fut);
+        let size = ::core::mem::size_of_val(&fut);
+        let max_bytes: usize = $max_bytes;
+        assert!(
+            size <= max_bytes,
+            "Future of {} bytes exceeds the limit of {} bytes",
+            size,
+            max_bytes
+        );
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn small_futures_pass_the_check() {
+        crate::assert_future_size!(async { 1u32 }, 8);
+        let data = [0u8; 32];
+        crate::assert_future_size!(async move { data[0] }, 64,);
+    }
+
+    #[test]
+    #[should_panic(expected = "exceeds the limit of 256 bytes")]
+    fn big_futures_fail_the_check() {
+        let data = [0u8; 512];
+        crate::assert_future_size!(async move { data[0] }, 256);
+    }
+}
@@ -0,0 +1,394 @@
+use crate::sync::{fence, AtomicBool, AtomicUsize, Ordering};
+use crate::{
+    box_future_in,
+    boxed_future::zero_sized_future,
+    storage::{stored_future_vtable, PayloadStorage},
+    Allocator, DynamicFuture, Global,
+};
+use alloc::alloc::Layout;
+use core::future::Future;
+
+/// An allocator for a batch of `DynamicFuture`s which share a single buffer.
+///
+/// The buffer is partitioned into `slots` equally sized slots, each of which
+/// can hold one future. This allows to create a group of futures - which are
+/// e.g. polled concurrently and dropped together - with a single heap
+/// allocation instead of one allocation per future.
+///
+/// The buffer is allocated on the first call to [`BatchRecycler::allocate`],
+/// and each slot is sized for the future which is passed to this call. A slot
+/// is reused once the future which occupied it is dropped. Futures which don't
+/// fit into a slot, or which are allocated while all slots are in use, are
+/// boxed.
+///
+/// The buffer is freed once the allocator and all futures which use its slots
+/// are dropped. The buffer and the boxed futures are obtained from the
+/// allocator `A`.
+pub struct BatchRecycler<A: Allocator + Clone = Global> {
+    batch: *const BatchHeader<A>,
+    slots: usize,
+    alloc: A,
+}
+
+impl<A: Allocator + Clone> Drop for BatchRecycler<A> {
+    fn drop(&mut self) {
+        if !self.batch.is_null() {
+            unsafe {
+                BatchHeader::release(self.batch);
+            }
+        }
+    }
+}
+
+impl BatchRecycler {
+    /// Creates a new allocator whose buffer will provide `slots` slots
+    pub fn new(slots: usize) -> Self {
+        Self::new_in(slots, Global)
+    }
+}
+
+impl<A: Allocator + Clone> BatchRecycler<A> {
+    /// Creates a new allocator whose buffer will provide `slots` slots, and
+    /// which obtains its memory from `alloc`
+    pub fn new_in(slots: usize, alloc: A) -> Self {
+        Self {
+            batch: core::ptr::null(),
+            slots,
+            alloc,
+        }
+    }
+
+    /// Returns the amount of slots of this allocator
+    pub fn slots(&self) -> usize {
+        self.slots
+    }
+
+    /// Transforms the passed future into a `DynamicFuture`.
+    ///
+    /// The future is moved into the first slot which is no longer in use. If
+    /// the buffer had not been allocated yet, it is allocated with slots which
+    /// are sized for this future.
+    #[track_caller]
+    pub fn allocate<'a, F, T>(&mut self, fut: F) -> DynamicFuture<'a, T>
+    where
+        F: Future<Output = T> + 'a,
+        A: 'a,
+    {
+        let layout = Layout::new::<F>();
+        if layout.size() == 0 {
+            // Zero sized futures don't require any storage
+            return zero_sized_future(fut);
+        }
+
+        if self.batch.is_null() && self.slots != 0 {
+            // If the buffer can not be allocated - e.g. because its size
+            // overflows - the future is boxed.
+            if let Ok(batch) =
+                unsafe { BatchHeader::allocate(layout, self.slots, self.alloc.clone()) }
+            {
+                self.batch = batch;
+            }
+        }
+        if self.batch.is_null() {
+            return box_future_in(fut, self.alloc.clone());
+        }
+
+        unsafe {
+            let batch = &*self.batch;
+            if !batch.fits_layout(layout) {
+                return box_future_in(fut, self.alloc.clone());
+            }
+
+            for index in 0..batch.slots {
+                let slot = BatchHeader::slot(self.batch, index);
+                if SlotHeader::try_acquire(slot) {
+                    core::ptr::write(
+                        <SlotHeader<A> as PayloadStorage<F>>::payload(slot as *const ()),
+                        fut,
+                    );
+                    return DynamicFuture::new(
+                        slot as *const (),
+                        stored_future_vtable::<F, T, SlotHeader<A>>(),
+                    );
+                }
+            }
+        }
+
+        // All slots are in use
+        box_future_in(fut, self.alloc.clone())
+    }
+}
+
+/// A header stored at the start of the buffer of a `BatchRecycler`, which is
+/// followed by the slots.
+struct BatchHeader<A> {
+    /// The amount of active references to the buffer. The allocator holds one
+    /// reference, and every slot which is in use holds another one.
+    refcount: AtomicUsize,
+    /// The amount of slots in the buffer
+    slots: usize,
+    /// The layout of the payload section of each slot
+    payload_layout: Layout,
+    /// The offset of the payload from the start of a slot
+    payload_offset: usize,
+    /// The distance between two slots
+    slot_stride: usize,
+    /// The offset of the first slot from the start of the buffer
+    slots_offset: usize,
+    /// The layout of the whole buffer
+    layout: Layout,
+    /// The allocator which allocated the buffer
+    alloc: A,
+}
+
+/// A header stored at the start of each slot, which is followed by the payload.
+///
+/// The pointer of a `DynamicFuture` which is stored in a slot refers to this
+/// header.
+struct SlotHeader<A> {
+    /// The buffer which contains this slot
+    batch: *const BatchHeader<A>,
+    /// Whether the slot is in use by a payload
+    in_use: AtomicBool,
+}
+
+impl<A: Allocator> BatchHeader<A> {
+    /// Allocates a buffer with `slots` slots for payloads of up to
+    /// `payload_layout` via `alloc`.
+    ///
+    /// The buffer holds a single reference for the caller.
+    unsafe fn allocate(
+        payload_layout: Layout,
+        slots: usize,
+        alloc: A,
+    ) -> Result<*const BatchHeader<A>, ()> {
+        let (slot_layout, payload_offset) = Layout::new::<SlotHeader<A>>()
+            .extend(payload_layout)
+            .map_err(|_| ())?;
+        let slot_layout = slot_layout.pad_to_align();
+        let slots_size = slot_layout.size().checked_mul(slots).ok_or(())?;
+        let slots_layout =
+            Layout::from_size_align(slots_size, slot_layout.align()).map_err(|_| ())?;
+        let (layout, slots_offset) = Layout::new::<BatchHeader<A>>()
+            .extend(slots_layout)
+            .map_err(|_| ())?;
+        let layout = layout.pad_to_align();
+
+        let batch = alloc.allocate(layout) as *mut BatchHeader<A>;
+        if batch.is_null() {
+            return Err(());
+        }
+
+        core::ptr::write(
+            batch,
+            BatchHeader {
+                refcount: AtomicUsize::new(1),
+                slots,
+                payload_layout,
+                payload_offset,
+                slot_stride: slot_layout.size(),
+                slots_offset,
+                layout,
+                alloc,
+            },
+        );
+        for index in 0..slots {
+            core::ptr::write(
+                Self::slot(batch, index),
+                SlotHeader {
+                    batch,
+                    in_use: AtomicBool::new(false),
+                },
+            );
+        }
+        Ok(batch)
+    }
+
+    /// Returns the address of the slot with the given index.
+    ///
+    /// The address is derived from `batch`, in order to be valid for the slot
+    /// and its payload.
+    unsafe fn slot(batch: *const BatchHeader<A>, index: usize) -> *mut SlotHeader<A> {
+        let offset = (*batch).slots_offset + index * (*batch).slot_stride;
+        (batch as *const u8).add(offset) as *mut SlotHeader<A>
+    }
+
+    /// Returns whether a payload of `data_layout` fits into a slot
+    fn fits_layout(&self, data_layout: Layout) -> bool {
+        self.payload_layout.size() >= data_layout.size()
+            && self.payload_layout.align() >= data_layout.align()
+    }
+
+    /// Releases a reference on the buffer, and frees the buffer if it was the
+    /// last reference.
+    ///
+    /// This follows the same protocol as the refcount of recyclable storage.
+    unsafe fn release(batch: *const BatchHeader<A>) {
+        if (*batch).refcount.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+
+        fence(Ordering::Acquire);
+        let layout = (*batch).layout;
+        // Move the allocator out of the buffer before it gets freed
+        let alloc = core::ptr::read(core::ptr::addr_of!((*batch).alloc));
+        alloc.deallocate(batch as *mut u8, layout);
+    }
+}
+
+impl<A> SlotHeader<A> {
+    /// Tries to acquire the slot in order to store a new payload inside it.
+    ///
+    /// On success the slot holds a reference on the buffer. The caller must
+    /// hold a reference on the buffer.
+    unsafe fn try_acquire(slot: *const SlotHeader<A>) -> bool {
+        if (*slot)
+            .in_use
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+
+        // Like cloning an `Arc`, this does not require any synchronization
+        // since the caller already holds a reference.
+        (*(*slot).batch).refcount.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+}
+
+// A payload in a slot holds a reference on the buffer. Once the payload had
+// been dropped, the slot is marked as free before the reference is released.
+impl<P, A: Allocator> PayloadStorage<P> for SlotHeader<A> {
+    unsafe fn payload(ptr: *const ()) -> *mut P {
+        let slot = ptr as *const Self;
+        (slot as *const u8).add((*(*slot).batch).payload_offset) as *mut P
+    }
+
+    unsafe fn release(ptr: *const ()) {
+        let slot = ptr as *const Self;
+        let batch = (*slot).batch;
+        (*slot).in_use.store(false, Ordering::Release);
+        BatchHeader::release(batch);
+    }
+
+    unsafe fn size(ptr: *const ()) -> usize {
+        let slot = ptr as *const Self;
+        (*(*slot).batch).payload_layout.size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{poll_once, yield_now, yield_value, CountingAllocator, PanicOnDrop};
+    use alloc::vec::Vec;
+    use core::task::Poll;
+
+    /// Returns whether `fut` is stored in one of the slots of `recycler`
+    fn in_slot<T, A: Allocator + Clone>(
+        recycler: &BatchRecycler<A>,
+        fut: &DynamicFuture<'_, T>,
+    ) -> bool {
+        (0..recycler.slots).any(
+            |index| unsafe { BatchHeader::slot(recycler.batch, index) } as *const () == fut.ptr(),
+        )
+    }
+
+    #[test]
+    fn futures_are_stored_in_the_slots_of_one_buffer() {
+        let mut recycler = BatchRecycler::new(8);
+        assert_eq!(8, recycler.slots());
+        let mut futures: Vec<_> = (0..8)
+            .map(|value| recycler.allocate(yield_value(value)))
+            .collect();
+        for fut in &futures {
+            assert!(in_slot(&recycler, fut));
+        }
+
+        // All slots are in use
+        let ninth = recycler.allocate(yield_value(8));
+        assert!(!in_slot(&recycler, &ninth));
+
+        for (value, fut) in futures.iter_mut().enumerate() {
+            assert!(poll_once(fut).is_pending());
+            assert_eq!(Poll::Ready(value as u32), poll_once(fut));
+        }
+
+        // A slot is reused once its future was dropped
+        let freed = futures.remove(3).ptr();
+        let fut = recycler.allocate(yield_value(9));
+        assert_eq!(freed, fut.ptr());
+        drop((ninth, fut));
+    }
+
+    #[test]
+    fn all_slots_share_a_single_allocation() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = BatchRecycler::new_in(8, alloc.clone());
+        let futures: Vec<_> = (0..8)
+            .map(|value| recycler.allocate(yield_value(value)))
+            .collect();
+        assert_eq!(1, alloc.allocations());
+
+        // The ninth future gets boxed via the same allocator
+        let ninth = recycler.allocate(yield_value(8));
+        assert!(!in_slot(&recycler, &ninth));
+        assert_eq!(2, alloc.allocations());
+
+        drop(ninth);
+        assert_eq!(1, alloc.deallocations());
+        drop(futures);
+        drop(recycler);
+        assert_eq!(2, alloc.deallocations());
+    }
+
+    #[test]
+    fn futures_which_do_not_fit_are_boxed() {
+        let mut recycler = BatchRecycler::new(2);
+        let small = recycler.allocate(yield_value(1));
+        let data = [0u8; 64];
+        let big = recycler.allocate(async move { data[0] });
+        assert!(in_slot(&recycler, &small));
+        assert!(!in_slot(&recycler, &big));
+    }
+
+    #[test]
+    fn allocators_without_slots_box_all_futures() {
+        let mut recycler = BatchRecycler::new(0);
+        let mut fut = recycler.allocate(yield_value(1));
+        assert!(recycler.batch.is_null());
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready(1), poll_once(&mut fut));
+    }
+
+    #[test]
+    fn futures_outlive_the_allocator() {
+        let mut recycler = BatchRecycler::new(2);
+        let mut first = recycler.allocate(yield_value(1));
+        let mut second = recycler.allocate(yield_value(2));
+        drop(recycler);
+        assert!(poll_once(&mut first).is_pending());
+        drop(first);
+        assert!(poll_once(&mut second).is_pending());
+        assert_eq!(Poll::Ready(2), poll_once(&mut second));
+    }
+
+    #[test]
+    fn slots_are_freed_if_dropping_the_future_panics() {
+        let mut recycler = BatchRecycler::new(1);
+        let mut fut = recycler.allocate(async {
+            let _panic = PanicOnDrop;
+            yield_now().await;
+        });
+        assert!(poll_once(&mut fut).is_pending());
+        let slot = fut.ptr();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || drop(fut)));
+        assert!(res.is_err());
+
+        let fut = recycler.allocate(async {
+            yield_now().await;
+        });
+        assert_eq!(slot, fut.ptr());
+    }
+}
@@ -0,0 +1,74 @@
+//! A minimal executor which drives a single `DynamicFuture` on the current
+//! thread.
+
+use crate::DynamicFuture;
+use alloc::sync::Arc;
+use core::task::{Context, Poll, Waker};
+use std::{
+    task::Wake,
+    thread::{self, Thread},
+};
+
+/// Wakes the thread which blocks on a future by unparking it
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs the passed `DynamicFuture` to completion on the current thread, and
+/// returns its output.
+///
+/// The thread is parked while the future is pending, and is unparked by the
+/// `Waker` which is passed to the future. Since a wakeup which happens before
+/// the thread is parked is remembered by the thread, no wakeup gets lost. This
+/// allows to drive `DynamicFuture`s without depending on an executor.
+///
+/// This must not be called from within an asynchronous context, since it
+/// blocks the thread.
+pub fn block_on_dynamic<T>(mut fut: DynamicFuture<'_, T>) -> T {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match fut.poll_unpin(&mut cx) {
+            Poll::Ready(output) => return output,
+            // Parking might return spuriously. The future is then polled again.
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{box_future, test_utils::yield_now};
+
+    #[test]
+    fn yielding_futures_complete() {
+        let fut = box_future(async {
+            for _ in 0..3 {
+                yield_now().await;
+            }
+            5u32
+        });
+        assert_eq!(5, block_on_dynamic(fut));
+    }
+
+    #[test]
+    fn futures_which_are_woken_from_other_threads_complete() {
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        let sending = thread::spawn(move || {
+            thread::sleep(core::time::Duration::from_millis(10));
+            sender.send(7u32).unwrap();
+        });
+        let fut = box_future(async move { receiver.await.unwrap() });
+        assert_eq!(7, block_on_dynamic(fut));
+        sending.join().unwrap();
+    }
+}
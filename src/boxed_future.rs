@@ -3,42 +3,437 @@
 //! However in comparison to `Pin<Box<dyn Future>>` this mechanism will retain
 //! the `DynamicFuture` contract.
 
-use crate::{DynamicFuture, DynamicFutureVtable};
-use std::{
-    future::Future,
-    pin::Pin,
-    task::{Context, Poll},
+use crate::{
+    storage::{
+        drop_stored_payload, poll_stored_future, stored_future_vtable, stored_payload_size,
+        PayloadStorage,
+    },
+    vtable_for, Allocator, DynamicFuture, DynamicFutureVtable, SendDynamicFuture,
 };
+use alloc::{
+    alloc::{handle_alloc_error, Layout},
+    boxed::Box,
+};
+#[cfg(debug_assertions)]
+use core::cell::Cell;
+use core::{any::TypeId, future::Future, ptr::NonNull};
+
+/// A payload which is stored in memory that was obtained from the global
+/// allocator for the layout of the payload - e.g. via `Box`
+pub(crate) struct Boxed;
+
+impl<P> PayloadStorage<P> for Boxed {
+    unsafe fn payload(ptr: *const ()) -> *mut P {
+        ptr as *const P as *mut P
+    }
 
-unsafe fn drop_boxed_future<F>(ptr: *const ()) {
-    let fut: Box<F> = Box::from_raw(ptr as *const F as *mut F);
-    drop(fut);
+    unsafe fn release(ptr: *const ()) {
+        // This matches how `Box` frees its memory
+        let layout = Layout::new::<P>();
+        if layout.size() != 0 {
+            alloc::alloc::dealloc(ptr as *mut u8, layout);
+        }
+    }
+}
+
+/// A `Future` which is stored in memory that was obtained from the global
+/// allocator for the layout of the `BoxedFuture`.
+///
+/// In debug builds the `Future` is accompanied by a flag which detects
+/// reentrant polls. The `Future` is placed at the start of the storage, so that
+/// a pointer to the storage also points to the `Future`.
+#[repr(C)]
+struct BoxedFuture<F> {
+    fut: F,
+    #[cfg(debug_assertions)]
+    polling: Cell<bool>,
 }
 
-unsafe fn poll_boxed_future<T, F: Future<Output = T>>(
-    ptr: *const (),
-    cx: &mut Context<'_>,
-) -> Poll<T> {
-    let fut: &mut F = &mut *(ptr as *const F as *mut F);
-    let pinned = Pin::new_unchecked(fut);
-    pinned.poll(cx)
+impl<F> BoxedFuture<F> {
+    fn new(fut: F) -> Self {
+        BoxedFuture {
+            fut,
+            #[cfg(debug_assertions)]
+            polling: Cell::new(false),
+        }
+    }
+}
+
+impl<F> PayloadStorage<F> for BoxedFuture<F> {
+    unsafe fn payload(ptr: *const ()) -> *mut F {
+        let boxed = ptr as *const BoxedFuture<F> as *mut BoxedFuture<F>;
+        core::ptr::addr_of_mut!((*boxed).fut)
+    }
+
+    unsafe fn release(ptr: *const ()) {
+        <Boxed as PayloadStorage<BoxedFuture<F>>>::release(ptr);
+    }
+
+    #[cfg(debug_assertions)]
+    unsafe fn polling<'s>(ptr: *const ()) -> Option<&'s Cell<bool>> {
+        Some(&(*(ptr as *const BoxedFuture<F>)).polling)
+    }
 }
 
 fn boxed_future_vtable<'a, F: Future<Output = T> + 'a, T>() -> &'a DynamicFutureVtable<T> {
-    &DynamicFutureVtable {
-        drop_fn: drop_boxed_future::<F>,
-        poll_fn: poll_boxed_future::<T, F>,
+    stored_future_vtable::<F, T, BoxedFuture<F>>()
+}
+
+/// Stores a zero sized `Future` without allocating storage for it.
+///
+/// The `Future` is placed at a dangling but well aligned pointer, which is
+/// valid for zero sized accesses. It is still dropped in place, since a zero
+/// sized type might implement `Drop`.
+pub(crate) fn zero_sized_future<'a, F, T>(fut: F) -> DynamicFuture<'a, T>
+where
+    F: Future<Output = T> + 'a,
+{
+    debug_assert_eq!(core::mem::size_of::<F>(), 0);
+    let ptr = NonNull::<F>::dangling().as_ptr();
+    unsafe {
+        core::ptr::write(ptr, fut);
+        DynamicFuture::new(ptr as *const (), vtable_for::<F, T>())
     }
 }
 
 /// Stores a `Future` in a `Box` on the heap.
 ///
 /// However in comparison to `Pin<Box<dyn Future>>` this mechanism will retain
-/// the `DynamicFuture` contract.
+/// the `DynamicFuture` contract. Zero sized `Future`s are not allocated.
 pub fn box_future<'a, F, T>(fut: F) -> DynamicFuture<'a, T>
 where
     F: Future<Output = T> + 'a,
 {
-    let b = Box::new(fut);
+    if core::mem::size_of::<F>() == 0 {
+        return zero_sized_future(fut);
+    }
+
+    let b = Box::new(BoxedFuture::new(fut));
     unsafe { DynamicFuture::new(Box::into_raw(b) as *const (), boxed_future_vtable::<F, T>()) }
 }
+
+fn typed_boxed_future_vtable<F, T, S>() -> &'static DynamicFutureVtable<T>
+where
+    F: Future<Output = T> + 'static,
+    S: PayloadStorage<F> + 'static,
+{
+    &DynamicFutureVtable {
+        drop_fn: drop_stored_payload::<F, S>,
+        poll_fn: poll_stored_future::<T, F, S>,
+        size_fn: Some(stored_payload_size::<F, S>),
+        type_id_fn: Some(TypeId::of::<F>),
+    }
+}
+
+/// Stores a `Future` in a `Box` on the heap, and records its type.
+///
+/// This works like [`box_future`], but the type of the `Future` can later be
+/// recovered via [`DynamicFuture::downcast_source`]. This requires the
+/// `Future` to be `'static`.
+pub fn typed_box_future<F, T>(fut: F) -> DynamicFuture<'static, T>
+where
+    F: Future<Output = T> + 'static,
+{
+    if core::mem::size_of::<F>() == 0 {
+        // Boxing a zero sized `Future` does not allocate
+        let b = Box::new(fut);
+        return unsafe {
+            DynamicFuture::new(
+                Box::into_raw(b) as *const (),
+                typed_boxed_future_vtable::<F, T, Boxed>(),
+            )
+        };
+    }
+
+    // The `Future` is located at the start of the `BoxedFuture`, which allows
+    // to downcast the pointer to it
+    let b = Box::new(BoxedFuture::new(fut));
+    unsafe {
+        DynamicFuture::new(
+            Box::into_raw(b) as *const (),
+            typed_boxed_future_vtable::<F, T, BoxedFuture<F>>(),
+        )
+    }
+}
+
+/// Stores a `Future` in a `Box` on the heap without aborting if memory can not
+/// be allocated.
+///
+/// If the allocation fails, the `Future` is handed back to the caller.
+pub fn try_box_future<'a, F, T>(fut: F) -> Result<DynamicFuture<'a, T>, F>
+where
+    F: Future<Output = T> + 'a,
+{
+    if core::mem::size_of::<F>() == 0 {
+        // Zero sized types don't require any storage
+        return Ok(zero_sized_future(fut));
+    }
+
+    unsafe {
+        let ptr = alloc::alloc::alloc(Layout::new::<BoxedFuture<F>>()) as *mut BoxedFuture<F>;
+        if ptr.is_null() {
+            return Err(fut);
+        }
+        core::ptr::write(ptr, BoxedFuture::new(fut));
+        // The memory had been allocated by the global allocator using the
+        // layout of `BoxedFuture<F>`, like the memory of a `Box`.
+        Ok(DynamicFuture::new(
+            ptr as *const (),
+            boxed_future_vtable::<F, T>(),
+        ))
+    }
+}
+
+/// A `Future` which is stored in memory that was obtained from a custom allocator.
+///
+/// The allocator is stored next to the `Future`, since it is required for freeing
+/// the memory once the `Future` is dropped. Streams are stored the same way.
+pub(crate) struct AllocatedFuture<F, A> {
+    pub(crate) fut: F,
+    pub(crate) alloc: A,
+}
+
+impl<F, A: Allocator> PayloadStorage<F> for AllocatedFuture<F, A> {
+    unsafe fn payload(ptr: *const ()) -> *mut F {
+        let allocated = ptr as *const AllocatedFuture<F, A> as *mut AllocatedFuture<F, A>;
+        core::ptr::addr_of_mut!((*allocated).fut)
+    }
+
+    unsafe fn release(ptr: *const ()) {
+        let allocated = ptr as *const AllocatedFuture<F, A> as *mut AllocatedFuture<F, A>;
+        // Move the allocator out of the storage before it gets freed
+        let alloc = core::ptr::read(core::ptr::addr_of!((*allocated).alloc));
+        let layout = Layout::new::<AllocatedFuture<F, A>>();
+        if layout.size() != 0 {
+            alloc.deallocate(allocated as *mut u8, layout);
+        }
+    }
+}
+
+fn allocated_future_vtable<'a, F, T, A>() -> &'a DynamicFutureVtable<T>
+where
+    F: Future<Output = T> + 'a,
+    A: Allocator + 'a,
+{
+    stored_future_vtable::<F, T, AllocatedFuture<F, A>>()
+}
+
+/// Stores a `Future` on the heap using the allocator `alloc`.
+///
+/// This works like [`box_future`], but obtains the memory for the `Future` from
+/// a custom allocator.
+pub fn box_future_in<'a, F, T, A>(fut: F, alloc: A) -> DynamicFuture<'a, T>
+where
+    F: Future<Output = T> + 'a,
+    A: Allocator + 'a,
+{
+    match try_box_future_in(fut, alloc) {
+        Ok(fut) => fut,
+        Err(_) => handle_alloc_error(Layout::new::<AllocatedFuture<F, A>>()),
+    }
+}
+
+/// Stores a `Future` on the heap using the allocator `alloc`, without aborting if
+/// memory can not be allocated.
+///
+/// If the allocation fails, the `Future` is handed back to the caller.
+pub fn try_box_future_in<'a, F, T, A>(fut: F, alloc: A) -> Result<DynamicFuture<'a, T>, F>
+where
+    F: Future<Output = T> + 'a,
+    A: Allocator + 'a,
+{
+    let layout = Layout::new::<AllocatedFuture<F, A>>();
+    let ptr = if layout.size() == 0 {
+        // Zero sized types don't require any storage
+        NonNull::<AllocatedFuture<F, A>>::dangling().as_ptr()
+    } else {
+        let ptr = alloc.allocate(layout) as *mut AllocatedFuture<F, A>;
+        if ptr.is_null() {
+            return Err(fut);
+        }
+        ptr
+    };
+
+    unsafe {
+        core::ptr::write(ptr, AllocatedFuture { fut, alloc });
+        Ok(DynamicFuture::new(
+            ptr as *const (),
+            allocated_future_vtable::<F, T, A>(),
+        ))
+    }
+}
+
+/// Stores a `Future` which owns all its captures in a `Box` on the heap.
+///
+/// This works like [`box_future`], but fixes the lifetime of the returned
+/// `DynamicFuture` to `'static`. This allows to store the returned `Future` in
+/// collections or data structures which require `'static` types without
+/// having to name the lifetime.
+pub fn box_future_owned<F, T>(fut: F) -> DynamicFuture<'static, T>
+where
+    F: Future<Output = T> + 'static,
+{
+    box_future(fut)
+}
+
+/// Stores a `Send` `Future` in a `Box` on the heap.
+///
+/// This works like [`box_future`], but the returned `SendDynamicFuture` can be
+/// sent to other threads - e.g. in order to spawn it on a multithreaded executor.
+pub fn box_future_send<'a, F, T>(fut: F) -> SendDynamicFuture<'a, T>
+where
+    F: Future<Output = T> + Send + 'a,
+{
+    // Safety: The future is required to be `Send`
+    unsafe { SendDynamicFuture::from_dynamic(box_future(fut)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{CountingAllocator, DropCounter, FailingAllocator};
+    use core::cell::Cell;
+    use futures::executor::block_on;
+    use std::thread;
+
+    #[test]
+    fn send_future_can_be_polled_on_other_thread() {
+        let value = 5u32;
+        let fut = box_future_send(async move { value * 2 });
+        let result = thread::spawn(move || block_on(fut)).join().unwrap();
+        assert_eq!(10, result);
+    }
+
+    #[test]
+    fn send_future_converts_into_dynamic_future() {
+        let fut: DynamicFuture<'_, u32> = box_future_send(async { 3 }).into();
+        assert_eq!(3, block_on(fut));
+    }
+
+    #[test]
+    fn try_box_future_boxes_futures() {
+        let value = [1u8; 32];
+        let fut = try_box_future(async move { value[31] })
+            .unwrap_or_else(|_| panic!("The allocation failed"));
+        assert_eq!(1, block_on(fut));
+    }
+
+    #[test]
+    fn box_future_in_balances_allocations() {
+        let alloc = CountingAllocator::default();
+        let value = [2u8; 32];
+        let fut = box_future_in(async move { value[0] }, alloc.clone());
+        assert_eq!(1, alloc.allocations());
+        assert_eq!(2, block_on(fut));
+        assert_eq!(1, alloc.deallocations());
+
+        // Dropping a future which was not polled to completion also frees it
+        drop(box_future_in(async move { value[0] }, alloc.clone()));
+        assert_eq!(2, alloc.allocations());
+        assert_eq!(2, alloc.deallocations());
+    }
+
+    #[test]
+    fn try_box_future_in_hands_back_the_future_on_failure() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let fut = async move {
+            let _counter = counter;
+        };
+        let fut = match try_box_future_in(fut, FailingAllocator) {
+            Ok(_) => panic!("The allocation should fail"),
+            Err(fut) => fut,
+        };
+        assert_eq!(0, drops.get());
+        block_on(fut);
+        assert_eq!(1, drops.get());
+    }
+
+    std::thread_local! {
+        static ZST_DROPS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// A zero sized future whose drop is observable
+    struct ZeroSized;
+
+    impl Future for ZeroSized {
+        type Output = u8;
+
+        fn poll(
+            self: core::pin::Pin<&mut Self>,
+            _cx: &mut core::task::Context<'_>,
+        ) -> core::task::Poll<u8> {
+            core::task::Poll::Ready(9)
+        }
+    }
+
+    impl Drop for ZeroSized {
+        fn drop(&mut self) {
+            ZST_DROPS.with(|drops| drops.set(drops.get() + 1));
+        }
+    }
+
+    #[test]
+    fn zero_sized_futures_are_dropped_without_allocating() {
+        let drops_before = ZST_DROPS.with(Cell::get);
+        let fut = box_future(ZeroSized);
+        assert_eq!(
+            NonNull::<ZeroSized>::dangling().as_ptr() as *const (),
+            fut.ptr()
+        );
+        assert_eq!(9, block_on(fut));
+        assert_eq!(drops_before + 1, ZST_DROPS.with(Cell::get));
+
+        let alloc = CountingAllocator::default();
+        let mut recycler = crate::RecyclableFutureAllocator::new_in(alloc.clone());
+        let (fut, reused) = recycler.allocate_tracked(ZeroSized);
+        assert_eq!(crate::Reused::ZeroSized, reused);
+        drop(fut);
+        assert_eq!(drops_before + 2, ZST_DROPS.with(Cell::get));
+        assert_eq!(0, alloc.allocations());
+        assert_eq!(None, recycler.cached_layout());
+    }
+
+    #[test]
+    fn vtables_outlive_their_futures_and_are_not_allocated() {
+        let alloc = CountingAllocator::default();
+        let fut = box_future_in(async { 3u32 }, alloc.clone());
+        // The vtable is a static, which stays valid after the future is gone
+        let vtable: &'static DynamicFutureVtable<u32> = fut.vtable();
+        drop(fut);
+        assert!(vtable.size_fn().is_some());
+
+        // Only the futures themselves are allocated
+        let futures: alloc::vec::Vec<_> = (0..4u32)
+            .map(|i| box_future_in(async move { i }, alloc.clone()))
+            .collect();
+        assert_eq!(5, alloc.allocations());
+        for (i, fut) in futures.into_iter().enumerate() {
+            assert_eq!(i as u32, block_on(fut));
+        }
+        assert_eq!(5, alloc.deallocations());
+    }
+
+    #[test]
+    fn owned_futures_can_be_stored_in_static_collections() {
+        use alloc::vec::Vec;
+        use core::cell::RefCell;
+
+        std::thread_local! {
+            static PENDING: RefCell<Vec<DynamicFuture<'static, u32>>> =
+                const { RefCell::new(Vec::new()) };
+        }
+
+        let mut recycler = crate::RecyclableFutureAllocator::new();
+        let values = alloc::vec![1u32, 2, 3];
+        let boxed = box_future_owned(async move { values.iter().sum::<u32>() });
+        let recycled = recycler.allocate_owned(async { 4u32 });
+        PENDING.with(|pending| pending.borrow_mut().extend([boxed, recycled]));
+        // The recycled future keeps its storage alive
+        drop(recycler);
+
+        let futures = PENDING.with(|pending| core::mem::take(&mut *pending.borrow_mut()));
+        let outputs: Vec<u32> = futures.into_iter().map(block_on).collect();
+        assert_eq!(alloc::vec![6, 4], outputs);
+    }
+}
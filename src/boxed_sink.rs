@@ -0,0 +1,32 @@
+//! Stores a `Sink` in a `Box` on the heap.
+//!
+//! However in comparison to `Pin<Box<dyn Sink>>` this mechanism will retain
+//! the `DynamicSink` contract.
+
+use crate::{boxed_future::Boxed, storage::stored_sink_vtable, DynamicSink, DynamicSinkVtable};
+use alloc::boxed::Box;
+use futures_sink::Sink;
+
+fn boxed_sink_vtable<'a, S, Item, E>() -> &'a DynamicSinkVtable<Item, E>
+where
+    S: Sink<Item, Error = E> + 'a,
+{
+    stored_sink_vtable::<S, Item, E, Boxed>()
+}
+
+/// Stores a `Sink` in a `Box` on the heap.
+///
+/// However in comparison to `Pin<Box<dyn Sink>>` this mechanism will retain
+/// the `DynamicSink` contract.
+pub fn box_sink<'a, S, Item, E>(sink: S) -> DynamicSink<'a, Item, E>
+where
+    S: Sink<Item, Error = E> + 'a,
+{
+    let b = Box::new(sink);
+    unsafe {
+        DynamicSink::new(
+            Box::into_raw(b) as *const (),
+            boxed_sink_vtable::<S, Item, E>(),
+        )
+    }
+}
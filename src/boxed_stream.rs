@@ -0,0 +1,59 @@
+//! Stores a `Stream` in a `Box` on the heap.
+//!
+//! However in comparison to `Pin<Box<dyn Stream>>` this mechanism will retain
+//! the `DynamicStream` contract.
+
+use crate::{
+    boxed_future::{AllocatedFuture, Boxed},
+    storage::stored_stream_vtable,
+    Allocator, DynamicStream, DynamicStreamVtable,
+};
+use alloc::{alloc::Layout, boxed::Box};
+use core::ptr::NonNull;
+use futures_core::Stream;
+
+fn boxed_stream_vtable<'a, S: Stream<Item = T> + 'a, T>() -> &'a DynamicStreamVtable<T> {
+    stored_stream_vtable::<S, T, Boxed>()
+}
+
+/// Stores a `Stream` in a `Box` on the heap.
+///
+/// However in comparison to `Pin<Box<dyn Stream>>` this mechanism will retain
+/// the `DynamicStream` contract.
+pub fn box_stream<'a, S, T>(stream: S) -> DynamicStream<'a, T>
+where
+    S: Stream<Item = T> + 'a,
+{
+    let b = Box::new(stream);
+    unsafe { DynamicStream::new(Box::into_raw(b) as *const (), boxed_stream_vtable::<S, T>()) }
+}
+
+/// Stores a `Stream` on the heap using the allocator `alloc`, without aborting
+/// if memory can not be allocated.
+///
+/// If the allocation fails, the `Stream` is handed back to the caller.
+pub(crate) fn try_box_stream_in<'a, S, T, A>(stream: S, alloc: A) -> Result<DynamicStream<'a, T>, S>
+where
+    S: Stream<Item = T> + 'a,
+    A: Allocator + 'a,
+{
+    let layout = Layout::new::<AllocatedFuture<S, A>>();
+    let ptr = if layout.size() == 0 {
+        // Zero sized types don't require any storage
+        NonNull::<AllocatedFuture<S, A>>::dangling().as_ptr()
+    } else {
+        let ptr = alloc.allocate(layout) as *mut AllocatedFuture<S, A>;
+        if ptr.is_null() {
+            return Err(stream);
+        }
+        ptr
+    };
+
+    unsafe {
+        core::ptr::write(ptr, AllocatedFuture { fut: stream, alloc });
+        Ok(DynamicStream::new(
+            ptr as *const (),
+            stored_stream_vtable::<S, T, AllocatedFuture<S, A>>(),
+        ))
+    }
+}
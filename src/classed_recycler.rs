@@ -0,0 +1,235 @@
+use crate::{
+    box_future_in,
+    boxed_future::zero_sized_future,
+    recycler::{
+        new_retained_recyclable_future, release_recycler_reference, reuse_recyclable_future,
+        RecyclableFutureHeader,
+    },
+    Allocator, DynamicFuture, Global,
+};
+use alloc::{alloc::Layout, vec::Vec};
+use core::future::Future;
+
+/// The size of the storage in the smallest size class
+const MIN_CLASS_SIZE: usize = 16;
+
+/// An allocator for `DynamicFuture`s which reuses storage between futures of
+/// different sizes.
+///
+/// The allocator maintains `N` size classes. The storage in size class `i`
+/// can hold futures of up to `16 << i` bytes - i.e. the sizes of the classes
+/// are increasing powers of two. Futures are stored in the smallest size class
+/// they fit into, and reuse any storage of this class which is no longer in
+/// use. Each class retains storage for up to `slots_per_class` futures.
+///
+/// This allows to recycle storage for workloads which produce futures of
+/// multiple distinct sizes - e.g. from different methods of a trait object -
+/// for which a single slot of storage would be replaced all the time.
+///
+/// Futures which are bigger than the biggest size class, and futures which
+/// can't be stored because all storage of their class is in use, are boxed.
+/// The storage and the boxed futures are obtained from the allocator `A`.
+pub struct ClassedRecycler<const N: usize, A: Allocator + Clone = Global> {
+    classes: [Vec<*const RecyclableFutureHeader<A>>; N],
+    slots_per_class: usize,
+    alloc: A,
+}
+
+impl<const N: usize, A: Allocator + Clone> Drop for ClassedRecycler<N, A> {
+    fn drop(&mut self) {
+        for class in self.classes.iter_mut() {
+            for slot in class.drain(..) {
+                unsafe {
+                    release_recycler_reference(slot);
+                }
+            }
+        }
+    }
+}
+
+impl<const N: usize> ClassedRecycler<N> {
+    /// Creates a new allocator which will keep storage for up to
+    /// `slots_per_class` futures in each of its `N` size classes around.
+    pub fn new(slots_per_class: usize) -> Self {
+        Self::new_in(slots_per_class, Global)
+    }
+}
+
+impl<const N: usize, A: Allocator + Clone> ClassedRecycler<N, A> {
+    /// Creates a new allocator which will keep storage for up to
+    /// `slots_per_class` futures in each of its `N` size classes around, and
+    /// which obtains the storage from `alloc`
+    pub fn new_in(slots_per_class: usize, alloc: A) -> Self {
+        Self {
+            classes: core::array::from_fn(|_| Vec::new()),
+            slots_per_class,
+            alloc,
+        }
+    }
+
+    /// Returns the maximum size of the futures which can be stored in the size
+    /// class with the given index.
+    pub fn class_size(class: usize) -> usize {
+        MIN_CLASS_SIZE << class
+    }
+
+    /// Returns the index of the smallest size class which can hold futures of
+    /// `size` bytes, or `None` if the future is bigger than all size classes.
+    fn class_of(size: usize) -> Option<usize> {
+        let class = size.max(MIN_CLASS_SIZE).checked_next_power_of_two()? / MIN_CLASS_SIZE;
+        let class = class.trailing_zeros() as usize;
+        if class < N {
+            Some(class)
+        } else {
+            None
+        }
+    }
+
+    /// Transforms the passed future into a `DynamicFuture`.
+    ///
+    /// This action will move the future on the heap and type erase its behavior.
+    /// The operation will reuse the storage of the futures size class if it is
+    /// no longer in use by a previously returned future.
+    #[track_caller]
+    pub fn allocate<'a, F, T>(&mut self, mut fut: F) -> DynamicFuture<'a, T>
+    where
+        F: Future<Output = T> + 'a,
+        A: 'a,
+    {
+        let layout = Layout::for_value(&fut);
+        if layout.size() == 0 {
+            // Zero sized futures don't require any storage
+            return zero_sized_future(fut);
+        }
+        let class = match Self::class_of(layout.size()) {
+            Some(class) => class,
+            None => return box_future_in(fut, self.alloc.clone()),
+        };
+
+        let slots = &mut self.classes[class];
+        for slot in slots.iter() {
+            if !unsafe { (**slot).fits_layout(layout) } {
+                continue;
+            }
+            match unsafe { reuse_recyclable_future(*slot, fut) } {
+                Ok(fut) => return fut,
+                Err(f) => fut = f,
+            }
+        }
+
+        if slots.len() < self.slots_per_class {
+            // The storage is sized for the whole class, in order to allow all
+            // futures of the class to reuse it.
+            let capacity = match Layout::from_size_align(Self::class_size(class), layout.align()) {
+                Ok(capacity) => capacity,
+                Err(_) => return box_future_in(fut, self.alloc.clone()),
+            };
+            let (fut, header) = new_retained_recyclable_future(fut, capacity, self.alloc.clone());
+            slots.extend(header);
+            return fut;
+        }
+
+        // All slots of the class are in use
+        box_future_in(fut, self.alloc.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{poll_once, CountingAllocator};
+    use core::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    /// A future of `S` bytes, which is immediately ready
+    struct Bytes<const S: usize>([u8; S]);
+
+    impl<const S: usize> Future for Bytes<S> {
+        type Output = u8;
+
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<u8> {
+            Poll::Ready(self.0[S - 1])
+        }
+    }
+
+    fn contains(recycler: &ClassedRecycler<4>, class: usize, ptr: *const ()) -> bool {
+        recycler.classes[class].contains(&(ptr as *const RecyclableFutureHeader))
+    }
+
+    #[test]
+    fn sizes_are_mapped_to_the_smallest_class_which_fits() {
+        assert_eq!(Some(0), ClassedRecycler::<4>::class_of(1));
+        assert_eq!(Some(0), ClassedRecycler::<4>::class_of(16));
+        assert_eq!(Some(1), ClassedRecycler::<4>::class_of(17));
+        assert_eq!(Some(3), ClassedRecycler::<4>::class_of(128));
+        assert_eq!(None, ClassedRecycler::<4>::class_of(129));
+        assert_eq!(None, ClassedRecycler::<4>::class_of(usize::MAX));
+        assert_eq!(128, ClassedRecycler::<4>::class_size(3));
+    }
+
+    #[test]
+    fn futures_of_different_sizes_reuse_the_storage_of_their_class() {
+        let mut recycler = ClassedRecycler::<4>::new(1);
+        let mut storage = None;
+        for _ in 0..3 {
+            let mut small = recycler.allocate(Bytes([1u8; 10]));
+            let mut medium = recycler.allocate(Bytes([2u8; 30]));
+            let mut large = recycler.allocate(Bytes([3u8; 100]));
+            let ptrs = (small.ptr(), medium.ptr(), large.ptr());
+            assert!(contains(&recycler, 0, ptrs.0));
+            assert!(contains(&recycler, 1, ptrs.1));
+            assert!(contains(&recycler, 3, ptrs.2));
+            assert_eq!(*storage.get_or_insert(ptrs), ptrs);
+
+            assert_eq!(Poll::Ready(1), poll_once(&mut small));
+            assert_eq!(Poll::Ready(2), poll_once(&mut medium));
+            assert_eq!(Poll::Ready(3), poll_once(&mut large));
+        }
+        assert!(recycler.classes[2].is_empty());
+    }
+
+    #[test]
+    fn allocations_are_bounded_by_the_storage_of_each_class() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = ClassedRecycler::<4, _>::new_in(1, alloc.clone());
+        for _ in 0..10 {
+            let mut small = recycler.allocate(Bytes([1u8; 10]));
+            let mut medium = recycler.allocate(Bytes([2u8; 30]));
+            let mut large = recycler.allocate(Bytes([3u8; 100]));
+            assert_eq!(Poll::Ready(1), poll_once(&mut small));
+            assert_eq!(Poll::Ready(2), poll_once(&mut medium));
+            assert_eq!(Poll::Ready(3), poll_once(&mut large));
+            drop((small, medium, large));
+
+            // One slot of storage for each of the 3 classes which are in use
+            assert_eq!(3, alloc.allocations());
+        }
+        assert_eq!(0, alloc.deallocations());
+        drop(recycler);
+        assert_eq!(3, alloc.deallocations());
+    }
+
+    #[test]
+    fn smaller_futures_of_a_class_reuse_its_storage() {
+        let mut recycler = ClassedRecycler::<4>::new(1);
+        let ptr = recycler.allocate(Bytes([0u8; 64])).ptr();
+        let mut fut = recycler.allocate(Bytes([4u8; 33]));
+        assert_eq!(ptr, fut.ptr());
+        assert_eq!(Poll::Ready(4), poll_once(&mut fut));
+    }
+
+    #[test]
+    fn futures_are_boxed_if_their_class_is_full_or_they_are_too_big() {
+        let mut recycler = ClassedRecycler::<4>::new(1);
+        let first = recycler.allocate(Bytes([0u8; 8]));
+        let second = recycler.allocate(Bytes([0u8; 8]));
+        assert!(contains(&recycler, 0, first.ptr()));
+        assert!(!contains(&recycler, 0, second.ptr()));
+
+        let mut big = recycler.allocate(Bytes([5u8; 256]));
+        assert_eq!(Poll::Ready(5), poll_once(&mut big));
+        assert!(recycler.classes.iter().all(|class| class.len() <= 1));
+    }
+}
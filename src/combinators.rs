@@ -0,0 +1,1063 @@
+//! Combinators which operate on `DynamicFuture`s, and which return a
+//! `DynamicFuture` themselves.
+
+use crate::{
+    box_future,
+    sync::{AtomicU8, AtomicUsize, Ordering},
+    DynamicFuture, SendDynamicFuture,
+};
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Stores either a `DynamicFuture` which has not yet completed, or its output
+enum MaybeDone<'a, T> {
+    Pending(DynamicFuture<'a, T>),
+    Done(T),
+    Taken,
+}
+
+impl<'a, T> MaybeDone<'a, T> {
+    /// Polls the `Future` if it has not yet completed. Returns whether the
+    /// output is available.
+    fn poll(&mut self, cx: &mut Context<'_>) -> bool {
+        match self {
+            MaybeDone::Pending(fut) => match fut.poll_unpin(cx) {
+                Poll::Ready(output) => {
+                    *self = MaybeDone::Done(output);
+                    true
+                }
+                Poll::Pending => false,
+            },
+            MaybeDone::Done(_) => true,
+            MaybeDone::Taken => panic!("Join polled after completion"),
+        }
+    }
+
+    fn take(&mut self) -> T {
+        match core::mem::replace(self, MaybeDone::Taken) {
+            MaybeDone::Done(output) => output,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Polls two `DynamicFuture`s until both have completed
+struct Join<'a, A, B> {
+    a: MaybeDone<'a, A>,
+    b: MaybeDone<'a, B>,
+}
+
+// The outputs are never pinned, and `DynamicFuture` is `Unpin`
+impl<'a, A, B> Unpin for Join<'a, A, B> {}
+
+impl<'a, A, B> Future for Join<'a, A, B> {
+    type Output = (A, B);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        // Both futures need to be polled in order to register for wakeups
+        let a_done = this.a.poll(cx);
+        let b_done = this.b.poll(cx);
+        if a_done && b_done {
+            Poll::Ready((this.a.take(), this.b.take()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Returns a `DynamicFuture` which polls both `a` and `b` to completion, and
+/// resolves to the outputs of both.
+///
+/// If the returned `Future` is dropped before both `Future`s completed, the
+/// still pending `Future` as well as the output of the completed one are
+/// dropped.
+pub fn dynamic_join<'a, A: 'a, B: 'a>(
+    a: DynamicFuture<'a, A>,
+    b: DynamicFuture<'a, B>,
+) -> DynamicFuture<'a, (A, B)> {
+    box_future(Join {
+        a: MaybeDone::Pending(a),
+        b: MaybeDone::Pending(b),
+    })
+}
+
+/// Stores the output of one of two possible `Future`s
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Either<A, B> {
+    /// The output of the first `Future`
+    Left(A),
+    /// The output of the second `Future`
+    Right(B),
+}
+
+/// `Either` is a `Future` if both variants are `Future`s with the same output.
+///
+/// Since the layout of the enum covers both variants, this allows to store the
+/// `Future`s of two branches - e.g. two different `async` blocks - in storage of
+/// the same layout. See [`RecyclableFutureAllocator::allocate_either`].
+///
+/// [`RecyclableFutureAllocator::allocate_either`]: crate::RecyclableFutureAllocator::allocate_either
+impl<A, B> Future for Either<A, B>
+where
+    A: Future,
+    B: Future<Output = A::Output>,
+{
+    type Output = A::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<A::Output> {
+        // The variant is never moved out of the pinned enum
+        unsafe {
+            match self.get_unchecked_mut() {
+                Either::Left(a) => Pin::new_unchecked(a).poll(cx),
+                Either::Right(b) => Pin::new_unchecked(b).poll(cx),
+            }
+        }
+    }
+}
+
+/// Polls two `DynamicFuture`s until either of them completes
+struct Select<'a, A, B> {
+    /// The pending futures. These are `None` once one of them completed.
+    inner: Option<(DynamicFuture<'a, A>, DynamicFuture<'a, B>)>,
+}
+
+impl<'a, A, B> Future for Select<'a, A, B> {
+    type Output = Either<A, B>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let (a, b) = this.inner.as_mut().expect("Select polled after completion");
+
+        let output = if let Poll::Ready(output) = a.poll_unpin(cx) {
+            Either::Left(output)
+        } else if let Poll::Ready(output) = b.poll_unpin(cx) {
+            Either::Right(output)
+        } else {
+            return Poll::Pending;
+        };
+
+        // Drop the `Future` which did not complete
+        this.inner = None;
+        Poll::Ready(output)
+    }
+}
+
+/// Returns a `DynamicFuture` which resolves to the output of whichever of `a`
+/// and `b` completes first.
+///
+/// `a` is polled first, and therefore wins if both `Future`s are ready at the
+/// same time. The `Future` which did not complete is dropped once the other one
+/// completed.
+pub fn dynamic_select<'a, A: 'a, B: 'a>(
+    a: DynamicFuture<'a, A>,
+    b: DynamicFuture<'a, B>,
+) -> DynamicFuture<'a, Either<A, B>> {
+    box_future(Select {
+        inner: Some((a, b)),
+    })
+}
+
+/// Polls a `DynamicFuture` until it completes or a deadline fires
+struct Timeout<'a, T, D> {
+    select: Select<'a, T, D>,
+}
+
+impl<'a, T, D> Future for Timeout<'a, T, D> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        match Pin::new(&mut self.get_mut().select).poll(cx) {
+            Poll::Ready(Either::Left(output)) => Poll::Ready(Some(output)),
+            Poll::Ready(Either::Right(_)) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Returns a `DynamicFuture` which resolves to the output of `fut` if it
+/// completes before `deadline`, and to `None` otherwise.
+///
+/// The deadline can be any `DynamicFuture` - e.g. a timer of the executor.
+/// Like for [`dynamic_select`], `fut` wins if both `Future`s are ready at the
+/// same time, and the `Future` which did not complete is dropped once the other
+/// one completed.
+pub fn dynamic_timeout<'a, T: 'a, D: 'a>(
+    fut: DynamicFuture<'a, T>,
+    deadline: DynamicFuture<'a, D>,
+) -> DynamicFuture<'a, Option<T>> {
+    box_future(Timeout {
+        select: Select {
+            inner: Some((fut, deadline)),
+        },
+    })
+}
+
+/// Calls a closure with a reference to the output of a `DynamicFuture`
+struct Inspect<'a, T, F> {
+    fut: DynamicFuture<'a, T>,
+    /// The closure. This is `None` once the `Future` completed.
+    f: Option<F>,
+}
+
+// The closure is never pinned, and `DynamicFuture` is `Unpin`
+impl<'a, T, F> Unpin for Inspect<'a, T, F> {}
+
+impl<'a, T, F: FnOnce(&T)> Future for Inspect<'a, T, F> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let this = self.get_mut();
+        let output = match this.fut.poll_unpin(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        let f = this.f.take().expect("Inspect polled after completion");
+        f(&output);
+        Poll::Ready(output)
+    }
+}
+
+/// Returns a `DynamicFuture` which calls `f` with a reference to the output of
+/// `fut` once it completed, and resolves to the output afterwards.
+pub fn dynamic_inspect<'a, T: 'a, F>(fut: DynamicFuture<'a, T>, f: F) -> DynamicFuture<'a, T>
+where
+    F: FnOnce(&T) + 'a,
+{
+    box_future(Inspect { fut, f: Some(f) })
+}
+
+/// Transforms the output of a `DynamicFuture` with a closure
+struct Map<'a, T, F> {
+    fut: DynamicFuture<'a, T>,
+    /// The closure. This is `None` once the `Future` completed.
+    f: Option<F>,
+}
+
+// The closure is never pinned, and `DynamicFuture` is `Unpin`
+impl<'a, T, F> Unpin for Map<'a, T, F> {}
+
+impl<'a, T, U, F: FnOnce(T) -> U> Future for Map<'a, T, F> {
+    type Output = U;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<U> {
+        let this = self.get_mut();
+        let output = match this.fut.poll_unpin(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        let f = this.f.take().expect("Map polled after completion");
+        Poll::Ready(f(output))
+    }
+}
+
+/// Returns a `DynamicFuture` which resolves to the output of `fut` after it
+/// got transformed by `f`.
+pub fn dynamic_map<'a, T: 'a, U, F>(fut: DynamicFuture<'a, T>, f: F) -> DynamicFuture<'a, U>
+where
+    F: FnOnce(T) -> U + 'a,
+{
+    box_future(Map { fut, f: Some(f) })
+}
+
+/// Returns a `DynamicFuture` which resolves to the output of `fut`, after the
+/// value of a successful output got transformed by `f`.
+pub fn dynamic_map_ok<'a, T: 'a, E: 'a, U, F>(
+    fut: DynamicFuture<'a, Result<T, E>>,
+    f: F,
+) -> DynamicFuture<'a, Result<U, E>>
+where
+    F: FnOnce(T) -> U + 'a,
+{
+    dynamic_map(fut, |output| output.map(f))
+}
+
+/// Returns a `DynamicFuture` which resolves to the output of `fut`, after the
+/// error of a failed output got transformed by `f`.
+pub fn dynamic_map_err<'a, T: 'a, E: 'a, G, F>(
+    fut: DynamicFuture<'a, Result<T, E>>,
+    f: F,
+) -> DynamicFuture<'a, Result<T, G>>
+where
+    F: FnOnce(E) -> G + 'a,
+{
+    dynamic_map(fut, |output| output.map_err(f))
+}
+
+/// Chains a second `DynamicFuture` to the successful output of a first one
+enum AndThen<'a, T, U, E, F> {
+    /// The first `Future` is in flight
+    First(DynamicFuture<'a, Result<T, E>>, F),
+    /// The `Future` which was created from the output of the first one is in
+    /// flight
+    Second(DynamicFuture<'a, Result<U, E>>),
+    Done,
+}
+
+// The closure is never pinned, and `DynamicFuture` is `Unpin`
+impl<'a, T, U, E, F> Unpin for AndThen<'a, T, U, E, F> {}
+
+impl<'a, T, U, E, F> Future for AndThen<'a, T, U, E, F>
+where
+    F: FnOnce(T) -> DynamicFuture<'a, Result<U, E>>,
+{
+    type Output = Result<U, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<U, E>> {
+        let this = self.get_mut();
+        loop {
+            match this {
+                AndThen::First(fut, _) => {
+                    let value = match fut.poll_unpin(cx) {
+                        Poll::Ready(Ok(value)) => value,
+                        Poll::Ready(Err(e)) => {
+                            *this = AndThen::Done;
+                            return Poll::Ready(Err(e));
+                        }
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    // The first `Future` is dropped before the second one is
+                    // created, which allows the second one to reuse its storage
+                    let f = match core::mem::replace(this, AndThen::Done) {
+                        AndThen::First(_, f) => f,
+                        _ => unreachable!(),
+                    };
+                    *this = AndThen::Second(f(value));
+                }
+                AndThen::Second(fut) => {
+                    let output = match fut.poll_unpin(cx) {
+                        Poll::Ready(output) => output,
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    *this = AndThen::Done;
+                    return Poll::Ready(output);
+                }
+                AndThen::Done => panic!("AndThen polled after completion"),
+            }
+        }
+    }
+}
+
+/// Returns a `DynamicFuture` which polls `fut`, and afterwards the future which
+/// is returned by `f` for the successful output of `fut`.
+///
+/// If `fut` fails, its error is returned without calling `f`. Otherwise `fut`
+/// is dropped before `f` is called. If the returned `Future` is dropped before
+/// it completed, the `Future` which is in flight is dropped with it.
+pub fn dynamic_and_then<'a, T: 'a, U: 'a, E: 'a, F>(
+    fut: DynamicFuture<'a, Result<T, E>>,
+    f: F,
+) -> DynamicFuture<'a, Result<U, E>>
+where
+    F: FnOnce(T) -> DynamicFuture<'a, Result<U, E>> + 'a,
+{
+    box_future(AndThen::First(fut, f))
+}
+
+/// Stops polling a `DynamicFuture` once it completed
+pub(crate) struct Fuse<'a, T> {
+    /// The pending `Future`. This is `None` once it completed.
+    pub(crate) inner: Option<DynamicFuture<'a, T>>,
+}
+
+impl<'a, T> Future for Fuse<'a, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let this = self.get_mut();
+        let fut = match this.inner.as_mut() {
+            Some(fut) => fut,
+            None => return Poll::Pending,
+        };
+        let output = match fut.poll_unpin(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.inner = None;
+        Poll::Ready(output)
+    }
+}
+
+/// Calls a closure if a `DynamicFuture` is dropped before it completed
+pub(crate) struct OnCancel<'a, T, F: FnOnce()> {
+    /// The pending `Future`. This is `None` once it completed.
+    pub(crate) fut: Option<DynamicFuture<'a, T>>,
+    pub(crate) on_cancel: Option<F>,
+}
+
+// The closure is never pinned, and `DynamicFuture` is `Unpin`
+impl<'a, T, F: FnOnce()> Unpin for OnCancel<'a, T, F> {}
+
+impl<'a, T, F: FnOnce()> Future for OnCancel<'a, T, F> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let this = self.get_mut();
+        let fut = this.fut.as_mut().expect("OnCancel polled after completion");
+        let output = match fut.poll_unpin(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.fut = None;
+        this.on_cancel = None;
+        Poll::Ready(output)
+    }
+}
+
+impl<'a, T, F: FnOnce()> Drop for OnCancel<'a, T, F> {
+    fn drop(&mut self) {
+        if let Some(fut) = self.fut.take() {
+            // The `Future` is dropped before the closure is called
+            drop(fut);
+            if let Some(on_cancel) = self.on_cancel.take() {
+                on_cancel();
+            }
+        }
+    }
+}
+
+/// Stores the output of a `DynamicFuture` once it completed, until it is taken.
+///
+/// The wrapper is created via [`DynamicFuture::cache_output`]. Polling it
+/// polls the `Future` until it completed, and resolves to `()` once the output
+/// is available. The output can then be taken exactly once via
+/// [`CachedOutput::take_output`]. The `Future` is dropped as soon as it
+/// completed, and is never polled again afterwards.
+pub struct CachedOutput<'a, T> {
+    state: MaybeDone<'a, T>,
+}
+
+// The output is never pinned, and `DynamicFuture` is `Unpin`
+impl<'a, T> Unpin for CachedOutput<'a, T> {}
+
+impl<'a, T> CachedOutput<'a, T> {
+    pub(crate) fn new(fut: DynamicFuture<'a, T>) -> Self {
+        Self {
+            state: MaybeDone::Pending(fut),
+        }
+    }
+
+    /// Returns whether the `Future` completed and its output was not yet taken
+    pub fn is_ready(&self) -> bool {
+        matches!(self.state, MaybeDone::Done(_))
+    }
+
+    /// Takes the output of the `Future`.
+    ///
+    /// Returns `None` if the `Future` has not yet completed, or if the output
+    /// had already been taken.
+    pub fn take_output(&mut self) -> Option<T> {
+        match self.state {
+            MaybeDone::Done(_) => Some(self.state.take()),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, T> Future for CachedOutput<'a, T> {
+    type Output = ();
+
+    /// Polls the `Future` if it has not yet completed.
+    ///
+    /// Resolves once the output is available. After the output had been taken
+    /// this still resolves, without polling the `Future` again.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        if let MaybeDone::Taken = this.state {
+            return Poll::Ready(());
+        }
+        if this.state.poll(cx) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Counts how often a `Future` which was created via
+/// [`DynamicFuture::counted`] was polled.
+///
+/// The counter is shared with the `Future`, and can still be read after the
+/// `Future` was dropped.
+#[derive(Debug, Clone, Default)]
+pub struct PollCounter(Arc<AtomicUsize>);
+
+impl PollCounter {
+    /// Returns how often the `Future` was polled so far
+    pub fn polls(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts the polls of a `DynamicFuture`
+pub(crate) struct Counted<'a, T> {
+    pub(crate) fut: DynamicFuture<'a, T>,
+    pub(crate) counter: PollCounter,
+}
+
+impl<'a, T> Future for Counted<'a, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let this = self.get_mut();
+        this.counter.0.fetch_add(1, Ordering::Relaxed);
+        this.fut.poll_unpin(cx)
+    }
+}
+
+/// The `Future` which is observed by a `DynamicFutureWeak` is still alive
+const OBSERVED_ALIVE: u8 = 0;
+/// The observed `Future` completed
+const OBSERVED_COMPLETED: u8 = 1;
+/// The observed `Future` was dropped before it completed
+const OBSERVED_CANCELLED: u8 = 2;
+
+/// Observes the state of a `Future` which was created via
+/// [`DynamicFuture::observed`], without keeping it alive.
+///
+/// The handle can neither poll nor drop the `Future`. It only shares the state
+/// of the `Future` - which is updated once the `Future` completes or is
+/// dropped - and can therefore still be queried after the `Future` and its
+/// storage are gone.
+#[derive(Debug, Clone)]
+pub struct DynamicFutureWeak(Arc<AtomicU8>);
+
+impl DynamicFutureWeak {
+    /// Creates the state of a `Future` which is alive
+    // With `--cfg async_trait_experiments_single_threaded` the state is not
+    // atomic, which restricts the handle to the thread of the `Future`
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub(crate) fn new() -> Self {
+        Self(Arc::new(AtomicU8::new(OBSERVED_ALIVE)))
+    }
+
+    /// Returns whether the `Future` had neither completed nor been dropped yet
+    pub fn is_alive(&self) -> bool {
+        self.0.load(Ordering::Acquire) == OBSERVED_ALIVE
+    }
+
+    /// Returns whether the `Future` completed
+    pub fn is_completed(&self) -> bool {
+        self.0.load(Ordering::Acquire) == OBSERVED_COMPLETED
+    }
+
+    /// Returns whether the `Future` was dropped before it completed
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire) == OBSERVED_CANCELLED
+    }
+}
+
+/// Publishes the state of a `DynamicFuture` to a `DynamicFutureWeak`
+pub(crate) struct Observed<'a, T> {
+    pub(crate) fut: DynamicFuture<'a, T>,
+    pub(crate) state: DynamicFutureWeak,
+}
+
+impl<'a, T> Future for Observed<'a, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let this = self.get_mut();
+        let output = match this.fut.poll_unpin(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.state.0.store(OBSERVED_COMPLETED, Ordering::Release);
+        Poll::Ready(output)
+    }
+}
+
+impl<'a, T> Drop for Observed<'a, T> {
+    fn drop(&mut self) {
+        // Only a `Future` which did not complete is cancelled
+        let _ = self.state.0.compare_exchange(
+            OBSERVED_ALIVE,
+            OBSERVED_CANCELLED,
+            Ordering::Release,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// Catches panics which occur while a `DynamicFuture` is polled
+#[cfg(feature = "std")]
+pub(crate) struct CatchUnwind<'a, T> {
+    /// The pending `Future`. This is `None` once it completed or panicked.
+    pub(crate) fut: Option<DynamicFuture<'a, T>>,
+}
+
+#[cfg(feature = "std")]
+impl<'a, T> Future for CatchUnwind<'a, T> {
+    type Output = std::thread::Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let this = self.get_mut();
+        let fut = this
+            .fut
+            .as_mut()
+            .expect("CatchUnwind polled after completion");
+        let output = match catch_unwind(AssertUnwindSafe(|| fut.poll_unpin(cx))) {
+            Ok(Poll::Ready(output)) => Ok(output),
+            Ok(Poll::Pending) => return Poll::Pending,
+            Err(payload) => Err(payload),
+        };
+
+        // The `Future` is never polled again. If its `poll` panicked, a panic
+        // of its destructor is caught as well, in order to not unwind into
+        // the caller.
+        let fut = this.fut.take();
+        if output.is_err() {
+            let _ = catch_unwind(AssertUnwindSafe(move || drop(fut)));
+        }
+        Poll::Ready(output)
+    }
+}
+
+struct PollFn<F> {
+    f: F,
+}
+
+// The closure is never pinned
+impl<F> Unpin for PollFn<F> {}
+
+impl<T, F: FnMut(&mut Context<'_>) -> Poll<T>> Future for PollFn<F> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        (self.get_mut().f)(cx)
+    }
+}
+
+/// Returns a `DynamicFuture` whose `poll` is implemented by the closure `f`.
+///
+/// This works like `core::future::poll_fn`, but the closure is stored on the
+/// heap and the returned `Future` is type erased. Like for any other `Future`,
+/// polling the returned `Future` after it completed is a logic error. What
+/// happens in this case is up to the closure, which will be called again.
+pub fn dynamic_poll_fn<'a, T, F>(f: F) -> DynamicFuture<'a, T>
+where
+    F: FnMut(&mut Context<'_>) -> Poll<T> + 'a,
+{
+    box_future(PollFn { f })
+}
+
+/// Combinators for `DynamicFuture`s, which return `DynamicFuture`s themselves.
+///
+/// The methods delegate to the free functions of this crate - e.g.
+/// [`DynamicFutureExt::map`] to [`dynamic_map`]. The trait is implemented for
+/// `DynamicFuture` and `SendDynamicFuture`. It is not object safe, since all
+/// combinators consume the `Future`.
+///
+/// Since `futures::FutureExt` is implemented for all futures and provides
+/// methods of the same name, the method which should be called needs to be
+/// disambiguated if both traits are imported.
+pub trait DynamicFutureExt<'a, T: 'a>: Into<DynamicFuture<'a, T>> {
+    /// Transforms the output of this `Future` with `f`.
+    ///
+    /// See [`dynamic_map`].
+    fn map<U, F>(self, f: F) -> DynamicFuture<'a, U>
+    where
+        F: FnOnce(T) -> U + 'a,
+    {
+        dynamic_map(self.into(), f)
+    }
+
+    /// Calls `f` with a reference to the output of this `Future` once it
+    /// completed.
+    ///
+    /// See [`dynamic_inspect`].
+    fn inspect<F>(self, f: F) -> DynamicFuture<'a, T>
+    where
+        F: FnOnce(&T) + 'a,
+    {
+        dynamic_inspect(self.into(), f)
+    }
+
+    /// Polls this `Future` and `other` until both completed.
+    ///
+    /// See [`dynamic_join`].
+    fn join<B: 'a>(self, other: DynamicFuture<'a, B>) -> DynamicFuture<'a, (T, B)> {
+        dynamic_join(self.into(), other)
+    }
+
+    /// Polls this `Future` and `other` until either of them completed.
+    ///
+    /// See [`dynamic_select`].
+    fn select<B: 'a>(self, other: DynamicFuture<'a, B>) -> DynamicFuture<'a, Either<T, B>> {
+        dynamic_select(self.into(), other)
+    }
+
+    /// Polls this `Future` until it completed or `deadline` fired.
+    ///
+    /// See [`dynamic_timeout`].
+    fn timeout<D: 'a>(self, deadline: DynamicFuture<'a, D>) -> DynamicFuture<'a, Option<T>> {
+        dynamic_timeout(self.into(), deadline)
+    }
+
+    /// Converts this `Future` into a `Pin<Box<dyn Future>>`.
+    ///
+    /// See [`DynamicFuture::into_pin_box`].
+    fn boxed(self) -> Pin<Box<dyn Future<Output = T> + 'a>> {
+        self.into().into_pin_box()
+    }
+}
+
+impl<'a, T: 'a> DynamicFutureExt<'a, T> for DynamicFuture<'a, T> {}
+
+impl<'a, T: 'a> DynamicFutureExt<'a, T> for SendDynamicFuture<'a, T> {}
+
+/// Combinators for `Future`s whose output is a `Result`.
+///
+/// The methods delegate to the free functions of this crate - e.g.
+/// [`TryDynamicFutureExt::map_ok`] to [`dynamic_map_ok`]. The trait is
+/// implemented for `DynamicFuture` and `SendDynamicFuture`.
+///
+/// Since `futures::TryFutureExt` provides methods of the same name, the method
+/// which should be called needs to be disambiguated if both traits are
+/// imported.
+pub trait TryDynamicFutureExt<'a, T: 'a, E: 'a>: Into<DynamicFuture<'a, Result<T, E>>> {
+    /// Transforms the value of a successful output of this `Future` with `f`.
+    ///
+    /// See [`dynamic_map_ok`].
+    fn map_ok<U, F>(self, f: F) -> DynamicFuture<'a, Result<U, E>>
+    where
+        F: FnOnce(T) -> U + 'a,
+    {
+        dynamic_map_ok(self.into(), f)
+    }
+
+    /// Transforms the error of a failed output of this `Future` with `f`.
+    ///
+    /// See [`dynamic_map_err`].
+    fn map_err<G, F>(self, f: F) -> DynamicFuture<'a, Result<T, G>>
+    where
+        F: FnOnce(E) -> G + 'a,
+    {
+        dynamic_map_err(self.into(), f)
+    }
+
+    /// Polls the `Future` which is returned by `f` for the successful output of
+    /// this `Future`.
+    ///
+    /// See [`dynamic_and_then`].
+    fn and_then<U: 'a, F>(self, f: F) -> DynamicFuture<'a, Result<U, E>>
+    where
+        F: FnOnce(T) -> DynamicFuture<'a, Result<U, E>> + 'a,
+    {
+        dynamic_and_then(self.into(), f)
+    }
+}
+
+impl<'a, T: 'a, E: 'a> TryDynamicFutureExt<'a, T, E> for DynamicFuture<'a, Result<T, E>> {}
+
+impl<'a, T: 'a, E: 'a> TryDynamicFutureExt<'a, T, E> for SendDynamicFuture<'a, Result<T, E>> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{counted_value, poll_once, yield_now, DropCounter};
+    use core::cell::Cell;
+
+    #[test]
+    fn join_resolves_to_both_outputs() {
+        let ready = box_future(async { 1u32 });
+        let yielding = box_future(async {
+            yield_now().await;
+            "two"
+        });
+        let mut joined = dynamic_join(ready, yielding);
+        assert!(poll_once(&mut joined).is_pending());
+        assert_eq!(Poll::Ready((1, "two")), poll_once(&mut joined));
+    }
+
+    #[test]
+    fn join_drops_the_completed_output_and_the_pending_future() {
+        let drops = Cell::new(0);
+        let output = DropCounter(&drops);
+        let pending = DropCounter(&drops);
+        let ready = box_future(async move { output });
+        let yielding = box_future(async move {
+            let _pending = pending;
+            yield_now().await;
+        });
+        let mut joined = dynamic_join(ready, yielding);
+        assert!(poll_once(&mut joined).is_pending());
+        assert_eq!(0, drops.get());
+        drop(joined);
+        assert_eq!(2, drops.get());
+    }
+
+    #[test]
+    fn select_resolves_to_the_winner_and_drops_the_loser() {
+        let drops = Cell::new(0);
+        let loser = DropCounter(&drops);
+        let ready = box_future(async { 1u32 });
+        let yielding = box_future(async move {
+            let _loser = loser;
+            yield_now().await;
+            "two"
+        });
+        let mut selected = dynamic_select(yielding, ready);
+        assert_eq!(Poll::Ready(Either::Right(1)), poll_once(&mut selected));
+        assert_eq!(1, drops.get());
+        drop(selected);
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn select_prefers_the_first_future() {
+        let mut selected = dynamic_select(box_future(async { 1u32 }), box_future(async { 2u8 }));
+        assert_eq!(Poll::Ready(Either::Left(1)), poll_once(&mut selected));
+    }
+
+    #[test]
+    fn timeout_resolves_to_none_once_the_deadline_fires() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let work = box_future(async move {
+            let _counter = counter;
+            yield_now().await;
+            1u32
+        });
+        let mut fut = dynamic_timeout(work, box_future(async {}));
+        assert_eq!(Poll::Ready(None), poll_once(&mut fut));
+        assert_eq!(1, drops.get());
+        drop(fut);
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn timeout_resolves_to_the_output_if_the_future_is_faster() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let deadline = box_future(async move {
+            let _counter = counter;
+            yield_now().await;
+            yield_now().await;
+        });
+        let work = box_future(async {
+            yield_now().await;
+            1u32
+        });
+        let mut fut = work.timeout(deadline);
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(0, drops.get());
+        assert_eq!(Poll::Ready(Some(1)), poll_once(&mut fut));
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn either_polls_the_stored_future() {
+        let data = [3u8; 16];
+        let mut left = core::pin::pin!(Either::<_, futures::future::Ready<u8>>::Left(async move {
+            yield_now().await;
+            data[0]
+        }));
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        assert!(left.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(Poll::Ready(3), left.as_mut().poll(&mut cx));
+
+        let mut right: Either<futures::future::Ready<u8>, _> =
+            Either::Right(futures::future::ready(4));
+        assert_eq!(Poll::Ready(4), Pin::new(&mut right).poll(&mut cx));
+    }
+
+    #[test]
+    fn inspect_observes_the_output_once() {
+        let calls = Cell::new(0);
+        let fut = box_future(async {
+            yield_now().await;
+            7u32
+        });
+        let mut inspected = DynamicFuture::inspect(fut, |output| {
+            assert_eq!(7, *output);
+            calls.set(calls.get() + 1);
+        });
+        assert!(poll_once(&mut inspected).is_pending());
+        assert_eq!(0, calls.get());
+        assert_eq!(Poll::Ready(7), poll_once(&mut inspected));
+        assert_eq!(1, calls.get());
+    }
+
+    #[test]
+    fn inspect_which_is_dropped_early_does_not_call_the_closure() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let calls = Cell::new(0);
+        let fut = box_future(async move {
+            let _counter = counter;
+            yield_now().await;
+        });
+        let mut inspected = dynamic_inspect(fut, |_| calls.set(calls.get() + 1));
+        assert!(poll_once(&mut inspected).is_pending());
+        drop(inspected);
+        assert_eq!(1, drops.get());
+        assert_eq!(0, calls.get());
+    }
+
+    #[test]
+    fn ext_methods_can_be_chained() {
+        let seen = Cell::new(0);
+        let fut = box_future(async {
+            yield_now().await;
+            20u32
+        })
+        .map(|value| value + 1)
+        .inspect(|value| seen.set(*value))
+        .join(box_future(async { "other" }));
+        assert_eq!((21, "other"), futures::executor::block_on(fut.boxed()));
+        assert_eq!(21, seen.get());
+    }
+
+    #[test]
+    fn poll_fn_calls_the_closure_on_every_poll() {
+        let calls = Cell::new(0);
+        let mut fut = dynamic_poll_fn(|cx| {
+            calls.set(calls.get() + 1);
+            if calls.get() == 1 {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            } else {
+                Poll::Ready(42)
+            }
+        });
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready(42), poll_once(&mut fut));
+        assert_eq!(2, calls.get());
+    }
+
+    #[test]
+    fn poll_fn_drops_the_closure_with_the_future() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let fut = dynamic_poll_fn(move |_| {
+            let _counter = &counter;
+            Poll::Ready(())
+        });
+        assert_eq!(0, drops.get());
+        drop(fut);
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn result_combinators_transform_the_output() {
+        let fut = box_future(async { Ok::<u32, u8>(2) }).map_ok(|value| value * 3);
+        assert_eq!(Poll::Ready(Ok(6)), poll_once(&mut { fut }));
+
+        let fut = box_future(async { Err::<u32, u8>(2) })
+            .map_ok(|value| value * 3)
+            .map_err(|error| error as u32 + 10);
+        assert_eq!(Poll::Ready(Err(12)), poll_once(&mut { fut }));
+    }
+
+    #[test]
+    fn and_then_chains_successful_outputs() {
+        let mut fut = box_future(async {
+            yield_now().await;
+            Ok::<u32, &str>(2)
+        })
+        .and_then(|value| {
+            box_future(async move {
+                yield_now().await;
+                Ok(value + 1)
+            })
+        });
+        assert!(poll_once(&mut fut).is_pending());
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready(Ok(3)), poll_once(&mut fut));
+
+        let called = Cell::new(false);
+        let mut fut = box_future(async { Err::<u32, &str>("failed") }).and_then(|value| {
+            called.set(true);
+            box_future(async move { Ok(value) })
+        });
+        assert_eq!(Poll::Ready(Err("failed")), poll_once(&mut fut));
+        assert!(!called.get());
+    }
+
+    #[test]
+    fn and_then_drops_the_future_in_flight() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let mut fut = box_future(async move {
+            let _counter = counter;
+            yield_now().await;
+            Ok::<u32, ()>(1)
+        })
+        .and_then(|value| box_future(async move { Ok(value) }));
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(0, drops.get());
+        drop(fut);
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn and_then_allows_to_recycle_the_storage_of_the_first_future() {
+        async fn step(value: u32) -> Result<u32, ()> {
+            yield_now().await;
+            Ok(value + 1)
+        }
+
+        let mut recycler = crate::RecyclableFutureAllocator::new();
+        let first = recycler.allocate(step(0));
+        let storage = first.ptr();
+        let second_storage = Cell::new(core::ptr::null());
+        let fut = first.and_then(|value| {
+            // The first future was dropped before the closure is called
+            let (second, reused) = recycler.allocate_tracked(step(value));
+            assert_eq!(crate::Reused::Recycled, reused);
+            second_storage.set(second.ptr());
+            second
+        });
+        assert_eq!(Ok(2), futures::executor::block_on(fut));
+        assert_eq!(storage, second_storage.get());
+    }
+
+    #[test]
+    fn cached_output_is_taken_once() {
+        let drops = Cell::new(0);
+        let mut recycler = crate::RecyclableFutureAllocator::new();
+        let mut fut = recycler
+            .allocate(counted_value(DropCounter(&drops), 1))
+            .cache_output();
+        assert!(poll_once(&mut fut).is_pending());
+        assert!(!fut.is_ready());
+        assert_eq!(None, fut.take_output());
+
+        assert_eq!(Poll::Ready(()), poll_once(&mut fut));
+        assert!(fut.is_ready());
+        // The future and its storage were released once it completed
+        assert_eq!(1, drops.get());
+        let (other, reused) = recycler.allocate_tracked(counted_value(DropCounter(&drops), 2));
+        assert_eq!(crate::Reused::Recycled, reused);
+        drop(other);
+
+        assert_eq!(Some(1), fut.take_output());
+        assert!(!fut.is_ready());
+        assert_eq!(None, fut.take_output());
+        assert_eq!(Poll::Ready(()), poll_once(&mut fut));
+        assert_eq!(None, fut.take_output());
+    }
+
+    #[test]
+    fn cached_output_does_not_poll_the_completed_future_again() {
+        let (fut, polls) = crate::box_future(async {
+            yield_now().await;
+            "done"
+        })
+        .counted();
+        let mut fut = fut.cache_output();
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready(()), poll_once(&mut fut));
+        assert_eq!(Poll::Ready(()), poll_once(&mut fut));
+        assert_eq!(Some("done"), fut.take_output());
+        assert_eq!(Poll::Ready(()), poll_once(&mut fut));
+        assert_eq!(2, polls.polls());
+    }
+}
@@ -0,0 +1,388 @@
+use crate::sync::{fence, AtomicU8, Ordering};
+use crate::{
+    box_future,
+    boxed_future::zero_sized_future,
+    storage::{poll_stored_future, stored_payload_size, PayloadStorage},
+    DynamicFuture, DynamicFutureVtable,
+};
+use alloc::alloc::Layout;
+use core::{cell::Cell, future::Future, marker::PhantomData};
+
+/// An allocator for `DynamicFuture`s which reuses storage like a
+/// `RecyclableFutureAllocator`, but defers running the destructors of the
+/// futures.
+///
+/// Dropping a future which was returned by this allocator only marks its
+/// storage as requiring a drop. The destructor of the future runs at the start
+/// of the next call to [`DeferredDropRecycler::allocate`] - before the storage
+/// is reused - or once the allocator is dropped. This moves the cost of
+/// expensive destructors out of the code which drops the future. As for
+/// `RecyclableFutureAllocator` with the default settings, the storage is only
+/// reused for futures of the same layout, and futures which can not use it are
+/// boxed.
+///
+/// If the allocator is dropped before the future, the destructor of the future
+/// runs immediately when the future is dropped.
+///
+/// The futures might borrow data for `'a`. The allocator can not outlive this
+/// lifetime, which guarantees that the destructors which it runs can still
+/// access the data. The allocator is not `Send`, since it might run the
+/// destructors of futures which are not `Send`.
+pub struct DeferredDropRecycler<'a> {
+    recycled: *const DeferredDropHeader,
+    _phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> Drop for DeferredDropRecycler<'a> {
+    fn drop(&mut self) {
+        if !self.recycled.is_null() {
+            unsafe {
+                DeferredDropHeader::release(self.recycled);
+            }
+        }
+    }
+}
+
+impl<'a> Default for DeferredDropRecycler<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> DeferredDropRecycler<'a> {
+    /// Creates a new allocator, which does not retain any storage yet
+    pub fn new() -> Self {
+        Self {
+            recycled: core::ptr::null(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Transforms the passed future into a `DynamicFuture`.
+    ///
+    /// If the future which was stored last in the storage of the allocator had
+    /// been dropped, its destructor runs first. Afterwards the storage is
+    /// reused for the new future if the layouts match.
+    pub fn allocate<F, T>(&mut self, fut: F) -> DynamicFuture<'a, T>
+    where
+        F: Future<Output = T> + 'a,
+    {
+        let layout = Layout::new::<F>();
+        if layout.size() == 0 {
+            self.run_pending_drop();
+            // Zero sized futures don't require any storage
+            return zero_sized_future(fut);
+        }
+
+        unsafe {
+            if self.recycled.is_null() {
+                match DeferredDropHeader::allocate(layout) {
+                    Ok(header) => self.recycled = header,
+                    Err(()) => return box_future(fut),
+                }
+            } else if !DeferredDropHeader::try_acquire(self.recycled) {
+                // The storage is still in use by a previous future
+                return box_future(fut);
+            } else {
+                // Give the storage back if the pending destructor panics
+                let guard = RelinquishGuard(self.recycled);
+                DeferredDropHeader::drop_pending_payload(self.recycled);
+                if (*self.recycled).data_layout != layout {
+                    // The storage can not be used for this future
+                    return box_future(fut);
+                }
+                core::mem::forget(guard);
+            }
+
+            core::ptr::write(
+                <DeferredDropHeader as PayloadStorage<F>>::payload(self.recycled as *const ()),
+                fut,
+            );
+            DynamicFuture::new(
+                self.recycled as *const (),
+                deferred_drop_future_vtable::<F, T>(),
+            )
+        }
+    }
+
+    /// Runs the destructor of the future which was stored last in the storage of
+    /// the allocator, if that future had been dropped and its destructor did not
+    /// run yet.
+    ///
+    /// Returns whether a destructor ran.
+    pub fn run_pending_drop(&mut self) -> bool {
+        if self.recycled.is_null() {
+            return false;
+        }
+        unsafe {
+            if !DeferredDropHeader::try_acquire(self.recycled) {
+                return false;
+            }
+            let _guard = RelinquishGuard(self.recycled);
+            DeferredDropHeader::drop_pending_payload(self.recycled)
+        }
+    }
+}
+
+/// A header stored in front of the futures of a `DeferredDropRecycler`
+struct DeferredDropHeader {
+    /// The amount of active references to the storage. This follows the same
+    /// protocol as the refcount of `RecyclableFutureAllocator`: The storage is
+    /// free for a new payload if only the allocator references it.
+    refcount: AtomicU8,
+    /// The layout of the payloads of the storage
+    data_layout: Layout,
+    /// Drops the payload whose destructor was deferred.
+    ///
+    /// This is only accessed by the holder of the payload, or by the holder
+    /// of the last reference after the payload released its reference.
+    pending_drop: Cell<Option<unsafe fn(*const ())>>,
+}
+
+/// Frees the storage which is referenced by the header once it is dropped
+struct DeallocGuard(*const DeferredDropHeader);
+
+impl Drop for DeallocGuard {
+    fn drop(&mut self) {
+        unsafe {
+            // The layout had been validated when the storage was allocated
+            let layout = DeferredDropHeader::layout_for((*self.0).data_layout)
+                .unwrap_or_else(|()| unreachable!());
+            alloc::alloc::dealloc(self.0 as *mut u8, layout);
+        }
+    }
+}
+
+/// Gives up the exclusive access to the storage which is referenced by the
+/// header once it is dropped
+struct RelinquishGuard(*const DeferredDropHeader);
+
+impl Drop for RelinquishGuard {
+    fn drop(&mut self) {
+        unsafe { DeferredDropHeader::relinquish(self.0) }
+    }
+}
+
+impl DeferredDropHeader {
+    /// Allocates storage for payloads of `data_layout`.
+    ///
+    /// The storage holds a reference for the allocator and one for the payload
+    /// which is about to be stored.
+    unsafe fn allocate(data_layout: Layout) -> Result<*const DeferredDropHeader, ()> {
+        let layout = Self::layout_for(data_layout)?;
+        let header = alloc::alloc::alloc(layout) as *mut DeferredDropHeader;
+        if header.is_null() {
+            return Err(());
+        }
+
+        core::ptr::write(
+            header,
+            DeferredDropHeader {
+                refcount: AtomicU8::new(2),
+                data_layout,
+                pending_drop: Cell::new(None),
+            },
+        );
+        Ok(header)
+    }
+
+    /// Returns the layout of storage which holds the header followed by a
+    /// payload of `data_layout`
+    fn layout_for(data_layout: Layout) -> Result<Layout, ()> {
+        let (layout, _) = Layout::new::<DeferredDropHeader>()
+            .extend(data_layout)
+            .map_err(|_| ())?;
+        Ok(layout.pad_to_align())
+    }
+
+    /// Tries to acquire the storage for exclusive access by the allocator.
+    ///
+    /// The caller must hold a reference on the storage.
+    unsafe fn try_acquire(header: *const DeferredDropHeader) -> bool {
+        (*header)
+            .refcount
+            .compare_exchange(1, 2, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Gives up the exclusive access to the storage which had been acquired
+    /// via `try_acquire`, without storing a payload
+    unsafe fn relinquish(header: *const DeferredDropHeader) {
+        (*header).refcount.store(1, Ordering::Relaxed);
+    }
+
+    /// Runs the destructor of the payload whose drop was deferred, if any.
+    ///
+    /// The caller must have exclusive access to the storage.
+    unsafe fn drop_pending_payload(header: *const DeferredDropHeader) -> bool {
+        match (*header).pending_drop.take() {
+            Some(drop_fn) => {
+                drop_fn(header as *const ());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Releases a reference on the storage. If it was the last reference, the
+    /// pending payload is dropped and the storage is freed.
+    unsafe fn release(header: *const DeferredDropHeader) {
+        if (*header).refcount.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+
+        fence(Ordering::Acquire);
+        // Free the storage even if the destructor of the payload panics
+        let _guard = DeallocGuard(header);
+        Self::drop_pending_payload(header);
+    }
+}
+
+impl<P> PayloadStorage<P> for DeferredDropHeader {
+    unsafe fn payload(ptr: *const ()) -> *mut P {
+        // Payloads always have the layout of the storage
+        let align = core::mem::align_of::<P>();
+        let offset = (core::mem::size_of::<DeferredDropHeader>() + align - 1) & !(align - 1);
+        (ptr as *const u8).add(offset) as *mut P
+    }
+
+    unsafe fn release(ptr: *const ()) {
+        Self::release(ptr as *const Self);
+    }
+}
+
+/// Drops the payload of type `P` in the storage which is referenced by `ptr`
+unsafe fn drop_payload<P>(ptr: *const ()) {
+    core::ptr::drop_in_place(<DeferredDropHeader as PayloadStorage<P>>::payload(ptr));
+}
+
+/// Marks the payload of type `P` as requiring a drop, and releases the
+/// reference of the payload on the storage
+unsafe fn defer_drop<P>(ptr: *const ()) {
+    let header = ptr as *const DeferredDropHeader;
+    (*header).pending_drop.set(Some(drop_payload::<P>));
+    DeferredDropHeader::release(header);
+}
+
+fn deferred_drop_future_vtable<'a, F, T>() -> &'a DynamicFutureVtable<T>
+where
+    F: Future<Output = T> + 'a,
+{
+    &DynamicFutureVtable {
+        drop_fn: defer_drop::<F>,
+        poll_fn: poll_stored_future::<T, F, DeferredDropHeader>,
+        size_fn: Some(stored_payload_size::<F, DeferredDropHeader>),
+        type_id_fn: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{poll_once, yield_now, DropCounter, PanicOnDrop};
+
+    /// Returns a future which increments `drops` once it is dropped
+    fn counted(drops: &Cell<usize>) -> impl Future<Output = ()> + '_ {
+        let counter = DropCounter(drops);
+        async move {
+            let _counter = counter;
+            yield_now().await;
+        }
+    }
+
+    #[test]
+    fn destructors_run_on_the_next_allocation() {
+        let drops = Cell::new(0);
+        let mut recycler = DeferredDropRecycler::new();
+        let mut fut = recycler.allocate(counted(&drops));
+        let storage = fut.ptr();
+        assert!(poll_once(&mut fut).is_pending());
+        drop(fut);
+        assert_eq!(0, drops.get());
+
+        let fut = recycler.allocate(counted(&drops));
+        assert_eq!(1, drops.get());
+        assert_eq!(storage, fut.ptr());
+        drop(fut);
+
+        assert!(recycler.run_pending_drop());
+        assert_eq!(2, drops.get());
+        assert!(!recycler.run_pending_drop());
+    }
+
+    #[test]
+    fn pending_destructors_run_when_the_allocator_is_dropped() {
+        let drops = Cell::new(0);
+        let mut recycler = DeferredDropRecycler::new();
+        drop(recycler.allocate(counted(&drops)));
+        assert_eq!(0, drops.get());
+        drop(recycler);
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn destructors_run_immediately_once_the_allocator_is_gone() {
+        let drops = Cell::new(0);
+        let mut recycler = DeferredDropRecycler::new();
+        let fut = recycler.allocate(counted(&drops));
+        drop(recycler);
+        assert_eq!(0, drops.get());
+        drop(fut);
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn futures_which_can_not_use_the_storage_are_boxed() {
+        let drops = Cell::new(0);
+        let mut recycler = DeferredDropRecycler::new();
+        let busy = recycler.allocate(counted(&drops));
+        let fut = recycler.allocate(counted(&drops));
+        assert_ne!(busy.ptr(), fut.ptr());
+        drop(fut);
+        // Boxed futures are dropped immediately
+        assert_eq!(1, drops.get());
+        let storage = busy.ptr();
+        drop(busy);
+
+        // The destructor of the previous future runs before the layout of the
+        // storage is checked
+        let data = [0u8; 64];
+        let fut = recycler.allocate(async move { data[0] });
+        assert_eq!(2, drops.get());
+        assert_ne!(storage, fut.ptr());
+    }
+
+    /// Returns a future which panics once it is dropped after it was polled,
+    /// if `panic` is set
+    async fn maybe_panicking(panic: bool) {
+        let _panic = panic.then_some(PanicOnDrop);
+        yield_now().await;
+    }
+
+    #[test]
+    fn storage_is_reused_after_a_deferred_destructor_panicked() {
+        let mut recycler = DeferredDropRecycler::new();
+        let mut fut = recycler.allocate(maybe_panicking(true));
+        assert!(poll_once(&mut fut).is_pending());
+        let storage = fut.ptr();
+        drop(fut);
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            recycler.run_pending_drop();
+        }));
+        assert!(res.is_err());
+        assert!(!recycler.run_pending_drop());
+
+        let mut fut = recycler.allocate(maybe_panicking(true));
+        assert_eq!(storage, fut.ptr());
+        assert!(poll_once(&mut fut).is_pending());
+        drop(fut);
+        // The destructor also runs - and panics - within the next allocation
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            drop(recycler.allocate(maybe_panicking(false)));
+        }));
+        assert!(res.is_err());
+
+        let fut = recycler.allocate(maybe_panicking(false));
+        assert_eq!(storage, fut.ptr());
+    }
+}
@@ -0,0 +1,408 @@
+//! Limits how deeply type-erased futures and streams poll each other
+//! synchronously on a thread.
+
+use crate::{box_future, DynamicFuture};
+#[cfg(feature = "futures")]
+use crate::{box_stream, DynamicStream};
+use alloc::vec::Vec;
+use core::{
+    cell::{Cell, RefCell},
+    future::Future,
+    marker::PhantomPinned,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+/// A depth limited future or stream whose inner future or stream needs to be
+/// polled once the stack unwound.
+///
+/// The function polls the inner object of the `DepthLimited` the pointer
+/// refers to, and returns whether it produced a result. The `Waker` is the one
+/// the `DepthLimited` was polled with when it reached the limit.
+type Parked = (*mut (), unsafe fn(*mut (), &mut Context<'_>) -> bool, Waker);
+
+std::thread_local! {
+    /// The amount of depth limited futures and streams which are currently
+    /// polled on this thread
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// The depth limited futures and streams which reached the limit during
+    /// the current outermost depth limited poll
+    static PARKED: RefCell<Vec<Parked>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Tracks that a depth limited future or stream is polled on this thread
+struct DepthGuard;
+
+impl DepthGuard {
+    /// Enters a depth limited poll at `depth`
+    fn enter(depth: usize) -> DepthGuard {
+        DEPTH.with(|current| current.set(depth + 1));
+        DepthGuard
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// The future or stream which is wrapped by a `DepthLimited`
+trait Inner {
+    type Output;
+
+    fn poll_inner(&mut self, cx: &mut Context<'_>) -> Poll<Self::Output>;
+
+    /// Returns whether the object completed with `output`, and will not be
+    /// polled again
+    fn is_done(output: &Self::Output) -> bool;
+
+    /// Returns the result of polling the object after it completed
+    fn poll_done() -> Poll<Self::Output>;
+}
+
+impl<'a, T> Inner for DynamicFuture<'a, T> {
+    type Output = T;
+
+    fn poll_inner(&mut self, cx: &mut Context<'_>) -> Poll<T> {
+        self.poll_unpin(cx)
+    }
+
+    fn is_done(_output: &T) -> bool {
+        true
+    }
+
+    fn poll_done() -> Poll<T> {
+        panic!("depth limited future polled after completion")
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<'a, T> Inner for DynamicStream<'a, T> {
+    type Output = Option<T>;
+
+    fn poll_inner(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        futures_core::Stream::poll_next(Pin::new(self), cx)
+    }
+
+    fn is_done(output: &Option<T>) -> bool {
+        output.is_none()
+    }
+
+    fn poll_done() -> Poll<Option<T>> {
+        Poll::Ready(None)
+    }
+}
+
+struct DepthLimited<I: Inner> {
+    /// The wrapped object. This is `None` once it completed.
+    inner: Option<I>,
+    max_depth: usize,
+    /// Whether this object is registered in `PARKED`
+    parked: bool,
+    /// The result of `inner` if it was produced after the object was parked,
+    /// and had not been returned yet
+    output: Option<I::Output>,
+    /// Parked objects are referenced by `PARKED`, and must not move
+    _pinned: PhantomPinned,
+}
+
+impl<I: Inner> DepthLimited<I> {
+    fn new(inner: I, max_depth: usize) -> Self {
+        Self {
+            inner: Some(inner),
+            max_depth,
+            parked: false,
+            output: None,
+            _pinned: PhantomPinned,
+        }
+    }
+
+    fn poll_limited(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<I::Output> {
+        // The object is not moved out of the `Pin`
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Some(output) = this.output.take() {
+            return Poll::Ready(output);
+        }
+        if this.parked {
+            // The inner object will be polled once the stack unwound
+            return Poll::Pending;
+        }
+
+        let depth = DEPTH.with(|depth| depth.get());
+        // The outermost depth limited poll always polls its inner object,
+        // since nothing else would poll it if it was parked
+        if depth != 0 && depth >= this.max_depth {
+            // Polling the inner object would exceed the limit. It is polled
+            // by the outermost depth limited poll instead, using the `Waker`
+            // of this poll - which might belong to an intermediate combinator.
+            let parked: Parked = (
+                this as *mut Self as *mut (),
+                poll_parked::<I>,
+                cx.waker().clone(),
+            );
+            PARKED.with(|parked_list| parked_list.borrow_mut().push(parked));
+            this.parked = true;
+            return Poll::Pending;
+        }
+
+        let _guard = DepthGuard::enter(depth);
+        let res = this.poll_inner(cx);
+        if depth == 0 {
+            poll_all_parked();
+        }
+        res
+    }
+
+    /// Polls the inner object, and drops it once it completed.
+    ///
+    /// Dropping a nested structure of objects recurses through all of them.
+    /// Releasing the objects once they completed avoids this for structures
+    /// which run to completion, since the objects on each level had already
+    /// been released once the level above is dropped.
+    fn poll_inner(&mut self, cx: &mut Context<'_>) -> Poll<I::Output> {
+        let inner = match &mut self.inner {
+            Some(inner) => inner,
+            None => return I::poll_done(),
+        };
+        let res = inner.poll_inner(cx);
+        if let Poll::Ready(output) = &res {
+            if I::is_done(output) {
+                self.inner = None;
+            }
+        }
+        res
+    }
+}
+
+impl<I: Inner> Drop for DepthLimited<I> {
+    fn drop(&mut self) {
+        if self.parked {
+            let ptr = self as *mut Self as *mut ();
+            let _ = PARKED.try_with(|parked| parked.borrow_mut().retain(|entry| entry.0 != ptr));
+        }
+    }
+}
+
+/// Polls the inner object of a parked `DepthLimited<I>`, and stores its result
+unsafe fn poll_parked<I: Inner>(ptr: *mut (), cx: &mut Context<'_>) -> bool {
+    let this = &mut *(ptr as *mut DepthLimited<I>);
+    this.parked = false;
+    match this.poll_inner(cx) {
+        Poll::Ready(output) => {
+            this.output = Some(output);
+            true
+        }
+        Poll::Pending => false,
+    }
+}
+
+/// Polls the inner objects of all depth limited futures and streams which
+/// reached the limit during the current outermost depth limited poll.
+///
+/// The objects are polled from the stack of the outermost poll, which limits
+/// the depth of the stack regardless of how deeply the objects are nested.
+/// Polling them might park further objects, which are polled afterwards.
+///
+/// Each object is polled with the `Waker` its depth limited future or stream
+/// had been polled with. Combinators between the outermost poll and the object
+/// which track wakeups per child - e.g. `FuturesUnordered` - therefore observe
+/// the wakeups of the object. If the object produced a result, this `Waker` is
+/// woken in order to pass the result on to the futures and streams which wrap
+/// the object.
+fn poll_all_parked() {
+    loop {
+        let parked = PARKED.with(|parked| parked.borrow_mut().pop());
+        let (ptr, poll_fn, waker) = match parked {
+            Some(parked) => parked,
+            None => break,
+        };
+        // A parked object stays in place until it is dropped, and removes
+        // itself from `PARKED` when it is dropped.
+        let mut cx = Context::from_waker(&waker);
+        if unsafe { poll_fn(ptr, &mut cx) } {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+impl<'a, T> Future for DepthLimited<DynamicFuture<'a, T>> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        self.poll_limited(cx)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<'a, T> futures_core::Stream for DepthLimited<DynamicStream<'a, T>> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.poll_limited(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match &self.inner {
+            Some(inner) => inner.size_hint(),
+            None => (0, Some(0)),
+        }
+    }
+}
+
+/// Returns a `DynamicFuture` which polls `fut`, unless `max_depth` depth
+/// limited futures and streams are already being polled on the current thread.
+///
+/// This bounds recursion which builds up while polling - e.g. deeply nested
+/// futures and streams which poll each other synchronously, or futures which
+/// synchronously poll freshly created futures that complete immediately and
+/// create the next one. Once the limit is reached, the returned future does
+/// not poll `fut` directly. Instead `fut` is polled by the outermost depth
+/// limited future or stream on the thread, after the stack of the futures in
+/// between unwound. `fut` is polled with the `Waker` the returned future was
+/// polled with. If `fut` produced its output this way, this `Waker` is woken,
+/// and the output is returned once the returned future is polled again.
+///
+/// Each nesting level of `max_depth` depth limited futures therefore adds
+/// another poll of the task before an output reaches the outermost future.
+///
+/// The outermost depth limited future or stream on a thread always polls its
+/// inner object directly. A `max_depth` of 0 therefore behaves like 1.
+///
+/// `fut` is dropped as soon as it completed. Dropping a deeply nested
+/// structure of futures before it completed still recurses through all of
+/// them.
+pub fn depth_limited<'a, T: 'a>(
+    fut: DynamicFuture<'a, T>,
+    max_depth: usize,
+) -> DynamicFuture<'a, T> {
+    box_future(DepthLimited::new(fut, max_depth))
+}
+
+/// Returns a `DynamicStream` which polls `stream`, unless `max_depth` depth
+/// limited futures and streams are already being polled on the current thread.
+///
+/// See [`depth_limited`] for how the stream is polled once the limit is
+/// reached. `stream` is dropped as soon as it ended.
+#[cfg(feature = "futures")]
+pub fn depth_limited_stream<'a, T: 'a>(
+    stream: DynamicStream<'a, T>,
+    max_depth: usize,
+) -> DynamicStream<'a, T> {
+    box_stream(DepthLimited::new(stream, max_depth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{poll_once, yield_now};
+    use futures::executor::block_on;
+
+    /// Returns a future which completes after `depth` nested depth limited
+    /// futures completed
+    fn nested_future(depth: usize, max_depth: usize) -> DynamicFuture<'static, usize> {
+        let mut fut = box_future(async {
+            yield_now().await;
+            0
+        });
+        for _ in 0..depth {
+            let inner = depth_limited(fut, max_depth);
+            fut = box_future(async move { inner.await + 1 });
+        }
+        fut
+    }
+
+    #[test]
+    fn futures_within_the_limit_are_polled_directly() {
+        let mut fut = depth_limited(nested_future(3, 16), 16);
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready(3), poll_once(&mut fut));
+    }
+
+    #[test]
+    fn futures_with_a_max_depth_of_zero_complete() {
+        // The outermost future has nobody to park itself with
+        let mut fut = depth_limited(box_future(async { 5 }), 0);
+        assert_eq!(Poll::Ready(5), poll_once(&mut fut));
+
+        assert_eq!(3, block_on(depth_limited(nested_future(3, 0), 0)));
+    }
+
+    #[test]
+    fn futures_nested_beyond_the_limit_complete() {
+        assert_eq!(100, block_on(nested_future(100, 8)));
+        assert_eq!(100, block_on(nested_future(100, 1)));
+    }
+
+    #[test]
+    fn parked_futures_which_are_dropped_are_not_polled() {
+        // The nested future reaches the limit and is parked, before it is
+        // dropped by the select since the other future completed
+        let selected = crate::dynamic_select(nested_future(10, 1), box_future(async { "ready" }));
+        let mut fut = depth_limited(selected, 1);
+        match poll_once(&mut fut) {
+            Poll::Ready(crate::Either::Right("ready")) => {}
+            _ => panic!("Expected the ready future to complete"),
+        }
+        PARKED.with(|parked| assert!(parked.borrow().is_empty()));
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn streams_keep_returning_none_after_they_ended() {
+        use futures::StreamExt;
+
+        let mut s = depth_limited_stream(box_stream(futures::stream::iter(0..2)), 4);
+        assert_eq!((2, Some(2)), s.size_hint());
+        assert_eq!(alloc::vec![0, 1], block_on((&mut s).collect::<Vec<_>>()));
+        assert_eq!((0, Some(0)), s.size_hint());
+        assert_eq!(None, block_on(s.next()));
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    // Polling 10,000 levels takes too long under miri
+    #[cfg_attr(miri, ignore)]
+    fn deeply_nested_streams_complete_without_overflowing_the_stack() {
+        use futures::{stream, StreamExt};
+
+        const DEPTH: usize = 10_000;
+
+        // Run on a thread with a known stack size, which is by far too small
+        // for polling all levels recursively
+        let items = std::thread::Builder::new()
+            .stack_size(1024 * 1024)
+            .spawn(|| {
+                let mut s: DynamicStream<'static, usize> = box_stream(stream::iter(0..3));
+                for _ in 0..DEPTH {
+                    // Each level wraps the stream below it, and polls it
+                    // synchronously
+                    let inner = depth_limited_stream(s, 32);
+                    s = box_stream(inner.map(|item| item + 1));
+                }
+                block_on(s.collect::<Vec<_>>())
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(alloc::vec![DEPTH, DEPTH + 1, DEPTH + 2], items);
+    }
+
+    #[test]
+    fn parked_futures_are_polled_with_the_waker_of_their_parent() {
+        use futures::stream::{FuturesUnordered, StreamExt};
+
+        // `FuturesUnordered` only polls children which woke the `Waker` it
+        // passed to them. The nested futures reach the limit within it, and
+        // need to wake this `Waker`.
+        let children: FuturesUnordered<_> = (0..3).map(|_| nested_future(10, 1)).collect();
+        let mut fut = depth_limited(box_future(children.collect::<Vec<_>>()), 1);
+        for _ in 0..1000 {
+            if let Poll::Ready(outputs) = poll_once(&mut fut) {
+                assert_eq!(alloc::vec![10, 10, 10], outputs);
+                return;
+            }
+        }
+        panic!("The nested futures did not complete");
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{
+    box_future,
+    boxed_future::zero_sized_future,
+    recycler::{
+        new_retained_recyclable_future, release_recycler_reference, reuse_recyclable_future,
+        RecyclableFutureHeader,
+    },
+    DynamicFuture, Global, Reused,
+};
+use alloc::alloc::Layout;
+use core::future::Future;
+
+/// An allocator for `DynamicFuture`s which alternates between two slots of
+/// storage.
+///
+/// This allows to reuse storage for ping-pong patterns, where the next future
+/// is allocated while the previously returned future is still in flight - e.g.
+/// when a request is built while the previous request is still outstanding.
+///
+/// The allocator will hand out whichever slot is compatible with the new future
+/// and no longer in use. If a slot is no longer in use but is not compatible,
+/// its storage is replaced with storage for the new future. If both slots are
+/// in use, the future will be boxed.
+pub struct DoubleBufferedRecycler {
+    /// The storage slots. A slot is null until storage is allocated for it.
+    slots: [*const RecyclableFutureHeader; 2],
+}
+
+impl Default for DoubleBufferedRecycler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for DoubleBufferedRecycler {
+    fn drop(&mut self) {
+        for slot in self.slots.iter() {
+            if !slot.is_null() {
+                unsafe {
+                    release_recycler_reference(*slot);
+                }
+            }
+        }
+    }
+}
+
+impl DoubleBufferedRecycler {
+    /// Creates a new allocator. Storage is only allocated once futures get
+    /// allocated.
+    pub fn new() -> Self {
+        Self {
+            slots: [core::ptr::null(); 2],
+        }
+    }
+
+    /// Transforms the passed future into a `DynamicFuture`.
+    ///
+    /// This action will move the future on the heap and type erase its behavior.
+    /// The operation will reuse the memory of a slot that is no longer in use
+    /// by a previously returned future if possible.
+    #[track_caller]
+    pub fn allocate<'a, F, T>(&mut self, fut: F) -> DynamicFuture<'a, T>
+    where
+        F: Future<Output = T> + 'a,
+    {
+        self.allocate_tracked(fut).0
+    }
+
+    /// Transforms the passed future into a `DynamicFuture`, and reports how the
+    /// storage for it was obtained.
+    #[track_caller]
+    pub fn allocate_tracked<'a, F, T>(&mut self, mut fut: F) -> (DynamicFuture<'a, T>, Reused)
+    where
+        F: Future<Output = T> + 'a,
+    {
+        let layout = Layout::for_value(&fut);
+        if layout.size() == 0 {
+            // Zero sized futures don't require any storage
+            return (zero_sized_future(fut), Reused::ZeroSized);
+        }
+        for slot in self.slots.iter() {
+            if slot.is_null() || !unsafe { (**slot).matches_layout(layout) } {
+                continue;
+            }
+            match unsafe { reuse_recyclable_future(*slot, fut) } {
+                Ok(fut) => return (fut, Reused::Recycled),
+                Err(f) => fut = f,
+            }
+        }
+
+        // Allocate storage for an empty slot. If there is none, replace the
+        // storage of a slot which is no longer in use but does not fit the
+        // future - otherwise a slot would stick to the layout of its first
+        // future forever.
+        let slot = match self.slots.iter().position(|slot| slot.is_null()) {
+            Some(index) => Some(index),
+            None => self
+                .slots
+                .iter()
+                .position(|slot| unsafe { (**slot).is_free() }),
+        };
+        if let Some(index) = slot {
+            let (fut, header) = new_retained_recyclable_future(fut, layout, Global);
+            let header = match header {
+                Some(header) => header,
+                None => return (fut, Reused::BoxedFallback),
+            };
+            let slot = &mut self.slots[index];
+            if !slot.is_null() {
+                // The previous storage is freed once it is no longer in use
+                unsafe { release_recycler_reference(*slot) };
+            }
+            *slot = header;
+            return (fut, Reused::FreshAllocation);
+        }
+
+        // Both slots are in use
+        (box_future(fut), Reused::BoxedFallback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{poll_once, yield_now, yield_value};
+    use core::task::Poll;
+
+    #[test]
+    fn second_future_uses_the_other_slot() {
+        let mut recycler = DoubleBufferedRecycler::new();
+        for round in 0..3 {
+            let (mut first, first_reused) = recycler.allocate_tracked(yield_value(1));
+            assert!(poll_once(&mut first).is_pending());
+            let (mut second, second_reused) = recycler.allocate_tracked(yield_value(2));
+            assert_ne!(first.ptr(), second.ptr());
+
+            let expected = if round == 0 {
+                Reused::FreshAllocation
+            } else {
+                Reused::Recycled
+            };
+            assert_eq!(expected, first_reused);
+            assert_eq!(expected, second_reused);
+
+            assert_eq!(Poll::Ready(1), poll_once(&mut first));
+            assert!(poll_once(&mut second).is_pending());
+            assert_eq!(Poll::Ready(2), poll_once(&mut second));
+        }
+
+        let first = recycler.allocate(yield_value(1));
+        let second = recycler.allocate(yield_value(2));
+        let (third, reused) = recycler.allocate_tracked(yield_value(3));
+        assert_eq!(Reused::BoxedFallback, reused);
+        drop((first, second, third));
+    }
+
+    #[test]
+    fn free_slots_are_refitted_for_other_layouts() {
+        let mut recycler = DoubleBufferedRecycler::new();
+        let small = recycler.allocate(yield_value(1));
+        let other_small = recycler.allocate(yield_value(2));
+        drop((small, other_small));
+
+        let data = [0u8; 64];
+        let big = move || async move {
+            yield_now().await;
+            data[0]
+        };
+        let (fut, reused) = recycler.allocate_tracked(big());
+        assert_eq!(Reused::FreshAllocation, reused);
+        drop(fut);
+        let (fut, reused) = recycler.allocate_tracked(big());
+        assert_eq!(Reused::Recycled, reused);
+
+        // The other slot still holds the storage for the small futures
+        let (small, reused) = recycler.allocate_tracked(yield_value(3));
+        assert_eq!(Reused::Recycled, reused);
+        drop((fut, small));
+    }
+
+    #[test]
+    fn slots_which_are_in_use_are_not_refitted() {
+        let mut recycler = DoubleBufferedRecycler::new();
+        let first = recycler.allocate(yield_value(1));
+        let second = recycler.allocate(yield_value(2));
+
+        let data = [0u8; 64];
+        let (big, reused) = recycler.allocate_tracked(async move { data[0] });
+        assert_eq!(Reused::BoxedFallback, reused);
+        drop(big);
+
+        // Both futures still use their storage, and can be completed
+        assert_eq!(2, futures::executor::block_on(second));
+        assert_eq!(1, futures::executor::block_on(first));
+    }
+}
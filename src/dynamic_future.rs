@@ -1,8 +1,19 @@
-use std::{
+#[cfg(feature = "std")]
+use crate::combinators::CatchUnwind;
+use crate::{
+    combinators::{
+        CachedOutput, Counted, DynamicFutureWeak, Fuse, Observed, OnCancel, PollCounter,
+    },
+    storage::{stored_future_vtable, InPlace},
+};
+use alloc::boxed::Box;
+use core::{
+    any::TypeId,
+    fmt,
     future::Future,
     marker::PhantomData,
     pin::Pin,
-    task::{Context, Poll},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
 };
 
 /// A dynamically dispatched `Future`
@@ -12,6 +23,15 @@ use std::{
 /// heap allocated and does not move while the `Future` is not dropped.
 ///
 /// Thereby this `Future` can be `Unpin`
+///
+/// `DynamicFuture` is covariant in both `'a` and `T`, since the vtable only
+/// produces values of `T`. A `DynamicFuture<'long, &'long str>` can therefore
+/// be used where a `DynamicFuture<'short, &'short str>` is expected.
+///
+/// The output may borrow from the same lifetime as the `Future` itself. This
+/// allows async trait methods to return data which is borrowed from `self`,
+/// e.g. `fn name<'a>(&'a self) -> DynamicFuture<'a, &'a str>`.
+#[must_use = "futures do nothing unless polled"]
 pub struct DynamicFuture<'a, T> {
     inner: *const (),
     /// The vtable which defines how the `Future` is polled and dropped.
@@ -23,29 +43,44 @@ pub struct DynamicFuture<'a, T> {
 }
 
 // This Future is always `Unpin`, since the actual future is stored on the heap
-// and has a pinned location
+// and has a pinned location. The vtable functions only receive the pointer to
+// the actual future - never the address of the `DynamicFuture` - so nothing
+// relies on the `DynamicFuture` itself staying in place between polls.
 impl<'a, T> Unpin for DynamicFuture<'a, T> {}
 
 impl<'a, T> Drop for DynamicFuture<'a, T> {
     fn drop(&mut self) {
         // Delegate destruction of the `Future` to the vtable
         unsafe {
-            (self.vtable.drop_fn)(self.inner as *const ());
+            (self.vtable.drop_fn)(self.inner);
         }
     }
 }
 
+impl<'a, T> fmt::Debug for DynamicFuture<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The backing `Future` is type erased and is not accessed
+        f.debug_struct("DynamicFuture")
+            .field("ptr", &self.inner)
+            .field("vtable", &(self.vtable as *const DynamicFutureVtable<T>))
+            .field("output", &core::any::type_name::<T>())
+            .finish()
+    }
+}
+
 impl<'a, T> Future for DynamicFuture<'a, T> {
     type Output = T;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        unsafe { (self.vtable.poll_fn)(self.inner as *const (), cx) }
+        unsafe { (self.vtable.poll_fn)(self.inner, cx) }
     }
 }
 
 impl<'a, T> DynamicFuture<'a, T> {
     /// Creates a new `DynamicFuture`.
     ///
+    /// # Safety
+    ///
     /// This method is `unsafe`. The caller must guarantee that the vtable and
     /// ptr are valid, and applying the methods of the vtable onto the pointer
     /// results in a correctly behaving and safe future implementation.
@@ -57,17 +92,468 @@ impl<'a, T> DynamicFuture<'a, T> {
         }
     }
 
+    /// Creates a new `DynamicFuture` from a pointer and the functions which
+    /// poll and drop the `Future` it refers to.
+    ///
+    /// This allows to construct `DynamicFuture`s from functions which are only
+    /// known at runtime - e.g. functions which are provided by an FFI layer.
+    /// Since the vtable of a `DynamicFuture` needs to outlive it, the pointer
+    /// and the functions are moved into a `Box`, which is freed after `drop_fn`
+    /// had been called.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that
+    /// - `poll_fn` can be called with `ptr` repeatedly until it returns
+    ///   `Poll::Ready`. `ptr` must not change its location during this time,
+    ///   since the `Future` it refers to is considered to be pinned.
+    /// - `drop_fn` can be called with `ptr` exactly once, and that `ptr` is
+    ///   never used again afterwards. `drop_fn` is responsible for freeing any
+    ///   storage `ptr` refers to.
+    /// - `ptr` stays valid for the lifetime `'a`.
+    pub unsafe fn from_raw_parts(
+        ptr: *const (),
+        poll_fn: unsafe fn(*const (), &mut Context<'_>) -> Poll<T>,
+        drop_fn: unsafe fn(*const ()),
+    ) -> Self
+    where
+        T: 'a,
+    {
+        let parts = Box::new(RawParts {
+            ptr,
+            poll_fn,
+            drop_fn,
+        });
+        DynamicFuture::new(Box::into_raw(parts) as *const (), raw_parts_vtable::<T>())
+    }
+
     /// Returns the pointer stored in this `Future`
+    ///
+    /// The `DynamicFuture` still owns the `Future` which is referenced by the
+    /// pointer. Use [`DynamicFuture::into_raw_parts`] in order to transfer the
+    /// ownership.
     pub fn ptr(&self) -> *const () {
         self.inner
     }
 
+    /// Returns the identity of the storage which backs this `Future`, or `None`
+    /// if the `Future` is zero sized and therefore has no storage.
+    ///
+    /// The identity stays valid after the `Future` was dropped. Comparing it
+    /// with the identity of a later `Future` reports whether the later `Future`
+    /// reused the storage - e.g. of a `RecyclableFutureAllocator`.
+    pub fn storage_id(&self) -> Option<StorageId> {
+        if self.backing_size() == Some(0) {
+            return None;
+        }
+        Some(StorageId(self.inner as usize))
+    }
+
+    /// Returns whether this `Future` and `other` refer to the same storage.
+    ///
+    /// Storage is only reused after the `Future` which used it had been
+    /// dropped, so two `Future`s which were allocated as intended never share
+    /// storage while both are alive. Zero sized `Future`s don't have any
+    /// storage, and never share it. Use [`DynamicFuture::storage_id`] in order
+    /// to check whether a `Future` reused the storage of a `Future` which had
+    /// already been dropped.
+    pub fn shares_storage_with<U>(&self, other: &DynamicFuture<'_, U>) -> bool {
+        match self.storage_id() {
+            Some(id) => Some(id) == other.storage_id(),
+            None => false,
+        }
+    }
+
+    /// Decomposes this `Future` into its pointer and vtable, without dropping
+    /// the `Future` which is referenced by them.
+    ///
+    /// The caller takes over the ownership of the `Future`. It can be turned
+    /// back into a `DynamicFuture` via [`DynamicFuture::new`], or can be driven
+    /// and dropped manually via the vtable. The `Future` is leaked if neither
+    /// happens.
+    pub fn into_raw_parts(self) -> (*const (), &'a DynamicFutureVtable<T>) {
+        let parts = (self.inner, self.vtable);
+        core::mem::forget(self);
+        parts
+    }
+
     /// Returns the vtable stored in this `Future`
+    ///
+    /// The vtables of this crate are `static`s. `Future`s of the same type
+    /// which are stored the same way typically share a vtable, but this is not
+    /// guaranteed - the address of a vtable must not be used to identify the
+    /// type of a `Future`. Use [`DynamicFuture::downcast_source`] instead.
     pub fn vtable(&self) -> &'a DynamicFutureVtable<T> {
         self.vtable
     }
+
+    /// Returns this `Future` pinned, for interoperability with code which is
+    /// written against `Pin<&mut F>`.
+    ///
+    /// This is possible since `DynamicFuture` is always `Unpin`. The
+    /// `DynamicFuture` may still be moved after the returned reference is
+    /// dropped - e.g. between polls - since only the actual future behind the
+    /// pointer has to stay in place.
+    pub fn as_pin_mut(&mut self) -> Pin<&mut Self> {
+        Pin::new(self)
+    }
+
+    /// Polls this `Future` without requiring it to be pinned.
+    ///
+    /// This is possible since `DynamicFuture` is always `Unpin`.
+    pub fn poll_unpin(&mut self, cx: &mut Context<'_>) -> Poll<T> {
+        Pin::new(self).poll(cx)
+    }
+
+    /// Polls this `Future` once with the `Waker` of `cx`, without giving up
+    /// ownership of it.
+    ///
+    /// In contrast to [`DynamicFuture::now_or_never`] the `Future` is neither
+    /// consumed nor dropped - regardless of the result. If it returns
+    /// `Poll::Pending`, the `Waker` of `cx` is registered for a wakeup, and the
+    /// `Future` can be polled again later. Once it returned `Poll::Ready`, the
+    /// caller must not poll it again, and should drop it in order to release
+    /// its storage. This is equivalent to [`DynamicFuture::poll_unpin`].
+    pub fn poll_once(&mut self, cx: &mut Context<'_>) -> Poll<T> {
+        self.poll_unpin(cx)
+    }
+
+    /// Polls this `Future` exactly once with a no-op waker.
+    ///
+    /// Returns the output of the `Future` if it is immediately ready. In this
+    /// case the `Future` is dropped. Otherwise the `Future` is handed back, and
+    /// can be polled again.
+    pub fn now_or_never(mut self) -> Result<T, Self> {
+        let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+        let mut cx = Context::from_waker(&waker);
+        match self.poll_unpin(&mut cx) {
+            Poll::Ready(output) => Ok(output),
+            Poll::Pending => Err(self),
+        }
+    }
+
+    /// Returns the size in bytes of the storage which backs this `Future`, if
+    /// the vtable tracks it.
+    pub fn backing_size(&self) -> Option<usize> {
+        self.vtable
+            .size_fn
+            .map(|size_fn| unsafe { size_fn(self.inner) })
+    }
+
+    /// Returns a reference to the `Future` of type `F` which backs this
+    /// `DynamicFuture`, or `None` if it is backed by a different type.
+    ///
+    /// This only succeeds if the vtable tracks the type of the `Future` - e.g.
+    /// for `Future`s which were created via [`typed_box_future`](crate::typed_box_future).
+    pub fn downcast_source<F: 'static>(&self) -> Option<&F> {
+        let type_id_fn = self.vtable.type_id_fn?;
+        if type_id_fn() != TypeId::of::<F>() {
+            return None;
+        }
+        // The vtable guarantees that the pointer refers to a `Future` of the
+        // tracked type. The `Future` is only accessed via a shared reference,
+        // which does not move it.
+        Some(unsafe { &*(self.inner as *const F) })
+    }
+
+    /// Converts this `Future` into a `Pin<Box<dyn Future>>`, for APIs which
+    /// require the standard boxed `Future` type.
+    ///
+    /// The `DynamicFuture` itself is moved into the `Box`, which requires an
+    /// allocation for the pointer and vtable. The backing `Future` stays at its
+    /// current storage location and is dropped once the `Box` is dropped.
+    pub fn into_pin_box(self) -> Pin<Box<dyn Future<Output = T> + 'a>>
+    where
+        T: 'a,
+    {
+        Box::pin(self)
+    }
+
+    /// Creates a `DynamicFuture` from a `Pin<Box<dyn Future>>`.
+    ///
+    /// The boxed `Future` is not moved. Since `Pin<Box<dyn Future>>` is a fat
+    /// pointer which does not fit into the thin pointer of the `DynamicFuture`,
+    /// the `Pin<Box<dyn Future>>` itself is moved into another `Box`. Polling
+    /// the `DynamicFuture` polls the boxed `Future`, and dropping it drops the
+    /// `Box`.
+    pub fn from_pin_box(fut: Pin<Box<dyn Future<Output = T> + 'a>>) -> Self
+    where
+        T: 'a,
+    {
+        crate::box_future(fut)
+    }
+
+    /// Returns a `Future` which can safely be polled after it completed.
+    ///
+    /// The returned `Future` resolves to the output of this `Future`. Once it
+    /// did, it returns `Poll::Pending` on every further poll, without
+    /// registering the `Waker` - instead of polling this `Future` in an invalid
+    /// state, which e.g. panics for `async` blocks. This `Future` is dropped as
+    /// soon as it completes, which also releases its storage. The returned
+    /// `Future` is boxed.
+    pub fn fused(self) -> Self
+    where
+        T: 'a,
+    {
+        crate::box_future(Fuse { inner: Some(self) })
+    }
+
+    /// Returns a `Future` which publishes whether it is still alive, together
+    /// with a handle which observes this.
+    ///
+    /// The handle reports the `Future` as alive until it completes or is
+    /// dropped. It does not keep the `Future` or its storage alive, and can not
+    /// access them - it only shares an `Arc`'d state with the returned
+    /// `Future`, which boxes this `Future` together with the state. This allows
+    /// e.g. schedulers to detect cancelled work.
+    pub fn observed(self) -> (Self, DynamicFutureWeak)
+    where
+        T: 'a,
+    {
+        let state = DynamicFutureWeak::new();
+        let fut = crate::box_future(Observed {
+            fut: self,
+            state: state.clone(),
+        });
+        (fut, state)
+    }
+
+    /// Returns a `Future` which counts how often it is polled, together with
+    /// the counter.
+    ///
+    /// Every poll of the returned `Future` increments the counter before this
+    /// `Future` is polled. This allows to detect `Future`s which are polled
+    /// more often than expected - e.g. by an executor which polls them without
+    /// having been woken up. The returned `Future` is boxed, and shares the
+    /// counter via an `Arc`.
+    pub fn counted(self) -> (Self, PollCounter)
+    where
+        T: 'a,
+    {
+        let counter = PollCounter::default();
+        let fut = crate::box_future(Counted {
+            fut: self,
+            counter: counter.clone(),
+        });
+        (fut, counter)
+    }
+
+    /// Returns a `Future` which catches panics of this `Future`.
+    ///
+    /// The returned `Future` resolves to `Ok` with the output of this `Future`,
+    /// or to `Err` with the panic payload if polling it panicked. In the latter
+    /// case this `Future` is dropped immediately and is never polled again. A
+    /// panic while dropping it is caught and discarded.
+    ///
+    /// Since the type of this `Future` is erased, it can not be required to be
+    /// `UnwindSafe`. The caller is responsible for not observing state which
+    /// was left inconsistent by the panic. The returned `Future` is boxed.
+    #[cfg(feature = "std")]
+    pub fn catch_unwind(self) -> DynamicFuture<'a, std::thread::Result<T>>
+    where
+        T: 'a,
+    {
+        crate::box_future(CatchUnwind { fut: Some(self) })
+    }
+
+    /// Returns a `Future` which calls `f` if it is dropped before it completed.
+    ///
+    /// The returned `Future` resolves to the output of this `Future`. If it is
+    /// dropped before that happened - i.e. if it is cancelled - this `Future`
+    /// is dropped first, and `f` is called afterwards. If the `Future`
+    /// completes, `f` is dropped without being called. This `Future` and `f`
+    /// are boxed together.
+    pub fn on_cancel<F>(self, f: F) -> Self
+    where
+        T: 'a,
+        F: FnOnce() + 'a,
+    {
+        crate::box_future(OnCancel {
+            fut: Some(self),
+            on_cancel: Some(f),
+        })
+    }
+
+    /// Returns a wrapper which stores the output of this `Future` once it
+    /// completed, until it is taken via [`CachedOutput::take_output`].
+    ///
+    /// This allows to poll the `Future` manually until it is ready, and to
+    /// extract its output afterwards without polling it again. The wrapper
+    /// does not allocate.
+    pub fn cache_output(self) -> CachedOutput<'a, T> {
+        CachedOutput::new(self)
+    }
+}
+
+fn noop_raw_waker() -> RawWaker {
+    unsafe fn clone(_: *const ()) -> RawWaker {
+        noop_raw_waker()
+    }
+    unsafe fn noop(_: *const ()) {}
+
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    RawWaker::new(core::ptr::null(), &VTABLE)
+}
+
+impl<'a, T: 'a> From<DynamicFuture<'a, T>> for Pin<Box<dyn Future<Output = T> + 'a>> {
+    fn from(fut: DynamicFuture<'a, T>) -> Self {
+        fut.into_pin_box()
+    }
+}
+
+impl<'a, T: 'a> From<Pin<Box<dyn Future<Output = T> + 'a>>> for DynamicFuture<'a, T> {
+    fn from(fut: Pin<Box<dyn Future<Output = T> + 'a>>) -> Self {
+        DynamicFuture::from_pin_box(fut)
+    }
 }
 
+/// A `DynamicFuture` which can be sent between threads.
+///
+/// `DynamicFuture` stores a raw pointer to the type-erased `Future` and is
+/// therefore neither `Send` nor `Sync`. `SendDynamicFuture` wraps a
+/// `DynamicFuture` whose backing `Future` is known to be `Send`, and shares its
+/// vtable based implementation of `poll` and `drop`.
+///
+/// Moving a `SendDynamicFuture` to another thread moves ownership of the heap
+/// allocated `Future`, which is sound as long as that `Future` is `Send`.
+/// The safe constructors therefore statically require the `Future` to be `Send`.
+#[must_use = "futures do nothing unless polled"]
+pub struct SendDynamicFuture<'a, T> {
+    inner: DynamicFuture<'a, T>,
+}
+
+// The backing `Future` was required to be `Send` when the `SendDynamicFuture`
+// was created. The vtable only consists of function pointers.
+unsafe impl<'a, T> Send for SendDynamicFuture<'a, T> {}
+
+impl<'a, T> fmt::Debug for SendDynamicFuture<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SendDynamicFuture")
+            .field(&self.inner)
+            .finish()
+    }
+}
+
+impl<'a, T> Future for SendDynamicFuture<'a, T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.inner.poll_unpin(cx)
+    }
+}
+
+impl<'a, T> SendDynamicFuture<'a, T> {
+    /// Creates a new `SendDynamicFuture` from a `DynamicFuture`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the `Future` which is referenced by the
+    /// `DynamicFuture` is `Send`, and that it is valid to run the methods of the
+    /// vtable on any thread. When building with
+    /// `--cfg async_trait_experiments_single_threaded` this is never the case
+    /// for `Future`s which are stored in recyclable storage.
+    pub unsafe fn from_dynamic(fut: DynamicFuture<'a, T>) -> Self {
+        Self { inner: fut }
+    }
+
+    /// Converts this `Future` into a `DynamicFuture`, which is no longer `Send`
+    pub fn into_dynamic(self) -> DynamicFuture<'a, T> {
+        self.inner
+    }
+
+    /// Returns the pointer stored in this `Future`
+    pub fn ptr(&self) -> *const () {
+        self.inner.ptr()
+    }
+
+    /// Returns the vtable stored in this `Future`
+    pub fn vtable(&self) -> &'a DynamicFutureVtable<T> {
+        self.inner.vtable()
+    }
+
+    /// Returns the size in bytes of the storage which backs this `Future`, if
+    /// the vtable tracks it.
+    pub fn backing_size(&self) -> Option<usize> {
+        self.inner.backing_size()
+    }
+}
+
+impl<'a, T> From<SendDynamicFuture<'a, T>> for DynamicFuture<'a, T> {
+    fn from(fut: SendDynamicFuture<'a, T>) -> Self {
+        fut.into_dynamic()
+    }
+}
+
+// Fails to compile if `DynamicFuture` or `SendDynamicFuture` are not covariant
+#[allow(dead_code)]
+fn assert_covariance<'long: 'short, 'short>(
+    fut: DynamicFuture<'long, &'long str>,
+    send_fut: SendDynamicFuture<'long, &'long str>,
+) -> (
+    DynamicFuture<'short, &'short str>,
+    SendDynamicFuture<'short, &'short str>,
+) {
+    (fut, send_fut)
+}
+
+/// Polls the `Future` of type `F` which is stored at `ptr`
+pub(crate) unsafe fn poll_future_at<T, F: Future<Output = T>>(
+    ptr: *const (),
+    cx: &mut Context<'_>,
+) -> Poll<T> {
+    let fut: &mut F = &mut *(ptr as *const F as *mut F);
+    let pinned = Pin::new_unchecked(fut);
+    pinned.poll(cx)
+}
+
+/// Returns the vtable for a `Future` of type `F` which is stored at the
+/// location the pointer of the `DynamicFuture` refers to.
+///
+/// The vtable polls the `Future` at that location, and drops it in place once
+/// the `DynamicFuture` is dropped. It does not free the storage of the
+/// `Future`. The owner of the storage is responsible for keeping it valid and
+/// pinned until the `DynamicFuture` is dropped, and for freeing it afterwards.
+pub fn vtable_for<'a, F, T>() -> &'a DynamicFutureVtable<T>
+where
+    F: Future<Output = T> + 'a,
+{
+    stored_future_vtable::<F, T, InPlace>()
+}
+
+/// The pointer and functions of a `DynamicFuture` which was created via
+/// [`DynamicFuture::from_raw_parts`]
+struct RawParts<T> {
+    ptr: *const (),
+    poll_fn: unsafe fn(*const (), &mut Context<'_>) -> Poll<T>,
+    drop_fn: unsafe fn(*const ()),
+}
+
+unsafe fn poll_raw_parts<T>(ptr: *const (), cx: &mut Context<'_>) -> Poll<T> {
+    let parts = &*(ptr as *const RawParts<T>);
+    (parts.poll_fn)(parts.ptr, cx)
+}
+
+unsafe fn drop_raw_parts<T>(ptr: *const ()) {
+    let parts = Box::from_raw(ptr as *const RawParts<T> as *mut RawParts<T>);
+    (parts.drop_fn)(parts.ptr);
+}
+
+fn raw_parts_vtable<'a, T: 'a>() -> &'a DynamicFutureVtable<T> {
+    &DynamicFutureVtable {
+        poll_fn: poll_raw_parts::<T>,
+        drop_fn: drop_raw_parts::<T>,
+        size_fn: None,
+        type_id_fn: None,
+    }
+}
+
+/// Identifies the storage which backs a `DynamicFuture`.
+///
+/// This is obtained via [`DynamicFuture::storage_id`]. It does not keep the
+/// storage alive, and can be compared after the `Future` was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StorageId(usize);
+
 /// Defines the behavior of a dynamically dispatched `Future`
 pub struct DynamicFutureVtable<T> {
     /// Advances the state of this `Future`. This method is called every time
@@ -75,4 +561,692 @@ pub struct DynamicFutureVtable<T> {
     pub poll_fn: unsafe fn(*const (), &mut Context<'_>) -> Poll<T>,
     /// Drops the `Future`.
     pub drop_fn: unsafe fn(*const ()),
+    /// Returns the size in bytes of the storage which backs the `Future`.
+    /// This is `None` if the size is not tracked.
+    ///
+    /// The field is private, so that vtables can only be created via
+    /// [`DynamicFutureVtable::new`]. Further fields can therefore be added
+    /// without breaking vtables which are defined outside of this crate.
+    pub(crate) size_fn: Option<unsafe fn(*const ()) -> usize>,
+    /// Returns the `TypeId` of the `Future`. This is `None` if the type is not
+    /// tracked.
+    ///
+    /// If this is set, the pointer of the `DynamicFuture` must point to a
+    /// `Future` of the returned type, which allows to downcast it via
+    /// [`DynamicFuture::downcast_source`]. The field is private for the same
+    /// reason as `size_fn`.
+    pub(crate) type_id_fn: Option<fn() -> TypeId>,
+}
+
+impl<T> DynamicFutureVtable<T> {
+    /// Creates a vtable from the functions which poll and drop a `Future`.
+    ///
+    /// The vtable does not track the size or type of the `Future`. Since this is a
+    /// `const fn`, the vtable can be stored in a `static`.
+    pub const fn new(
+        poll_fn: unsafe fn(*const (), &mut Context<'_>) -> Poll<T>,
+        drop_fn: unsafe fn(*const ()),
+    ) -> Self {
+        Self {
+            poll_fn,
+            drop_fn,
+            size_fn: None,
+            type_id_fn: None,
+        }
+    }
+
+    /// Returns this vtable with a function which reports the size in bytes of
+    /// the storage that backs the `Future`.
+    ///
+    /// The function is called with the pointer of the `DynamicFuture` by
+    /// [`DynamicFuture::backing_size`], and needs to be valid for it in the
+    /// same way as the other functions of the vtable.
+    pub const fn with_size_fn(self, size_fn: unsafe fn(*const ()) -> usize) -> Self {
+        Self {
+            size_fn: Some(size_fn),
+            ..self
+        }
+    }
+
+    /// Returns the function which reports the size of the storage that backs
+    /// the `Future`, or `None` if the size is not tracked
+    pub fn size_fn(&self) -> Option<unsafe fn(*const ()) -> usize> {
+        self.size_fn
+    }
+
+    /// Returns this vtable with a function which reports the `TypeId` of the
+    /// `Future`, which allows to downcast it via
+    /// [`DynamicFuture::downcast_source`].
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the pointer of every `DynamicFuture`
+    /// which uses the vtable refers to a `Future` of the type whose `TypeId` is
+    /// returned by `type_id_fn`.
+    pub const unsafe fn with_type_id_fn(self, type_id_fn: fn() -> TypeId) -> Self {
+        Self {
+            type_id_fn: Some(type_id_fn),
+            ..self
+        }
+    }
+
+    /// Returns the function which reports the `TypeId` of the `Future`, or
+    /// `None` if the type is not tracked
+    pub fn type_id_fn(&self) -> Option<fn() -> TypeId> {
+        self.type_id_fn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{poll_once, yield_now, yield_value, DropCounter},
+        RecyclableFutureAllocator, Reused,
+    };
+    use core::cell::Cell;
+    use futures::executor::block_on;
+
+    #[test]
+    fn recycled_future_is_converted_into_pin_box() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let (fut, reused) = recycler.allocate_tracked(yield_value(3));
+        assert_eq!(Reused::FreshAllocation, reused);
+
+        let boxed: Pin<Box<dyn Future<Output = u32>>> = fut.into_pin_box();
+        assert_eq!(3, block_on(boxed));
+
+        // The storage is released once the box is dropped
+        let (fut, reused) = recycler.allocate_tracked(yield_value(4));
+        assert_eq!(Reused::Recycled, reused);
+        assert_eq!(4, block_on(fut.into_pin_box()));
+    }
+
+    #[test]
+    fn pin_box_which_is_dropped_releases_the_storage() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let boxed = recycler.allocate(yield_value(3)).into_pin_box();
+        let (fut, reused) = recycler.allocate_tracked(yield_value(4));
+        assert_eq!(Reused::BoxedFallback, reused);
+        drop(fut);
+        drop(boxed);
+
+        let (_fut, reused) = recycler.allocate_tracked(yield_value(5));
+        assert_eq!(Reused::Recycled, reused);
+    }
+
+    #[test]
+    fn pin_box_round_trips() {
+        let value = [7u8; 16];
+        let boxed: Pin<Box<dyn Future<Output = u8>>> = Box::pin(async move {
+            yield_now().await;
+            value[15]
+        });
+        let fut = DynamicFuture::from_pin_box(boxed);
+        let boxed = fut.into_pin_box();
+        assert_eq!(7, block_on(DynamicFuture::from_pin_box(boxed)));
+    }
+
+    #[test]
+    fn from_pin_box_drops_the_box() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let boxed: Pin<Box<dyn Future<Output = ()> + '_>> = Box::pin(async move {
+            let _counter = counter;
+            yield_now().await;
+        });
+        let mut fut = DynamicFuture::from_pin_box(boxed);
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(0, drops.get());
+        drop(fut);
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn debug_output_contains_the_output_type() {
+        let fut = crate::box_future(async { 1u32 });
+        let debug = alloc::format!("{:?}", fut);
+        assert!(debug.starts_with("DynamicFuture"), "{}", debug);
+        assert!(debug.contains("output: \"u32\""), "{}", debug);
+    }
+
+    /// A future which counts down to zero, and yields on every step
+    struct Countdown<'c> {
+        remaining: u32,
+        _counter: DropCounter<'c>,
+    }
+
+    impl Future for Countdown<'_> {
+        type Output = &'static str;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<&'static str> {
+            if self.remaining == 0 {
+                return Poll::Ready("done");
+            }
+            self.remaining -= 1;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn vtable_for_polls_and_drops_the_future_in_place() {
+        let drops = Cell::new(0);
+        let mut storage = core::mem::ManuallyDrop::new(Countdown {
+            remaining: 2,
+            _counter: DropCounter(&drops),
+        });
+        let ptr = &mut *storage as *mut Countdown<'_> as *const ();
+        let mut fut = unsafe { DynamicFuture::new(ptr, vtable_for::<Countdown<'_>, _>()) };
+        assert_eq!(ptr, fut.ptr());
+        assert_eq!(
+            Some(core::mem::size_of::<Countdown<'_>>()),
+            fut.backing_size()
+        );
+
+        assert!(poll_once(&mut fut).is_pending());
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready("done"), poll_once(&mut fut));
+        assert_eq!(0, drops.get());
+
+        // The future is dropped in place, while the storage stays valid
+        drop(fut);
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn backing_size_covers_the_captured_data() {
+        let data = [0u8; 1024];
+        let boxed = crate::box_future(async move { data[0] });
+        assert!(boxed.backing_size().unwrap() >= 1024);
+
+        let mut recycler = RecyclableFutureAllocator::new();
+        let recycled = recycler.allocate(async move { data[1] });
+        assert!(recycled.backing_size().unwrap() >= 1024);
+    }
+
+    unsafe fn poll_countdown(ptr: *const (), cx: &mut Context<'_>) -> Poll<&'static str> {
+        Pin::new_unchecked(&mut *(ptr as *mut Countdown<'_>)).poll(cx)
+    }
+
+    unsafe fn drop_countdown(ptr: *const ()) {
+        core::ptr::drop_in_place(ptr as *mut Countdown<'_>);
+    }
+
+    unsafe fn countdown_size(_ptr: *const ()) -> usize {
+        core::mem::size_of::<Countdown<'_>>()
+    }
+
+    #[test]
+    fn vtables_which_are_created_via_new_report_the_size_fn() {
+        let vtable = DynamicFutureVtable::new(poll_countdown, drop_countdown);
+        assert!(vtable.size_fn().is_none());
+
+        let drops = Cell::new(0);
+        let mut storage = core::mem::ManuallyDrop::new(Countdown {
+            remaining: 1,
+            _counter: DropCounter(&drops),
+        });
+        let vtable = vtable.with_size_fn(countdown_size);
+        let ptr = &mut *storage as *mut Countdown<'_> as *const ();
+        let mut fut = unsafe { DynamicFuture::new(ptr, &vtable) };
+        assert_eq!(
+            Some(core::mem::size_of::<Countdown<'_>>()),
+            fut.backing_size()
+        );
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready("done"), poll_once(&mut fut));
+        drop(fut);
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn now_or_never_returns_the_output_of_ready_futures() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let fut = crate::box_future(async move {
+            let _counter = counter;
+            5
+        });
+        assert_eq!(5, fut.now_or_never().unwrap());
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn now_or_never_hands_back_pending_futures() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let fut = crate::box_future(async move {
+            let _counter = counter;
+            yield_now().await;
+            6
+        });
+        let fut = fut.now_or_never().unwrap_err();
+        assert_eq!(0, drops.get());
+        assert_eq!(6, fut.now_or_never().unwrap());
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn poll_unpin_drives_the_future_to_completion() {
+        let drops = Cell::new(0);
+        let mut fut = crate::box_future(Countdown {
+            remaining: 3,
+            _counter: DropCounter(&drops),
+        });
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let mut polls = 1;
+        while fut.poll_unpin(&mut cx).is_pending() {
+            polls += 1;
+        }
+        assert_eq!(4, polls);
+
+        // The future is only dropped together with the `DynamicFuture`
+        assert_eq!(0, drops.get());
+        drop(fut);
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn conversions_via_into_take_ownership() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let fut = crate::box_future(async move {
+            let _counter = counter;
+            yield_now().await;
+            8u32
+        });
+        let boxed: Pin<Box<dyn Future<Output = u32> + '_>> = fut.into();
+        let fut: DynamicFuture<'_, u32> = boxed.into();
+        assert_eq!(0, drops.get());
+        assert_eq!(8, block_on(fut));
+        assert_eq!(1, drops.get());
+    }
+
+    unsafe fn drop_boxed_countdown(ptr: *const ()) {
+        drop(Box::from_raw(ptr as *mut Countdown<'_>));
+    }
+
+    #[test]
+    fn from_raw_parts_polls_and_drops_via_the_functions() {
+        let drops = Cell::new(0);
+        let storage = Box::new(Countdown {
+            remaining: 1,
+            _counter: DropCounter(&drops),
+        });
+        let ptr = Box::into_raw(storage) as *const ();
+        let mut fut =
+            unsafe { DynamicFuture::from_raw_parts(ptr, poll_countdown, drop_boxed_countdown) };
+        assert!(fut.backing_size().is_none());
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready("done"), poll_once(&mut fut));
+        assert_eq!(0, drops.get());
+        drop(fut);
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn from_raw_parts_drops_pending_futures() {
+        let drops = Cell::new(0);
+        let storage = Box::new(Countdown {
+            remaining: 5,
+            _counter: DropCounter(&drops),
+        });
+        let ptr = Box::into_raw(storage) as *const ();
+        let mut fut =
+            unsafe { DynamicFuture::from_raw_parts(ptr, poll_countdown, drop_boxed_countdown) };
+        assert!(poll_once(&mut fut).is_pending());
+        drop(fut);
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn into_raw_parts_transfers_the_ownership() {
+        let drops = Cell::new(0);
+        let mut recycler = RecyclableFutureAllocator::new();
+        let mut fut = recycler.allocate(Countdown {
+            remaining: 1,
+            _counter: DropCounter(&drops),
+        });
+        assert!(poll_once(&mut fut).is_pending());
+
+        let (ptr, vtable) = fut.into_raw_parts();
+        assert_eq!(0, drops.get());
+        // The storage is still in use
+        let (other, reused) = recycler.allocate_tracked(yield_value(1));
+        assert_eq!(Reused::BoxedFallback, reused);
+        drop(other);
+
+        let mut fut = unsafe { DynamicFuture::new(ptr, vtable) };
+        assert_eq!(Poll::Ready("done"), poll_once(&mut fut));
+        drop(fut);
+        assert_eq!(1, drops.get());
+        let (_fut, reused) = recycler.allocate_tracked(Countdown {
+            remaining: 0,
+            _counter: DropCounter(&drops),
+        });
+        assert_eq!(Reused::Recycled, reused);
+    }
+
+    /// A `'static` future which counts down to zero
+    struct StaticCountdown(u32);
+
+    impl Future for StaticCountdown {
+        type Output = u32;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u32> {
+            if self.0 == 0 {
+                return Poll::Ready(0);
+            }
+            self.0 -= 1;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn typed_futures_can_be_downcast() {
+        let mut fut = crate::typed_box_future(StaticCountdown(2));
+        assert!(fut.vtable().type_id_fn().is_some());
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(1, fut.downcast_source::<StaticCountdown>().unwrap().0);
+        assert!(fut.downcast_source::<u32>().is_none());
+    }
+
+    #[test]
+    fn untyped_futures_can_not_be_downcast() {
+        let boxed = crate::box_future(StaticCountdown(1));
+        assert!(boxed.downcast_source::<StaticCountdown>().is_none());
+
+        let mut recycler = RecyclableFutureAllocator::new();
+        let recycled = recycler.allocate(StaticCountdown(1));
+        assert!(recycled.vtable().type_id_fn().is_none());
+        assert!(recycled.downcast_source::<StaticCountdown>().is_none());
+    }
+
+    unsafe fn poll_static_countdown(ptr: *const (), cx: &mut Context<'_>) -> Poll<u32> {
+        Pin::new_unchecked(&mut *(ptr as *mut StaticCountdown)).poll(cx)
+    }
+
+    unsafe fn drop_static_countdown(ptr: *const ()) {
+        core::ptr::drop_in_place(ptr as *mut StaticCountdown);
+    }
+
+    #[test]
+    fn vtables_which_are_created_via_new_can_track_the_type() {
+        let vtable = DynamicFutureVtable::new(poll_static_countdown, drop_static_countdown);
+        assert!(vtable.type_id_fn().is_none());
+        let vtable = unsafe { vtable.with_type_id_fn(TypeId::of::<StaticCountdown>) };
+
+        let mut storage = StaticCountdown(3);
+        let ptr = &mut storage as *mut StaticCountdown as *const ();
+        let fut = unsafe { DynamicFuture::new(ptr, &vtable) };
+        assert_eq!(3, fut.downcast_source::<StaticCountdown>().unwrap().0);
+        assert!(fut.backing_size().is_none());
+    }
+
+    #[test]
+    fn fused_future_stays_pending_after_completion() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let mut fut = crate::box_future(async move {
+            let _counter = counter;
+            yield_now().await;
+            3u32
+        })
+        .fused();
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready(3), poll_once(&mut fut));
+        // The inner future was dropped once it completed
+        assert_eq!(1, drops.get());
+
+        for _ in 0..3 {
+            assert!(poll_once(&mut fut).is_pending());
+        }
+        drop(fut);
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn on_cancel_runs_if_the_future_is_dropped_before_completion() {
+        let cancelled = Cell::new(0);
+        let mut fut = crate::box_future(async {
+            yield_now().await;
+            1u32
+        })
+        .on_cancel(|| cancelled.set(cancelled.get() + 1));
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(0, cancelled.get());
+        drop(fut);
+        assert_eq!(1, cancelled.get());
+
+        // A future which was never polled is cancelled as well
+        let fut =
+            crate::box_future(async { 1u32 }).on_cancel(|| cancelled.set(cancelled.get() + 1));
+        drop(fut);
+        assert_eq!(2, cancelled.get());
+    }
+
+    #[test]
+    fn on_cancel_does_not_run_if_the_future_completed() {
+        let cancelled = Cell::new(false);
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let cancelled_ref = &cancelled;
+        let fut = crate::box_future(async {
+            yield_now().await;
+            1u32
+        })
+        .on_cancel(move || {
+            let _counter = counter;
+            cancelled_ref.set(true);
+        });
+        assert_eq!(1, block_on(fut));
+        assert!(!cancelled.get());
+        // The closure was dropped without being called
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn shares_storage_with_compares_the_storage() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let first = recycler.allocate(async {
+            yield_now().await;
+            1u32
+        });
+        assert!(first.shares_storage_with(&first));
+
+        // Live futures never share storage
+        let second = recycler.allocate(async { "two" });
+        assert!(!first.shares_storage_with(&second));
+        let boxed = crate::box_future(async { 3u8 });
+        let other_boxed = crate::box_future(async { 4u8 });
+        assert!(!boxed.shares_storage_with(&other_boxed));
+        assert!(!boxed.shares_storage_with(&first));
+
+        // Zero sized futures don't have any storage
+        let zero_sized = crate::box_future(futures::future::pending::<()>());
+        let other_zero_sized = crate::box_future(futures::future::pending::<()>());
+        assert_eq!(None, zero_sized.storage_id());
+        assert!(!zero_sized.shares_storage_with(&zero_sized));
+        assert!(!zero_sized.shares_storage_with(&other_zero_sized));
+    }
+
+    #[test]
+    fn sequential_recycled_futures_share_storage() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let first = recycler.allocate(yield_value(1));
+        let id = first.storage_id();
+        assert!(id.is_some());
+        assert_eq!(1, block_on(first));
+        let second = recycler.allocate(yield_value(2));
+        assert_eq!(id, second.storage_id());
+
+        // Boxed futures which are alive at the same time never share storage
+        let boxed = crate::box_future(yield_value(3));
+        let other_boxed = crate::box_future(yield_value(4));
+        assert_ne!(boxed.storage_id(), other_boxed.storage_id());
+        assert_ne!(id, boxed.storage_id());
+    }
+
+    #[test]
+    fn counted_future_counts_its_polls() {
+        let (fut, counter) = crate::box_future(async {
+            for _ in 0..3 {
+                yield_now().await;
+            }
+            1u32
+        })
+        .counted();
+        assert_eq!(0, counter.polls());
+        assert_eq!(1, block_on(fut));
+        // The counter can still be read once the future was dropped
+        assert_eq!(4, counter.polls());
+
+        let (mut fut, counter) = crate::box_future(async { 2u32 }).counted();
+        assert_eq!(Poll::Ready(2), poll_once(&mut fut));
+        assert_eq!(1, counter.polls());
+    }
+
+    /// Counts how often the `Waker` it is converted into gets woken
+    #[derive(Default)]
+    struct WakeCounter(std::sync::atomic::AtomicUsize);
+
+    impl futures::task::ArcWake for WakeCounter {
+        fn wake_by_ref(arc_self: &std::sync::Arc<Self>) {
+            arc_self
+                .0
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn poll_once_keeps_the_future_and_registers_the_waker() {
+        let counter = std::sync::Arc::new(WakeCounter::default());
+        let waker = futures::task::waker(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut fut = crate::box_future(async {
+            yield_now().await;
+            2u32
+        });
+        assert_eq!(Poll::Pending, fut.poll_once(&mut cx));
+        assert_eq!(1, counter.0.load(std::sync::atomic::Ordering::Relaxed));
+        assert_eq!(Poll::Ready(2), fut.poll_once(&mut cx));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn catch_unwind_resolves_to_the_panic() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let mut fut = crate::box_future(async move {
+            let _counter = counter;
+            yield_now().await;
+            panic!("second poll");
+        })
+        .catch_unwind();
+        assert!(poll_once(&mut fut).is_pending());
+        let payload = match poll_once(&mut fut) {
+            Poll::Ready(Err(payload)) => payload,
+            _ => panic!("Expected the panic to be caught"),
+        };
+        assert_eq!(Some(&"second poll"), payload.downcast_ref::<&str>());
+        assert_eq!(1, drops.get());
+        drop(fut);
+        assert_eq!(1, drops.get());
+
+        let mut fut = crate::box_future(async { 4u32 }).catch_unwind();
+        match poll_once(&mut fut) {
+            Poll::Ready(Ok(4)) => {}
+            _ => panic!("Expected the output"),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn catch_unwind_catches_panics_while_dropping_the_future() {
+        /// A future which panics when it is polled and when it is dropped
+        struct PanicEverywhere;
+
+        impl Future for PanicEverywhere {
+            type Output = ();
+
+            fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+                panic!("poll panicked");
+            }
+        }
+
+        impl Drop for PanicEverywhere {
+            fn drop(&mut self) {
+                panic!("drop panicked");
+            }
+        }
+
+        let mut fut = crate::box_future(PanicEverywhere).catch_unwind();
+        assert!(matches!(poll_once(&mut fut), Poll::Ready(Err(_))));
+        drop(fut);
+    }
+
+    #[test]
+    fn futures_can_be_moved_between_polls_through_as_pin_mut() {
+        fn poll_pinned<F: Future>(fut: Pin<&mut F>) -> Poll<F::Output> {
+            let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+            fut.poll(&mut cx)
+        }
+
+        let mut recycler = RecyclableFutureAllocator::new();
+        let data = [5u32; 8];
+        let mut fut = recycler.allocate(async move {
+            // The reference into the future stays valid across the yields
+            let value = &data[7];
+            for _ in 0..3 {
+                yield_now().await;
+            }
+            *value
+        });
+        let mut polls = 0;
+        let output = loop {
+            polls += 1;
+            if let Poll::Ready(output) = poll_pinned(fut.as_pin_mut()) {
+                break output;
+            }
+            // Move the `DynamicFuture` - but not the future it refers to
+            fut = *alloc::boxed::Box::new(fut);
+        };
+        assert_eq!(5, output);
+        assert_eq!(4, polls);
+    }
+
+    #[test]
+    fn weak_handle_observes_completion() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let (mut fut, weak) = recycler.allocate(yield_value(1)).observed();
+        assert!(weak.is_alive());
+        assert!(poll_once(&mut fut).is_pending());
+        assert!(weak.is_alive());
+        assert_eq!(Poll::Ready(1), poll_once(&mut fut));
+        assert!(!weak.is_alive());
+        assert!(weak.is_completed());
+
+        // Dropping the completed future does not cancel it
+        drop(fut);
+        assert!(weak.is_completed());
+        assert!(!weak.is_cancelled());
+    }
+
+    #[test]
+    fn weak_handle_observes_cancellation() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let (mut fut, weak) = recycler.allocate(yield_value(1)).observed();
+        let other = weak.clone();
+        assert!(poll_once(&mut fut).is_pending());
+        drop(fut);
+        // The state outlives the future and the allocator
+        drop(recycler);
+        assert!(!weak.is_alive());
+        assert!(weak.is_cancelled());
+        assert!(!weak.is_completed());
+        assert!(other.is_cancelled());
+    }
 }
@@ -0,0 +1,205 @@
+use core::{
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A dynamically dispatched `Sink`
+///
+/// This is the `Sink` counterpart of `DynamicFuture`. The actual implementation
+/// is hidden behind the `Sink`s vtable, and the `Sink`s backing storage
+/// location must not move while the `Sink` is not dropped.
+///
+/// Thereby this `Sink` can be `Unpin`. Items of type `Item` are sent into the
+/// `Sink`, and all operations fail with errors of type `E`.
+#[must_use = "sinks do nothing unless polled"]
+pub struct DynamicSink<'a, Item, E> {
+    inner: *const (),
+    /// The vtable which defines how the `Sink` is driven and dropped.
+    vtable: &'a DynamicSinkVtable<Item, E>,
+    /// Allows to store a lifetime with the `Sink` if required
+    _phantom: PhantomData<&'a ()>,
+}
+
+// This Sink is always `Unpin`, since the actual sink is stored on the heap
+// and has a pinned location
+impl<'a, Item, E> Unpin for DynamicSink<'a, Item, E> {}
+
+impl<'a, Item, E> Drop for DynamicSink<'a, Item, E> {
+    fn drop(&mut self) {
+        // Delegate destruction of the `Sink` to the vtable
+        unsafe {
+            (self.vtable.drop_fn)(self.inner);
+        }
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<'a, Item, E> futures_sink::Sink<Item> for DynamicSink<'a, Item, E> {
+    type Error = E;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+        DynamicSink::poll_ready(self, cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Item) -> Result<(), E> {
+        DynamicSink::start_send(self, item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+        DynamicSink::poll_flush(self, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+        DynamicSink::poll_close(self, cx)
+    }
+}
+
+impl<'a, Item, E> DynamicSink<'a, Item, E> {
+    /// Creates a new `DynamicSink`.
+    ///
+    /// # Safety
+    ///
+    /// This method is `unsafe`. The caller must guarantee that the vtable and
+    /// ptr are valid, and applying the methods of the vtable onto the pointer
+    /// results in a correctly behaving and safe sink implementation.
+    pub unsafe fn new(ptr: *const (), vtable: &'a DynamicSinkVtable<Item, E>) -> Self {
+        Self {
+            inner: ptr,
+            vtable,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the pointer stored in this `Sink`
+    pub fn ptr(&self) -> *const () {
+        self.inner
+    }
+
+    /// Returns the vtable stored in this `Sink`
+    pub fn vtable(&self) -> &'a DynamicSinkVtable<Item, E> {
+        self.vtable
+    }
+
+    /// Attempts to prepare the `Sink` to receive a value.
+    ///
+    /// This must return `Poll::Ready(Ok(()))` before each call to
+    /// [`DynamicSink::start_send`].
+    pub fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+        unsafe { (self.vtable.poll_ready_fn)(self.inner, cx) }
+    }
+
+    /// Begins the process of sending a value to the `Sink`.
+    pub fn start_send(self: Pin<&mut Self>, item: Item) -> Result<(), E> {
+        unsafe { (self.vtable.start_send_fn)(self.inner, item) }
+    }
+
+    /// Flushes any remaining data which is buffered by the `Sink`.
+    pub fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+        unsafe { (self.vtable.poll_flush_fn)(self.inner, cx) }
+    }
+
+    /// Flushes any remaining data and closes the `Sink`.
+    pub fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+        unsafe { (self.vtable.poll_close_fn)(self.inner, cx) }
+    }
+}
+
+/// Defines the behavior of a dynamically dispatched `Sink`
+pub struct DynamicSinkVtable<Item, E> {
+    /// Prepares the `Sink` to receive a value.
+    pub poll_ready_fn: unsafe fn(*const (), &mut Context<'_>) -> Poll<Result<(), E>>,
+    /// Sends a value to the `Sink`.
+    pub start_send_fn: unsafe fn(*const (), Item) -> Result<(), E>,
+    /// Flushes the `Sink`.
+    pub poll_flush_fn: unsafe fn(*const (), &mut Context<'_>) -> Poll<Result<(), E>>,
+    /// Flushes and closes the `Sink`.
+    pub poll_close_fn: unsafe fn(*const (), &mut Context<'_>) -> Poll<Result<(), E>>,
+    /// Drops the `Sink`.
+    pub drop_fn: unsafe fn(*const ()),
+}
+
+#[cfg(all(test, feature = "futures"))]
+mod tests {
+    use crate::box_sink;
+    use alloc::vec::Vec;
+    use core::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+    use futures::{executor::block_on, SinkExt};
+
+    /// A sink which collects items, and which is only ready on every other
+    /// poll. The item `0` is rejected.
+    #[derive(Default)]
+    struct Collect {
+        items: Vec<u32>,
+        flushed: usize,
+        closed: bool,
+        ready: bool,
+    }
+
+    impl futures_sink::Sink<u32> for Collect {
+        type Error = &'static str;
+
+        fn poll_ready(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            self.ready = !self.ready;
+            if self.ready {
+                Poll::Ready(Ok(()))
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+
+        fn start_send(mut self: Pin<&mut Self>, item: u32) -> Result<(), Self::Error> {
+            if item == 0 {
+                return Err("zero");
+            }
+            self.items.push(item);
+            Ok(())
+        }
+
+        fn poll_flush(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            self.flushed = self.items.len();
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            self.closed = true;
+            self.poll_flush(cx)
+        }
+    }
+
+    #[test]
+    fn boxed_sink_forwards_all_calls() {
+        let mut collect = Collect::default();
+        {
+            let mut sink = box_sink(&mut collect);
+            for item in 1..4 {
+                block_on(sink.send(item)).unwrap();
+            }
+            assert_eq!(Err("zero"), block_on(sink.send(0)));
+            block_on(sink.close()).unwrap();
+        }
+        assert_eq!(alloc::vec![1, 2, 3], collect.items);
+        assert_eq!(3, collect.flushed);
+        assert!(collect.closed);
+    }
+
+    #[test]
+    fn zero_sized_sinks_are_dropped() {
+        let mut sink = box_sink(futures::sink::drain::<u32>());
+        block_on(sink.send(1)).unwrap();
+        drop(sink);
+    }
+}
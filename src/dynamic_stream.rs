@@ -0,0 +1,203 @@
+use core::{
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A dynamically dispatched `Stream`
+///
+/// This is the `Stream` counterpart of `DynamicFuture`. The actual implementation
+/// is hidden behind the `Stream`s vtable, and the `Stream`s backing storage
+/// location must not move while the `Stream` is not dropped.
+///
+/// Thereby this `Stream` can be `Unpin`. Like `DynamicFuture` it is covariant
+/// in both `'a` and `T`.
+#[must_use = "streams do nothing unless polled"]
+pub struct DynamicStream<'a, T> {
+    inner: *const (),
+    /// The vtable which defines how the `Stream` is polled and dropped.
+    vtable: &'a DynamicStreamVtable<T>,
+    /// Allows to store a lifetime with the `Stream` if required
+    _phantom: PhantomData<&'a ()>,
+}
+
+// This Stream is always `Unpin`, since the actual stream is stored on the heap
+// and has a pinned location
+impl<'a, T> Unpin for DynamicStream<'a, T> {}
+
+impl<'a, T> Drop for DynamicStream<'a, T> {
+    fn drop(&mut self) {
+        // Delegate destruction of the `Stream` to the vtable
+        unsafe {
+            (self.vtable.drop_fn)(self.inner);
+        }
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<'a, T> futures_core::Stream for DynamicStream<'a, T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        DynamicStream::poll_next(self, cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        DynamicStream::size_hint(self)
+    }
+}
+
+impl<'a, T> DynamicStream<'a, T> {
+    /// Creates a new `DynamicStream`.
+    ///
+    /// # Safety
+    ///
+    /// This method is `unsafe`. The caller must guarantee that the vtable and
+    /// ptr are valid, and applying the methods of the vtable onto the pointer
+    /// results in a correctly behaving and safe stream implementation.
+    pub unsafe fn new(ptr: *const (), vtable: &'a DynamicStreamVtable<T>) -> Self {
+        Self {
+            inner: ptr,
+            vtable,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the pointer stored in this `Stream`
+    pub fn ptr(&self) -> *const () {
+        self.inner
+    }
+
+    /// Returns the vtable stored in this `Stream`
+    pub fn vtable(&self) -> &'a DynamicStreamVtable<T> {
+        self.vtable
+    }
+
+    /// Attempts to pull out the next value of this `Stream`.
+    ///
+    /// Returns `Poll::Ready(None)` once the `Stream` is exhausted.
+    pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        unsafe { (self.vtable.poll_next_fn)(self.inner, cx) }
+    }
+
+    /// Returns the bounds on the remaining length of this `Stream`.
+    ///
+    /// This has the same meaning as `Iterator::size_hint`. If the vtable does
+    /// not provide a `remaining_hint_fn`, `(0, None)` is returned.
+    pub fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.vtable.remaining_hint_fn {
+            Some(remaining_hint_fn) => unsafe { remaining_hint_fn(self.inner) },
+            None => (0, None),
+        }
+    }
+
+    /// Returns a `Future` which resolves to the next value of this `Stream`.
+    // This mirrors `StreamExt::next`, and is not an `Iterator` method
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Next<'_, 'a, T> {
+        Next { stream: self }
+    }
+}
+
+/// The `Future` which is returned by [`DynamicStream::next`]
+#[must_use = "futures do nothing unless polled"]
+pub struct Next<'s, 'a, T> {
+    stream: &'s mut DynamicStream<'a, T>,
+}
+
+impl<'s, 'a, T> Future for Next<'s, 'a, T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.stream).poll_next(cx)
+    }
+}
+
+// Fails to compile if `DynamicStream` is not covariant
+#[allow(dead_code)]
+fn assert_covariance<'long: 'short, 'short>(
+    stream: DynamicStream<'long, &'long str>,
+) -> DynamicStream<'short, &'short str> {
+    stream
+}
+
+/// The lower and the optional upper bound on the remaining length of a `Stream`
+type SizeHint = (usize, Option<usize>);
+
+/// Defines the behavior of a dynamically dispatched `Stream`
+pub struct DynamicStreamVtable<T> {
+    /// Attempts to pull out the next value of this `Stream`. This method is
+    /// called every time the `Stream` is `.poll_next()`ed.
+    pub poll_next_fn: unsafe fn(*const (), &mut Context<'_>) -> Poll<Option<T>>,
+    /// Drops the `Stream`.
+    pub drop_fn: unsafe fn(*const ()),
+    /// Returns the bounds on the remaining length of the `Stream`. Streams
+    /// which don't know their length can leave this empty.
+    pub remaining_hint_fn: Option<unsafe fn(*const ()) -> SizeHint>,
+}
+
+#[cfg(all(test, feature = "futures"))]
+mod tests {
+    use crate::{box_stream, test_utils::poll_once};
+    use core::task::Poll;
+    use futures::stream;
+
+    #[test]
+    fn boxed_stream_is_drained() {
+        let mut stream = box_stream(stream::iter(1..=4u32));
+        assert_eq!((4, Some(4)), stream.size_hint());
+        let mut items = alloc::vec::Vec::new();
+        while let Poll::Ready(Some(item)) = poll_once(&mut stream.next()) {
+            items.push(item);
+        }
+        assert_eq!([1, 2, 3, 4], items[..]);
+        assert_eq!(Poll::Ready(None), poll_once(&mut stream.next()));
+    }
+
+    #[test]
+    fn boxed_stream_implements_stream() {
+        use futures::StreamExt;
+
+        let stream = box_stream(stream::iter(0..10u32).filter(|i| core::future::ready(i % 3 == 0)));
+        let items: alloc::vec::Vec<u32> = futures::executor::block_on(stream.collect());
+        assert_eq!([0, 3, 6, 9], items[..]);
+    }
+
+    #[test]
+    fn size_hint_tracks_the_remaining_items() {
+        let mut stream = box_stream(stream::iter(0..3u32));
+        assert_eq!((3, Some(3)), stream.size_hint());
+        for remaining in (0..3).rev() {
+            assert!(matches!(
+                poll_once(&mut stream.next()),
+                Poll::Ready(Some(_))
+            ));
+            assert_eq!((remaining, Some(remaining)), stream.size_hint());
+        }
+    }
+
+    #[test]
+    fn collected_items_are_presized_from_the_size_hint() {
+        // A countdown of 5 items. Without the size hint the capacity of the
+        // `Vec` would grow from 4 to 8 while the items are collected.
+        let stream = box_stream(stream::iter((0..5u32).rev()));
+        let items: alloc::vec::Vec<u32> = futures::executor::block_on_stream(stream).collect();
+        assert_eq!([4, 3, 2, 1, 0], items[..]);
+        assert_eq!(5, items.capacity());
+    }
+
+    #[test]
+    fn size_hint_is_unknown_without_a_hint_function() {
+        let boxed = box_stream(stream::iter(0..3u32));
+        let vtable = crate::DynamicStreamVtable {
+            remaining_hint_fn: None,
+            ..*boxed.vtable()
+        };
+        let ptr = boxed.ptr();
+        core::mem::forget(boxed);
+
+        let stream = unsafe { crate::DynamicStream::new(ptr, &vtable) };
+        assert_eq!((0, None), stream.size_hint());
+    }
+}
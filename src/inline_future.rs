@@ -0,0 +1,172 @@
+use crate::DynamicFuture;
+use core::{
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A dynamically dispatched `Future` which stores the functions of its vtable
+/// inline.
+///
+/// `DynamicFuture` refers to its vtable, which requires an additional pointer
+/// indirection on every poll. `DynamicFutureInline` instead stores the
+/// functions which poll and drop the `Future` next to the pointer. This makes
+/// it one pointer bigger than `DynamicFuture`, and saves the indirection.
+///
+/// It is created from a `DynamicFuture`, and polls and drops the `Future`
+/// exactly like the `DynamicFuture` would have done. The optional functions of
+/// the vtable are not retained, so the size and type of the `Future` can not
+/// be queried.
+#[must_use = "futures do nothing unless polled"]
+pub struct DynamicFutureInline<'a, T> {
+    inner: *const (),
+    /// Advances the state of the `Future`
+    poll_fn: unsafe fn(*const (), &mut Context<'_>) -> Poll<T>,
+    /// Drops the `Future`
+    drop_fn: unsafe fn(*const ()),
+    /// Allows to store a lifetime with the `Future` if required
+    _phantom: PhantomData<&'a ()>,
+}
+
+// This Future is always `Unpin`, since the actual future is stored on the heap
+// and has a pinned location
+impl<'a, T> Unpin for DynamicFutureInline<'a, T> {}
+
+impl<'a, T> Drop for DynamicFutureInline<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            (self.drop_fn)(self.inner);
+        }
+    }
+}
+
+impl<'a, T> fmt::Debug for DynamicFutureInline<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DynamicFutureInline")
+            .field("ptr", &self.inner)
+            .field("output", &core::any::type_name::<T>())
+            .finish()
+    }
+}
+
+impl<'a, T> Future for DynamicFutureInline<'a, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        unsafe { (self.poll_fn)(self.inner, cx) }
+    }
+}
+
+impl<'a, T> DynamicFutureInline<'a, T> {
+    /// Returns the pointer stored in this `Future`
+    pub fn ptr(&self) -> *const () {
+        self.inner
+    }
+
+    /// Polls this `Future` without requiring it to be pinned.
+    ///
+    /// This is possible since `DynamicFutureInline` is always `Unpin`.
+    pub fn poll_unpin(&mut self, cx: &mut Context<'_>) -> Poll<T> {
+        Pin::new(self).poll(cx)
+    }
+
+    /// Converts this `Future` back into a `DynamicFuture`.
+    ///
+    /// Since the original vtable is no longer available, the pointer and
+    /// functions are boxed - see [`DynamicFuture::from_raw_parts`].
+    pub fn into_dynamic(self) -> DynamicFuture<'a, T>
+    where
+        T: 'a,
+    {
+        let (ptr, poll_fn, drop_fn) = (self.inner, self.poll_fn, self.drop_fn);
+        core::mem::forget(self);
+        // The functions had been taken from the vtable of a `DynamicFuture`
+        // which owned the `Future` at `ptr`.
+        unsafe { DynamicFuture::from_raw_parts(ptr, poll_fn, drop_fn) }
+    }
+}
+
+impl<'a, T> From<DynamicFuture<'a, T>> for DynamicFutureInline<'a, T> {
+    fn from(fut: DynamicFuture<'a, T>) -> Self {
+        let (ptr, vtable) = fut.into_raw_parts();
+        Self {
+            inner: ptr,
+            poll_fn: vtable.poll_fn,
+            drop_fn: vtable.drop_fn,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+// Fails to compile if `DynamicFutureInline` is not covariant
+#[allow(dead_code)]
+fn assert_covariance<'long: 'short, 'short>(
+    fut: DynamicFutureInline<'long, &'long str>,
+) -> DynamicFutureInline<'short, &'short str> {
+    fut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{counted_value, poll_once, DropCounter},
+        RecyclableFutureAllocator, Reused,
+    };
+    use core::cell::Cell;
+
+    #[test]
+    fn inline_futures_are_polled_to_completion() {
+        let drops = Cell::new(0);
+        let mut recycler = RecyclableFutureAllocator::new();
+        let fut = recycler.allocate(counted_value(DropCounter(&drops), 1));
+        let ptr = fut.ptr();
+        let mut fut = DynamicFutureInline::from(fut);
+        assert_eq!(ptr, fut.ptr());
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready(1), poll_once(&mut fut));
+        drop(fut);
+        assert_eq!(1, drops.get());
+
+        // Dropping the inline future released the storage
+        let (fut, reused) = recycler.allocate_tracked(counted_value(DropCounter(&drops), 2));
+        assert_eq!(Reused::Recycled, reused);
+        let fut = DynamicFutureInline::from(fut);
+        assert_eq!(2, futures::executor::block_on(fut));
+    }
+
+    #[test]
+    fn pending_inline_futures_are_dropped_once() {
+        let drops = Cell::new(0);
+        let mut fut =
+            DynamicFutureInline::from(crate::box_future(counted_value(DropCounter(&drops), 1)));
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(0, drops.get());
+        drop(fut);
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn inline_futures_convert_back_into_dynamic_futures() {
+        let drops = Cell::new(0);
+        let mut fut =
+            DynamicFutureInline::from(crate::box_future(counted_value(DropCounter(&drops), 3)));
+        assert!(poll_once(&mut fut).is_pending());
+
+        // The `Future` continues where it left off
+        let mut fut = fut.into_dynamic();
+        assert_eq!(0, drops.get());
+        assert_eq!(Poll::Ready(3), poll_once(&mut fut));
+        drop(fut);
+        assert_eq!(1, drops.get());
+
+        let fut =
+            DynamicFutureInline::from(crate::box_future(counted_value(DropCounter(&drops), 4)));
+        let fut = fut.into_dynamic();
+        drop(fut);
+        // Dropping the converted `Future` drops the original `Future`
+        assert_eq!(2, drops.get());
+    }
+}
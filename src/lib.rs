@@ -1,6 +1,98 @@
+#![no_std]
+
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate std;
+
+#[cfg(feature = "macros")]
+pub use async_trait_experiments_macros::recyclable_async;
+
+mod assert_size;
+mod storage;
+mod sync;
+#[cfg(test)]
+mod test_utils;
+
+mod allocator;
+pub use allocator::{Allocator, Global};
 mod dynamic_future;
-pub use dynamic_future::{DynamicFuture, DynamicFutureVtable};
+pub use dynamic_future::{
+    vtable_for, DynamicFuture, DynamicFutureVtable, SendDynamicFuture, StorageId,
+};
+mod dynamic_stream;
+pub use dynamic_stream::{DynamicStream, DynamicStreamVtable, Next};
+mod dynamic_sink;
+pub use dynamic_sink::{DynamicSink, DynamicSinkVtable};
 mod recycler;
-pub use recycler::RecyclableFutureAllocator;
+#[cfg(feature = "stats")]
+pub use recycler::RecyclerStats;
+pub use recycler::{
+    future_layout, GrowthPolicy, RecyclableFutureAllocator, RecyclableFutureInfo, Reused,
+};
+#[cfg(feature = "unstable-internals")]
+pub use recycler::{new_recyclable_future_with_refcount, release_recyclable_reference};
+mod local_recycler;
+pub use local_recycler::LocalRecyclableFutureAllocator;
+mod deferred_drop_recycler;
+pub use deferred_drop_recycler::DeferredDropRecycler;
+mod recycled_once;
+pub use recycled_once::RecycledOnce;
+mod recycler_scope;
+pub use recycler_scope::RecyclerScope;
+mod multi_slot_recycler;
+pub use multi_slot_recycler::MultiSlotRecycler;
+mod batch_recycler;
+pub use batch_recycler::BatchRecycler;
+mod arena_recycler;
+pub use arena_recycler::ArenaRecycler;
+#[cfg(feature = "std")]
+mod thread_local_recycler;
+#[cfg(feature = "std")]
+pub use thread_local_recycler::thread_local_recycler;
+#[cfg(feature = "std")]
+mod block_on;
+#[cfg(feature = "std")]
+pub use block_on::block_on_dynamic;
+#[cfg(feature = "std")]
+mod depth_limited;
+#[cfg(feature = "std")]
+pub use depth_limited::depth_limited;
+#[cfg(all(feature = "std", feature = "futures"))]
+pub use depth_limited::depth_limited_stream;
+mod classed_recycler;
+pub use classed_recycler::ClassedRecycler;
+mod double_buffered_recycler;
+pub use double_buffered_recycler::DoubleBufferedRecycler;
+mod shared_recycler;
+pub use shared_recycler::{OwnedRecycledFuture, SharedRecycler};
+mod small_future;
+pub use small_future::SmallDynamicFuture;
+mod inline_future;
+pub use inline_future::DynamicFutureInline;
+mod combinators;
+pub use combinators::{
+    dynamic_and_then, dynamic_inspect, dynamic_join, dynamic_map, dynamic_map_err, dynamic_map_ok,
+    dynamic_poll_fn, dynamic_select, dynamic_timeout, CachedOutput, DynamicFutureExt,
+    DynamicFutureWeak, Either, PollCounter, TryDynamicFutureExt,
+};
 mod boxed_future;
-pub use boxed_future::box_future;
+pub use boxed_future::{
+    box_future, box_future_in, box_future_owned, box_future_send, try_box_future,
+    try_box_future_in, typed_box_future,
+};
+#[cfg(feature = "futures")]
+mod boxed_stream;
+#[cfg(feature = "futures")]
+pub use boxed_stream::box_stream;
+#[cfg(feature = "futures")]
+mod boxed_sink;
+#[cfg(feature = "futures")]
+pub use boxed_sink::box_sink;
+#[cfg(feature = "futures")]
+mod recyclable_stream;
+#[cfg(feature = "futures")]
+pub use recyclable_stream::{RecyclableStream, RecycleFromIter};
+#[cfg(feature = "futures")]
+mod as_stream;
+#[cfg(feature = "futures")]
+pub use as_stream::{AsStream, DynamicFutureStream};
@@ -0,0 +1,298 @@
+use crate::{
+    box_future,
+    boxed_future::zero_sized_future,
+    storage::{stored_future_vtable, PayloadStorage},
+    DynamicFuture,
+};
+use alloc::alloc::Layout;
+use core::{cell::Cell, fmt, future::Future, marker::PhantomData};
+
+/// An allocator for `DynamicFuture`s which can reuse storage, and which is
+/// restricted to a single thread.
+///
+/// This works like a `RecyclableFutureAllocator` with the default settings:
+/// The storage which is allocated for the first future is reused for following
+/// futures of the same layout, once the previous future had been dropped.
+/// Futures which can not use the storage are boxed.
+///
+/// In contrast to `RecyclableFutureAllocator` the refcount of the storage is a
+/// `Cell` instead of an atomic. The allocator is therefore neither `Send` nor
+/// `Sync`, and is intended for local executors which drive all futures on the
+/// same thread. This provides the same optimization as
+/// `--cfg async_trait_experiments_single_threaded` for individual allocators,
+/// while other allocators in the same binary keep using atomic refcounts.
+///
+/// The returned `DynamicFuture`s are not `Send` either. They must not be moved
+/// to other threads via `SendDynamicFuture::from_dynamic`.
+pub struct LocalRecyclableFutureAllocator {
+    recycled: *const LocalFutureHeader,
+    /// Makes the `!Send` and `!Sync` nature of the allocator explicit
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for LocalRecyclableFutureAllocator {
+    fn drop(&mut self) {
+        if !self.recycled.is_null() {
+            unsafe {
+                LocalFutureHeader::release(self.recycled);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for LocalRecyclableFutureAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("LocalRecyclableFutureAllocator");
+        s.field("cached", &!self.recycled.is_null());
+        if !self.recycled.is_null() {
+            // Only the header is accessed here. The payload might be in use by
+            // a future.
+            let header = unsafe { &*self.recycled };
+            s.field("size", &header.data_layout.size())
+                .field("refcount", &header.refcount.get());
+        }
+        s.finish()
+    }
+}
+
+impl Default for LocalRecyclableFutureAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LocalRecyclableFutureAllocator {
+    /// Creates a new allocator, which does not retain any storage yet
+    pub fn new() -> Self {
+        Self {
+            recycled: core::ptr::null(),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Transforms the passed future into a `DynamicFuture`.
+    ///
+    /// This action will move the future on the heap and type erase its behavior.
+    /// The operation will reuse memory from a previous `allocate` call if possible.
+    pub fn allocate<'a, F, T>(&mut self, fut: F) -> DynamicFuture<'a, T>
+    where
+        F: Future<Output = T> + 'a,
+    {
+        let layout = Layout::new::<F>();
+        if layout.size() == 0 {
+            // Zero sized futures don't require any storage
+            return zero_sized_future(fut);
+        }
+
+        unsafe {
+            if self.recycled.is_null() {
+                match LocalFutureHeader::allocate(layout) {
+                    Ok(header) => self.recycled = header,
+                    Err(()) => return box_future(fut),
+                }
+            } else if (*self.recycled).data_layout != layout
+                || !LocalFutureHeader::try_acquire(self.recycled)
+            {
+                // The storage can not be used for this future, or is still in
+                // use by a previous future
+                return box_future(fut);
+            }
+
+            core::ptr::write(
+                <LocalFutureHeader as PayloadStorage<F>>::payload(self.recycled as *const ()),
+                fut,
+            );
+            DynamicFuture::new(
+                self.recycled as *const (),
+                stored_future_vtable::<F, T, LocalFutureHeader>(),
+            )
+        }
+    }
+}
+
+/// A header stored in front of the futures of a `LocalRecyclableFutureAllocator`
+struct LocalFutureHeader {
+    /// The amount of active references to the storage. This follows the same
+    /// protocol as the refcount of `RecyclableFutureAllocator`: The storage is
+    /// free for a new payload if only the allocator references it.
+    refcount: Cell<u8>,
+    /// The layout of the payloads of the storage
+    data_layout: Layout,
+}
+
+impl LocalFutureHeader {
+    /// Allocates storage for payloads of `data_layout`.
+    ///
+    /// The storage holds a reference for the allocator and one for the payload
+    /// which is about to be stored.
+    unsafe fn allocate(data_layout: Layout) -> Result<*const LocalFutureHeader, ()> {
+        let layout = Self::layout_for(data_layout)?;
+        let header = alloc::alloc::alloc(layout) as *mut LocalFutureHeader;
+        if header.is_null() {
+            return Err(());
+        }
+
+        core::ptr::write(
+            header,
+            LocalFutureHeader {
+                refcount: Cell::new(2),
+                data_layout,
+            },
+        );
+        Ok(header)
+    }
+
+    /// Returns the layout of storage which holds the header followed by a
+    /// payload of `data_layout`
+    fn layout_for(data_layout: Layout) -> Result<Layout, ()> {
+        let (layout, _) = Layout::new::<LocalFutureHeader>()
+            .extend(data_layout)
+            .map_err(|_| ())?;
+        Ok(layout.pad_to_align())
+    }
+
+    /// Tries to acquire the storage in order to store a new payload inside it.
+    ///
+    /// The caller must hold a reference on the storage.
+    unsafe fn try_acquire(header: *const LocalFutureHeader) -> bool {
+        if (*header).refcount.get() != 1 {
+            return false;
+        }
+        (*header).refcount.set(2);
+        true
+    }
+
+    /// Releases a reference on the storage, and frees the storage if it was
+    /// the last reference.
+    unsafe fn release(header: *const LocalFutureHeader) {
+        let refcount = (*header).refcount.get() - 1;
+        (*header).refcount.set(refcount);
+        if refcount != 0 {
+            return;
+        }
+
+        // The layout had been validated when the storage was allocated
+        let layout = Self::layout_for((*header).data_layout).unwrap_or_else(|()| unreachable!());
+        alloc::alloc::dealloc(header as *mut u8, layout);
+    }
+}
+
+// A payload holds a reference on the storage, which is released once the
+// payload had been dropped.
+impl<P> PayloadStorage<P> for LocalFutureHeader {
+    unsafe fn payload(ptr: *const ()) -> *mut P {
+        // Payloads always have the layout of the storage
+        let align = core::mem::align_of::<P>();
+        let offset = (core::mem::size_of::<LocalFutureHeader>() + align - 1) & !(align - 1);
+        (ptr as *const u8).add(offset) as *mut P
+    }
+
+    unsafe fn release(ptr: *const ()) {
+        Self::release(ptr as *const Self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{counted_value, poll_once, yield_value, DropCounter};
+    use core::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    #[test]
+    fn storage_is_reused_once_the_future_was_dropped() {
+        let mut recycler = LocalRecyclableFutureAllocator::new();
+        let mut fut = recycler.allocate(yield_value(1));
+        let storage = fut.ptr();
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready(1), poll_once(&mut fut));
+        drop(fut);
+
+        let mut fut = recycler.allocate(yield_value(2));
+        assert_eq!(storage, fut.ptr());
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready(2), poll_once(&mut fut));
+    }
+
+    #[test]
+    fn futures_are_boxed_while_the_storage_is_in_use() {
+        let mut recycler = LocalRecyclableFutureAllocator::new();
+        let mut first = recycler.allocate(yield_value(1));
+        let mut second = recycler.allocate(yield_value(2));
+        let storage = first.ptr();
+        assert_ne!(storage, second.ptr());
+        assert!(poll_once(&mut second).is_pending());
+        assert_eq!(Poll::Ready(2), poll_once(&mut second));
+        drop(second);
+
+        // Dropping the boxed future does not free the storage
+        let third = recycler.allocate(yield_value(3));
+        assert_ne!(storage, third.ptr());
+        drop(third);
+        assert!(poll_once(&mut first).is_pending());
+        assert_eq!(Poll::Ready(1), poll_once(&mut first));
+        drop(first);
+
+        let fut = recycler.allocate(yield_value(4));
+        assert_eq!(storage, fut.ptr());
+    }
+
+    #[test]
+    fn futures_of_a_different_layout_are_boxed() {
+        let mut recycler = LocalRecyclableFutureAllocator::new();
+        let storage = recycler.allocate(yield_value(1)).ptr();
+
+        let data = [7u8; 64];
+        let mut fut = recycler.allocate(async move { data[63] });
+        assert_ne!(storage, fut.ptr());
+        assert_eq!(Poll::Ready(7), poll_once(&mut fut));
+        drop(fut);
+
+        // The storage is kept for the original layout
+        let fut = recycler.allocate(yield_value(2));
+        assert_eq!(storage, fut.ptr());
+    }
+
+    /// A zero sized future which is immediately ready
+    struct Ready;
+
+    impl Future for Ready {
+        type Output = u32;
+
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<u32> {
+            Poll::Ready(5)
+        }
+    }
+
+    #[test]
+    fn zero_sized_futures_do_not_use_the_storage() {
+        let mut recycler = LocalRecyclableFutureAllocator::new();
+        let mut fut = recycler.allocate(Ready);
+        assert_eq!(Poll::Ready(5), poll_once(&mut fut));
+        assert!(recycler.recycled.is_null());
+
+        // Zero sized futures don't touch storage which is in use
+        let busy = recycler.allocate(yield_value(1));
+        let mut fut = recycler.allocate(Ready);
+        assert_eq!(Poll::Ready(5), poll_once(&mut fut));
+        assert_eq!(2, unsafe { (*recycler.recycled).refcount.get() });
+        drop(busy);
+    }
+
+    #[test]
+    fn futures_can_outlive_the_allocator() {
+        let drops = Cell::new(0);
+        let mut recycler = LocalRecyclableFutureAllocator::new();
+        let mut fut = recycler.allocate(counted_value(DropCounter(&drops), 1));
+        assert!(poll_once(&mut fut).is_pending());
+        drop(recycler);
+
+        // The future holds the last reference on the storage, and frees it
+        assert_eq!(Poll::Ready(1), poll_once(&mut fut));
+        assert_eq!(1, drops.get());
+        drop(fut);
+        assert_eq!(1, drops.get());
+    }
+}
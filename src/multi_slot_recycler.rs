@@ -0,0 +1,153 @@
+use crate::{
+    box_future,
+    boxed_future::zero_sized_future,
+    recycler::{
+        new_retained_recyclable_future, release_recycler_reference, reuse_recyclable_future,
+        RecyclableFutureHeader,
+    },
+    DynamicFuture, Global,
+};
+use alloc::{alloc::Layout, vec::Vec};
+use core::future::Future;
+
+/// An allocator for `DynamicFuture`s which can reuse the storage of multiple
+/// futures.
+///
+/// In comparison to `RecyclableFutureAllocator` - which only remembers the
+/// storage of a single future - this allocator keeps up to `capacity` slots of
+/// storage around. This allows to reuse storage even if multiple futures which
+/// got returned by the allocator are in flight at the same time - e.g. for
+/// stream combinators which poll multiple futures concurrently.
+///
+/// The allocator will hand out the first slot whose storage is compatible with
+/// the new future and no longer in use. If all slots are in use, and no further
+/// slot can be created, the future will be boxed.
+pub struct MultiSlotRecycler {
+    slots: Vec<*const RecyclableFutureHeader>,
+    capacity: usize,
+}
+
+impl Drop for MultiSlotRecycler {
+    fn drop(&mut self) {
+        for slot in self.slots.drain(..) {
+            unsafe {
+                release_recycler_reference(slot);
+            }
+        }
+    }
+}
+
+impl MultiSlotRecycler {
+    /// Creates a new allocator which will keep storage for up to `capacity`
+    /// futures around.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Returns the maximum amount of slots this allocator will allocate.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Transforms the passed future into a `DynamicFuture`.
+    ///
+    /// This action will move the future on the heap and type erase its behavior.
+    /// The operation will reuse the memory of a slot that is no longer in use
+    /// by a previously returned future if possible.
+    #[track_caller]
+    pub fn allocate<'a, F, T>(&mut self, mut fut: F) -> DynamicFuture<'a, T>
+    where
+        F: Future<Output = T> + 'a,
+    {
+        let layout = Layout::for_value(&fut);
+        if layout.size() == 0 {
+            // Zero sized futures don't require any storage
+            return zero_sized_future(fut);
+        }
+        for slot in self.slots.iter() {
+            if !unsafe { (**slot).matches_layout(layout) } {
+                continue;
+            }
+            match unsafe { reuse_recyclable_future(*slot, fut) } {
+                Ok(fut) => return fut,
+                Err(f) => fut = f,
+            }
+        }
+
+        if self.slots.len() < self.capacity {
+            let (fut, header) = new_retained_recyclable_future(fut, layout, Global);
+            self.slots.extend(header);
+            return fut;
+        }
+
+        // All slots are in use
+        box_future(fut)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{poll_once, yield_now};
+    use core::task::Poll;
+
+    fn in_slot<T>(recycler: &MultiSlotRecycler, fut: &DynamicFuture<'_, T>) -> bool {
+        recycler
+            .slots
+            .contains(&(fut.ptr() as *const RecyclableFutureHeader))
+    }
+
+    #[test]
+    fn interleaved_futures_reuse_slots() {
+        let mut recycler = MultiSlotRecycler::new(2);
+        let make_fut = |recycler: &mut MultiSlotRecycler, value: u32| {
+            recycler.allocate(async move {
+                yield_now().await;
+                value
+            })
+        };
+
+        for round in 0..10 {
+            let mut first = make_fut(&mut recycler, round);
+            let mut second = make_fut(&mut recycler, round + 1);
+            assert!(in_slot(&recycler, &first));
+            assert!(in_slot(&recycler, &second));
+            assert_ne!(first.ptr(), second.ptr());
+
+            assert!(poll_once(&mut first).is_pending());
+            assert!(poll_once(&mut second).is_pending());
+            assert_eq!(Poll::Ready(round + 1), poll_once(&mut second));
+            assert_eq!(Poll::Ready(round), poll_once(&mut first));
+        }
+        assert_eq!(2, recycler.slots.len());
+    }
+
+    #[test]
+    fn futures_beyond_capacity_are_boxed() {
+        let mut recycler = MultiSlotRecycler::new(1);
+        let first = recycler.allocate(async { 1 });
+        let second = recycler.allocate(async { 2 });
+        assert!(in_slot(&recycler, &first));
+        assert!(!in_slot(&recycler, &second));
+        drop(first);
+
+        let third = recycler.allocate(async { 3 });
+        assert!(in_slot(&recycler, &third));
+        assert_eq!(1, recycler.slots.len());
+    }
+
+    #[test]
+    fn futures_can_outlive_the_recycler() {
+        let mut recycler = MultiSlotRecycler::new(1);
+        let mut fut = recycler.allocate(async {
+            yield_now().await;
+            5
+        });
+        drop(recycler);
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready(5), poll_once(&mut fut));
+    }
+}
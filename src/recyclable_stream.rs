@@ -0,0 +1,269 @@
+use crate::{
+    box_stream, Allocator, DynamicFuture, DynamicStream, Global, RecyclableFutureAllocator,
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures_core::Stream;
+
+/// A `Stream` which produces its items via futures, which all reuse the same
+/// storage.
+///
+/// The stream is created from an initial state and a function which produces
+/// a future for the next item from the state - like
+/// `futures::stream::unfold`. Each of these futures is allocated via a
+/// `RecyclableFutureAllocator` which is owned by the stream. Since a future
+/// is always dropped before the future for the next item is created, the
+/// storage for the first future is reused for all following items.
+///
+/// The state and the function are type erased, and are moved onto the heap
+/// once when the stream is created.
+#[must_use = "streams do nothing unless polled"]
+pub struct RecyclableStream<'a, T> {
+    inner: DynamicStream<'a, T>,
+}
+
+/// Drives the futures which produce the items of a `RecyclableStream`
+struct Unfold<'a, S, F, T, A: Allocator> {
+    /// The state for producing the next item. This is `None` while a future
+    /// is in flight, and after the stream completed.
+    state: Option<S>,
+    f: F,
+    /// The future which produces the next item
+    pending: Option<DynamicFuture<'a, Option<(T, S)>>>,
+    recycler: RecyclableFutureAllocator<A>,
+}
+
+// The state is never pinned, and `DynamicFuture` is `Unpin`
+impl<'a, S, F, T, A: Allocator> Unpin for Unfold<'a, S, F, T, A> {}
+
+impl<'a, S, F, Fut, T, A> Stream for Unfold<'a, S, F, T, A>
+where
+    F: FnMut(S) -> Fut,
+    Fut: Future<Output = Option<(T, S)>> + 'a,
+    A: Allocator + Clone + 'a,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let this = self.get_mut();
+        if this.pending.is_none() {
+            let state = match this.state.take() {
+                Some(state) => state,
+                // The stream completed
+                None => return Poll::Ready(None),
+            };
+            this.pending = Some(this.recycler.allocate((this.f)(state)));
+        }
+
+        let pending = this.pending.as_mut().unwrap();
+        let output = match pending.poll_unpin(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        // Release the storage, so that it can be reused for the next item
+        this.pending = None;
+
+        Poll::Ready(output.map(|(item, state)| {
+            this.state = Some(state);
+            item
+        }))
+    }
+}
+
+impl<'a, T: 'a> RecyclableStream<'a, T> {
+    /// Creates a `RecyclableStream` from an initial state and a function which
+    /// produces a future for the next item from the state.
+    ///
+    /// The future resolves either to the next item and the state for producing
+    /// the item after it, or to `None` once the stream completed.
+    pub fn unfold<S, F, Fut>(init: S, f: F) -> Self
+    where
+        S: 'a,
+        F: FnMut(S) -> Fut + 'a,
+        Fut: Future<Output = Option<(T, S)>> + 'a,
+    {
+        Self::unfold_in(init, f, Global)
+    }
+
+    /// Creates a `RecyclableStream` whose futures are allocated via `alloc`
+    fn unfold_in<S, F, Fut, A>(init: S, f: F, alloc: A) -> Self
+    where
+        S: 'a,
+        F: FnMut(S) -> Fut + 'a,
+        Fut: Future<Output = Option<(T, S)>> + 'a,
+        A: Allocator + Clone + 'a,
+    {
+        Self {
+            inner: box_stream(Unfold {
+                state: Some(init),
+                f,
+                pending: None,
+                recycler: RecyclableFutureAllocator::new_in(alloc),
+            }),
+        }
+    }
+}
+
+impl<'a, T> Stream for RecyclableStream<'a, T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// A `Stream` which awaits the futures that are produced by an iterator of
+/// closures one after another, and yields their outputs.
+///
+/// It is created via [`RecyclableFutureAllocator::recycle_from_iter`]. All
+/// futures are allocated via the same allocator. Since a future is always
+/// dropped before the next closure is called, the storage which is allocated
+/// for the first future is reused for all following ones. The allocator is
+/// borrowed until the stream is dropped.
+#[must_use = "streams do nothing unless polled"]
+pub struct RecycleFromIter<'r, I, T, A: Allocator = Global> {
+    recycler: &'r mut RecyclableFutureAllocator<A>,
+    iter: I,
+    /// The future which produces the next item
+    pending: Option<DynamicFuture<'r, T>>,
+}
+
+// The iterator is never pinned, and `DynamicFuture` is `Unpin`
+impl<'r, I, T, A: Allocator> Unpin for RecycleFromIter<'r, I, T, A> {}
+
+impl<'r, I, T, A: Allocator> RecycleFromIter<'r, I, T, A> {
+    pub(crate) fn new(recycler: &'r mut RecyclableFutureAllocator<A>, iter: I) -> Self {
+        Self {
+            recycler,
+            iter,
+            pending: None,
+        }
+    }
+}
+
+impl<'r, I, F, Fut, T, A> Stream for RecycleFromIter<'r, I, T, A>
+where
+    I: Iterator<Item = F>,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = T> + 'r,
+    A: Allocator + Clone + 'r,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let this = self.get_mut();
+        if this.pending.is_none() {
+            let f = match this.iter.next() {
+                Some(f) => f,
+                // All futures completed
+                None => return Poll::Ready(None),
+            };
+            this.pending = Some(this.recycler.allocate(f()));
+        }
+
+        let pending = this.pending.as_mut().unwrap();
+        let output = match pending.poll_unpin(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        // Release the storage, so that it can be reused for the next future
+        this.pending = None;
+        Poll::Ready(Some(output))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // The pending future produces one more item
+        let pending = self.pending.is_some() as usize;
+        let (lower, upper) = self.iter.size_hint();
+        (
+            lower.saturating_add(pending),
+            upper.and_then(|upper| upper.checked_add(pending)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{yield_now, CountingAllocator};
+    use futures::{executor::block_on, StreamExt};
+
+    #[test]
+    fn unfold_reuses_the_storage_for_all_items() {
+        let alloc = CountingAllocator::default();
+        let stream = RecyclableStream::unfold_in(
+            0u32,
+            |count| async move {
+                yield_now().await;
+                if count < 50 {
+                    Some((count, count + 1))
+                } else {
+                    None
+                }
+            },
+            alloc.clone(),
+        );
+        let items: alloc::vec::Vec<u32> = block_on(stream.collect());
+        assert_eq!((0..50).collect::<alloc::vec::Vec<_>>(), items);
+        assert_eq!(1, alloc.allocations());
+        assert_eq!(1, alloc.deallocations());
+    }
+
+    #[test]
+    fn recycle_from_iter_reuses_the_storage_for_all_futures() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        let stream = recycler.recycle_from_iter((0..50u32).map(|i| {
+            move || async move {
+                yield_now().await;
+                i * 2
+            }
+        }));
+        assert_eq!((50, Some(50)), stream.size_hint());
+        let sum = block_on(stream.fold(0, |sum, i| async move { sum + i }));
+        assert_eq!(2450, sum);
+        assert_eq!(1, alloc.allocations());
+        assert_eq!(0, alloc.deallocations());
+    }
+
+    #[test]
+    fn recycle_from_iter_presizes_collected_items() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let stream = recycler.recycle_from_iter((0..5u32).rev().map(|i| {
+            move || async move {
+                yield_now().await;
+                i
+            }
+        }));
+        // The capacity of the `Vec` would grow from 4 to 8 without the hint
+        let items: alloc::vec::Vec<u32> = futures::executor::block_on_stream(stream).collect();
+        assert_eq!([4, 3, 2, 1, 0], items[..]);
+        assert_eq!(5, items.capacity());
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn recycle_from_iter_is_reflected_in_the_stats() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let stream = recycler.recycle_from_iter((0..50u32).map(|i| {
+            move || async move {
+                yield_now().await;
+                i
+            }
+        }));
+        assert_eq!(50, block_on(stream.count()));
+
+        let stats = recycler.stats();
+        assert_eq!(50, stats.allocations);
+        assert_eq!(1, stats.fresh_allocations);
+        assert_eq!(49, stats.recycled);
+        assert_eq!(0, stats.boxed_fallbacks);
+    }
+}
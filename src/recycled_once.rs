@@ -0,0 +1,157 @@
+use crate::{box_future, Allocator, DynamicFuture, Global, RecyclableFutureAllocator};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A future which allocates another future via a `RecyclableFutureAllocator`
+/// once it is polled for the first time.
+///
+/// It is created via [`RecyclableFutureAllocator::allocate_lazy`]. Until the
+/// future is polled, the wrapped future is stored inline and the allocator is
+/// not touched. Dropping the future without polling it therefore neither
+/// allocates storage nor occupies the storage of the allocator.
+///
+/// The wrapped future is not polled before it is moved into its storage, which
+/// is why `RecycledOnce` is `Unpin`.
+#[must_use = "futures do nothing unless polled"]
+pub struct RecycledOnce<'r, F: Future, A: Allocator = Global> {
+    state: State<'r, F, A>,
+}
+
+enum State<'r, F: Future, A: Allocator> {
+    /// The future had not been polled yet
+    Init(&'r mut RecyclableFutureAllocator<A>, F),
+    /// The wrapped future was allocated via the allocator
+    Allocated(DynamicFuture<'r, F::Output>),
+    /// The wrapped future is currently being allocated
+    Empty,
+}
+
+// The wrapped future is only pinned after it had been moved into its storage,
+// and `DynamicFuture` is `Unpin`.
+impl<'r, F: Future, A: Allocator> Unpin for RecycledOnce<'r, F, A> {}
+
+impl<'r, F: Future, A: Allocator> RecycledOnce<'r, F, A> {
+    pub(crate) fn new(recycler: &'r mut RecyclableFutureAllocator<A>, fut: F) -> Self {
+        Self {
+            state: State::Init(recycler, fut),
+        }
+    }
+
+    /// Returns whether the wrapped future had already been allocated
+    pub fn is_allocated(&self) -> bool {
+        matches!(self.state, State::Allocated(_))
+    }
+
+    /// Moves the wrapped future into a `Box` instead of the storage of the
+    /// allocator, if it had not been polled yet.
+    ///
+    /// The returned `DynamicFuture` is independent of the allocator, and does
+    /// not borrow it. Once the wrapped future had been polled, it is pinned
+    /// inside the storage of the allocator and can no longer be moved. In this
+    /// case `self` is handed back.
+    ///
+    /// A `DynamicFuture` which had been returned by the allocator can not be
+    /// converted that way, since the type of the future which is required for
+    /// moving it is erased.
+    pub fn into_boxed<'a>(self) -> Result<DynamicFuture<'a, F::Output>, Self>
+    where
+        F: 'a,
+    {
+        match self.state {
+            State::Init(_, fut) => Ok(box_future(fut)),
+            state => Err(Self { state }),
+        }
+    }
+}
+
+impl<'r, F, A> Future for RecycledOnce<'r, F, A>
+where
+    F: Future + 'r,
+    A: Allocator + Clone + 'r,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = self.get_mut();
+        if let State::Init(..) = this.state {
+            if let State::Init(recycler, fut) = core::mem::replace(&mut this.state, State::Empty) {
+                this.state = State::Allocated(recycler.allocate(fut));
+            }
+        }
+
+        match &mut this.state {
+            State::Allocated(fut) => fut.poll_unpin(cx),
+            _ => unreachable!("RecycledOnce is always allocated before polling"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{poll_once, yield_value, CountingAllocator, DropCounter};
+    use core::cell::Cell;
+
+    #[test]
+    fn storage_is_only_allocated_once_polled() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let fut = recycler.allocate_lazy(async move {
+            let _counter = counter;
+            yield_value(1).await
+        });
+        assert!(!fut.is_allocated());
+        drop(fut);
+        assert_eq!(1, drops.get());
+        assert_eq!(0, alloc.allocations());
+
+        let mut fut = recycler.allocate_lazy(yield_value(2));
+        assert!(poll_once(&mut fut).is_pending());
+        assert!(fut.is_allocated());
+        assert_eq!(1, alloc.allocations());
+        assert_eq!(Poll::Ready(2), poll_once(&mut fut));
+    }
+
+    #[test]
+    fn polled_futures_reuse_the_storage() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        for value in 0..3 {
+            let fut = recycler.allocate_lazy(yield_value(value));
+            assert_eq!(value, futures::executor::block_on(fut));
+        }
+        assert_eq!(1, alloc.allocations());
+        assert_eq!(0, alloc.deallocations());
+    }
+
+    #[test]
+    fn unpolled_futures_can_be_boxed() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        let boxed = match recycler.allocate_lazy(yield_value(1)).into_boxed() {
+            Ok(boxed) => boxed,
+            Err(_) => panic!("An unpolled future can be boxed"),
+        };
+        // The boxed future outlives the allocator, and does not use its storage
+        drop(recycler);
+        assert_eq!(1, futures::executor::block_on(boxed));
+        assert_eq!(0, alloc.allocations());
+    }
+
+    #[test]
+    fn allocated_futures_are_handed_back() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let mut fut = recycler.allocate_lazy(yield_value(2));
+        assert!(poll_once(&mut fut).is_pending());
+        let mut fut = match fut.into_boxed() {
+            Ok(_) => panic!("An allocated future can not be boxed"),
+            Err(fut) => fut,
+        };
+        assert_eq!(Poll::Ready(2), poll_once(&mut fut));
+    }
+}
@@ -1,19 +1,100 @@
-use crate::{box_future, DynamicFuture, DynamicFutureVtable};
-use std::{
-    alloc::Layout,
-    future::Future,
-    pin::Pin,
-    sync::atomic::{AtomicUsize, Ordering},
-    task::{Context, Poll},
+use crate::sync::{fence, AtomicU8, Ordering};
+#[cfg(feature = "futures")]
+use crate::{
+    box_stream, boxed_stream::try_box_stream_in, storage::stored_stream_vtable, DynamicStream,
+    DynamicStreamVtable, RecycleFromIter,
 };
+use crate::{
+    boxed_future::zero_sized_future,
+    storage::{stored_future_vtable, PayloadStorage},
+    try_box_future_in, Allocator, DynamicFuture, DynamicFutureVtable, Either, Global, RecycledOnce,
+    RecyclerScope,
+};
+use alloc::alloc::{handle_alloc_error, Layout};
+use core::{fmt, future::Future};
+#[cfg(feature = "futures")]
+use futures_core::Stream;
 
 /// An allocator for `DynamicFuture`s which can reuse storage.
 ///
 /// If the future which was returned by this allocator had been polled to completion
 /// and is dropped, the allocator can reuse the memory allocated for it to return
 /// another future of the same type.
-pub struct RecyclableFutureAllocator {
-    recycled: *const RecyclableFutureHeader,
+///
+/// An allocator which is created via [`RecyclableFutureAllocator::growing`] will
+/// also reuse the memory for futures which are smaller than the previous ones, and
+/// grow its storage if a bigger future needs to be allocated.
+///
+/// The storage for futures is obtained from the allocator `A`, which defaults
+/// to the global allocator.
+///
+/// The storage is only tied to the layout of the futures, but not to their type
+/// or output type. The type of a future is captured by the vtable of the
+/// `DynamicFuture` which is returned for it, and not stored inside the storage.
+/// Futures with different output types can therefore reuse the same storage.
+///
+/// The allocator may be dropped while futures which were returned by it are
+/// still alive. The storage is shared between the allocator and the future
+/// which uses it, and is freed by whichever of them releases it last. The
+/// futures therefore stay valid, and can be polled until they are dropped.
+///
+/// The allocator is `Send` if `A` is `Send`, and can therefore be moved between
+/// threads - e.g. together with the object it is embedded into. When building
+/// with `--cfg async_trait_experiments_single_threaded` the refcount of the
+/// storage is not atomic, and the allocator is never `Send`.
+pub struct RecyclableFutureAllocator<A: Allocator = Global> {
+    recycled: *const RecyclableFutureHeader<A>,
+    /// Whether the storage is reused for smaller futures and grown for bigger ones
+    growing: bool,
+    /// How the storage of a growing allocator grows
+    growth_policy: GrowthPolicy,
+    /// How often the current storage may be reused before it is replaced
+    max_reuses: Option<usize>,
+    /// How often the current storage had been reused
+    reuses: usize,
+    /// The maximum amount of bytes of storage which may be retained
+    byte_budget: Option<usize>,
+    #[cfg(feature = "stats")]
+    stats: RecyclerStats,
+    alloc: A,
+}
+
+// The allocator exclusively owns its reference on the recycled storage. Futures
+// which still use the storage only share the atomic refcount with the allocator,
+// and the allocator only writes into the storage after observing via the
+// refcount that the previous future had been dropped. The storage is released
+// through the allocator `A` which is stored in the storage, and which might
+// therefore be used on the thread where the last reference is dropped.
+//
+// The allocator is not `Sync`, since allocating futures requires `&mut self`.
+#[cfg(not(async_trait_experiments_single_threaded))]
+unsafe impl<A: Allocator + Send> Send for RecyclableFutureAllocator<A> {}
+
+impl<A: Allocator> fmt::Debug for RecyclableFutureAllocator<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("RecyclableFutureAllocator");
+        s.field("growing", &self.growing)
+            .field("cached", &!self.recycled.is_null());
+        if !self.recycled.is_null() {
+            // Only the header is accessed here. The payload might be in use by
+            // a future.
+            let header = unsafe { &*self.recycled };
+            s.field("size", &header.size)
+                .field("refcount", &header.refcount.load(Ordering::Relaxed));
+        }
+        s.finish()
+    }
+}
+
+// Fails to compile if futures which resolve to data that is borrowed for the
+// same lifetime as the future can not be allocated
+#[allow(dead_code)]
+fn assert_borrowed_output<'a>(
+    recycler: &'a mut RecyclableFutureAllocator,
+    data: &'a [u8],
+) -> (DynamicFuture<'a, &'a [u8]>, DynamicFuture<'a, &'a [u8]>) {
+    let boxed = crate::box_future(async move { data });
+    (recycler.allocate(async move { data }), boxed)
 }
 
 impl Default for RecyclableFutureAllocator {
@@ -22,15 +103,11 @@ impl Default for RecyclableFutureAllocator {
     }
 }
 
-impl Drop for RecyclableFutureAllocator {
+impl<A: Allocator> Drop for RecyclableFutureAllocator<A> {
     fn drop(&mut self) {
         if !self.recycled.is_null() {
             unsafe {
-                // Decrement the refcount
-                if (*self.recycled).refcount.fetch_sub(1, Ordering::Relaxed) == 1 {
-                    // Free the memory allocated for the recyclable future
-                    (*(self.recycled as *mut RecyclableFutureHeader)).deallocate();
-                }
+                release_recycler_reference(self.recycled);
             }
         }
     }
@@ -38,178 +115,2896 @@ impl Drop for RecyclableFutureAllocator {
 
 impl RecyclableFutureAllocator {
     pub fn new() -> Self {
+        Self::new_in(Global)
+    }
+
+    /// Creates an allocator which reuses its storage for any future that fits
+    /// into it.
+    ///
+    /// If a future needs to be allocated which is bigger than the current storage,
+    /// the allocator will allocate new storage that fits both the previous and the
+    /// new future. The storage therefore grows monotonically to the biggest
+    /// future that had been seen, and futures with different layouts - e.g.
+    /// produced by different branches of an async method - can share it.
+    pub fn growing() -> Self {
+        Self::growing_in(Global)
+    }
+
+    /// Creates a growing allocator which immediately allocates storage for
+    /// futures of `layout`.
+    ///
+    /// This allows to provide storage for futures whose type can not be named,
+    /// but whose size is known or bounded: All futures which fit into `layout`
+    /// reuse the storage. In comparison to
+    /// [`RecyclableFutureAllocator::reserve_for`] this does not require the type
+    /// of the future.
+    pub fn with_layout(layout: Layout) -> Self {
+        Self::with_layout_in(layout, Global)
+    }
+}
+
+impl<A: Allocator + Clone> RecyclableFutureAllocator<A> {
+    /// Creates an allocator which obtains the storage for futures from `alloc`
+    pub fn new_in(alloc: A) -> Self {
         Self {
-            recycled: std::ptr::null(),
+            recycled: core::ptr::null(),
+            growing: false,
+            growth_policy: GrowthPolicy::Exact,
+            max_reuses: None,
+            reuses: 0,
+            byte_budget: None,
+            #[cfg(feature = "stats")]
+            stats: RecyclerStats::default(),
+            alloc,
         }
     }
 
+    /// Creates a growing allocator which obtains the storage for futures from
+    /// `alloc`.
+    ///
+    /// See [`RecyclableFutureAllocator::growing`] for details.
+    pub fn growing_in(alloc: A) -> Self {
+        Self {
+            recycled: core::ptr::null(),
+            growing: true,
+            growth_policy: GrowthPolicy::Exact,
+            max_reuses: None,
+            reuses: 0,
+            byte_budget: None,
+            #[cfg(feature = "stats")]
+            stats: RecyclerStats::default(),
+            alloc,
+        }
+    }
+
+    /// Creates a growing allocator which immediately allocates storage for
+    /// futures of `layout` via `alloc`.
+    ///
+    /// See [`RecyclableFutureAllocator::with_layout`] for details.
+    pub fn with_layout_in(layout: Layout, alloc: A) -> Self {
+        let mut recycler = Self::growing_in(alloc);
+        recycler.reserve(layout);
+        recycler
+    }
+
+    /// Sets how the storage of a growing allocator grows if a future does not
+    /// fit into it.
+    ///
+    /// The policy is also applied to the first storage which is allocated for
+    /// a future, but not to storage which is reserved explicitly. It has no
+    /// effect on allocators which are not growing. The default policy is
+    /// [`GrowthPolicy::Exact`].
+    pub fn with_growth_policy(mut self, policy: GrowthPolicy) -> Self {
+        self.growth_policy = policy;
+        self
+    }
+
+    /// Limits how often the storage of the allocator is reused.
+    ///
+    /// Once the storage had been reused `max_reuses` times, the next allocation
+    /// replaces it with fresh storage. This allows to periodically rotate the
+    /// memory which backs the futures, e.g. in order to avoid fragmentation.
+    /// The old storage is freed once it is no longer in use by a future.
+    pub fn with_max_reuses(mut self, max_reuses: usize) -> Self {
+        self.max_reuses = Some(max_reuses);
+        self
+    }
+
+    /// Limits the amount of memory which is retained by the allocator.
+    ///
+    /// The allocator will never retain storage whose size - including the
+    /// bookkeeping data in front of the future - exceeds `bytes`. Futures
+    /// which would require bigger storage are boxed instead. If a growing
+    /// allocator would need to grow its storage beyond the budget, the retained
+    /// storage is released and the future is boxed. This prevents a single
+    /// huge future from pinning a lot of memory for the lifetime of the
+    /// allocator.
+    pub fn with_byte_budget(mut self, bytes: usize) -> Self {
+        self.byte_budget = Some(bytes);
+        self
+    }
+
+    /// Returns a reference to the allocator which provides the storage for futures
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
     /// Transforms the passed future into a `DynamicFuture`.
     ///
     /// This action will move the future on the heap and type erase its behavior.
     /// The operation will reuse memory from a previous `allocate` call if possible.
+    #[track_caller]
     pub fn allocate<'a, F, T>(&mut self, fut: F) -> DynamicFuture<'a, T>
     where
         F: Future<Output = T> + 'a,
+        A: 'a,
+    {
+        self.allocate_tracked(fut).0
+    }
+
+    /// Transforms the passed future - which owns all its captures - into a
+    /// `'static` `DynamicFuture`.
+    ///
+    /// This works exactly like [`RecyclableFutureAllocator::allocate`], but
+    /// fixes the lifetime of the returned `Future` to `'static`. The storage of
+    /// the `Future` stays valid if the allocator is dropped first, and the
+    /// `Future` can therefore e.g. be stored in `'static` collections.
+    #[track_caller]
+    pub fn allocate_owned<F, T>(&mut self, fut: F) -> DynamicFuture<'static, T>
+    where
+        F: Future<Output = T> + 'static,
+        A: 'static,
+    {
+        self.allocate(fut)
+    }
+
+    /// Transforms the passed future - which might be `!Unpin` - into a
+    /// `DynamicFuture` which pins it in place.
+    ///
+    /// This works exactly like [`RecyclableFutureAllocator::allocate`], which
+    /// already upholds the pinning invariant for all futures. The method only
+    /// makes the guarantee explicit for self-referential futures like `async`
+    /// blocks which hold references across `.await` points:
+    ///
+    /// - The future is moved into its storage before it is polled for the
+    ///   first time. It is never moved afterwards - only dropped in place.
+    /// - The storage is only reused for another future after the previous
+    ///   future had been dropped, which is observed via the refcount of the
+    ///   storage. A future which is still alive is therefore never overwritten
+    ///   or moved.
+    /// - If the storage is replaced - e.g. because a growing allocator requires
+    ///   bigger storage - the previous storage is only freed once the future
+    ///   which is stored in it is dropped.
+    #[track_caller]
+    pub fn allocate_pinned<'a, F, T>(&mut self, fut: F) -> DynamicFuture<'a, T>
+    where
+        F: Future<Output = T> + 'a,
+        A: 'a,
+    {
+        self.allocate(fut)
+    }
+
+    /// Transforms the passed future into a `DynamicFuture` which resolves to
+    /// the output of `fut` after it got transformed by `f`.
+    ///
+    /// In contrast to [`DynamicFutureExt::map`](crate::DynamicFutureExt::map)
+    /// the future and the closure are fused into a single future, which is
+    /// stored in the storage of this allocator. The storage is therefore sized
+    /// for both of them, and no additional allocation for the combinator is
+    /// required.
+    #[track_caller]
+    pub fn allocate_map<'a, F, G, U>(&mut self, fut: F, f: G) -> DynamicFuture<'a, U>
+    where
+        F: Future + 'a,
+        G: FnOnce(F::Output) -> U + 'a,
+        A: 'a,
+    {
+        self.allocate(async move { f(fut.await) })
+    }
+
+    /// Transforms the future of one of two branches into a `DynamicFuture`.
+    ///
+    /// Both futures are stored as an [`Either`](crate::Either), whose layout
+    /// covers both of them. The storage is therefore sized for the bigger
+    /// future, and is reused for all following calls - regardless of which branch
+    /// produced the future. Allocating the futures of both branches directly
+    /// would only reuse the storage for futures of the same layout, and box
+    /// the others.
+    ///
+    /// The `Either` should be created by a single expression for both
+    /// branches, e.g. via `if`/`else`, since the types of `async` blocks can
+    /// not be named.
+    #[track_caller]
+    pub fn allocate_either<'a, L, R, T>(&mut self, fut: Either<L, R>) -> DynamicFuture<'a, T>
+    where
+        L: Future<Output = T> + 'a,
+        R: Future<Output = T> + 'a,
+        A: 'a,
+    {
+        self.allocate(fut)
+    }
+
+    /// Returns a future which allocates the passed future via this allocator
+    /// once it is polled for the first time.
+    ///
+    /// If the returned future is dropped without being polled, no storage is
+    /// allocated and the storage of the allocator stays available. The
+    /// allocator is borrowed until the returned future is dropped.
+    pub fn allocate_lazy<'r, F>(&'r mut self, fut: F) -> RecycledOnce<'r, F, A>
+    where
+        F: Future + 'r,
+    {
+        RecycledOnce::new(self, fut)
+    }
+
+    /// Returns a `Stream` which calls the closures of `iter` one after another,
+    /// awaits the futures they return, and yields their outputs.
+    ///
+    /// Every future is allocated via this allocator, and is dropped before the
+    /// next closure is called. Since all closures return futures of the same
+    /// type, the storage for the first future is reused for all following ones.
+    /// The allocator is borrowed until the returned stream is dropped.
+    #[cfg(feature = "futures")]
+    pub fn recycle_from_iter<'r, I, F, Fut>(
+        &'r mut self,
+        iter: I,
+    ) -> RecycleFromIter<'r, I::IntoIter, Fut::Output, A>
+    where
+        I: IntoIterator<Item = F>,
+        F: FnOnce() -> Fut,
+        Fut: Future + 'r,
+    {
+        RecycleFromIter::new(self, iter.into_iter())
+    }
+
+    /// Calls `f` with a scope which allocates futures via this allocator, and
+    /// which prevents those futures from outliving the call.
+    ///
+    /// Futures which are returned by [`RecyclableFutureAllocator::allocate`]
+    /// may outlive the allocator, since the storage is shared between them.
+    /// Futures which are allocated via the passed [`RecyclerScope`] are instead
+    /// bound to the scope. Returning them from `f` - directly or inside another
+    /// value - fails to compile. Unless a future is leaked, the storage of the
+    /// allocator is therefore no longer in use once `scope` returns.
+    pub fn scope<'env, R>(
+        &'env mut self,
+        f: impl for<'s> FnOnce(&'s mut RecyclerScope<'s, 'env, A>) -> R,
+    ) -> R {
+        f(&mut RecyclerScope::new(self))
+    }
+
+    /// Transforms the passed future into a `DynamicFuture`, and reports how the
+    /// storage for it was obtained.
+    ///
+    /// This works like [`RecyclableFutureAllocator::allocate`], but additionally
+    /// returns whether memory from a previous `allocate` call was reused.
+    #[track_caller]
+    pub fn allocate_tracked<'a, F, T>(&mut self, fut: F) -> (DynamicFuture<'a, T>, Reused)
+    where
+        F: Future<Output = T> + 'a,
+        A: 'a,
+    {
+        match self.try_allocate_inner(fut) {
+            Ok((fut, reused)) => {
+                self.record(reused);
+                (fut, reused)
+            }
+            Err(fut) => handle_alloc_error(Layout::for_value(&fut)),
+        }
+    }
+
+    /// Transforms the passed future into a `DynamicFuture` without aborting if
+    /// memory can not be allocated.
+    ///
+    /// This works like [`RecyclableFutureAllocator::allocate`]. However if the
+    /// storage for the future can not be allocated, the future is handed back to
+    /// the caller - who can e.g. retry later or run the future inline.
+    #[track_caller]
+    pub fn try_allocate<'a, F, T>(&mut self, fut: F) -> Result<DynamicFuture<'a, T>, F>
+    where
+        F: Future<Output = T> + 'a,
+        A: 'a,
+    {
+        let (fut, reused) = self.try_allocate_inner(fut)?;
+        self.record(reused);
+        Ok(fut)
+    }
+
+    /// Moves the passed future into the storage of this allocator, without
+    /// checking whether the storage can be reused.
+    ///
+    /// This works like [`RecyclableFutureAllocator::allocate`], but skips the
+    /// atomic check whether the previous future which used the storage had been
+    /// dropped, as well as the layout checks. Those conditions are only
+    /// verified in debug builds. This is intended for callers which repeatedly
+    /// allocate futures of the same type, and which know that the previous
+    /// future is gone - e.g. because they awaited it to completion themselves.
+    ///
+    /// Once the storage had been reused as often as configured via
+    /// [`RecyclableFutureAllocator::with_max_reuses`], the future is allocated
+    /// via [`RecyclableFutureAllocator::allocate`] instead, which replaces the
+    /// storage.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that
+    /// - the allocator retains storage - i.e. a future had been allocated via
+    ///   this allocator before, and was stored in recyclable storage.
+    /// - the future which was stored last in this storage had been dropped,
+    ///   and the drop happened before this call.
+    /// - `F` fits into the storage, e.g. because the previous future had the
+    ///   same type.
+    #[track_caller]
+    pub unsafe fn replace<'a, F, T>(&mut self, fut: F) -> DynamicFuture<'a, T>
+    where
+        F: Future<Output = T> + 'a,
+        A: 'a,
+    {
+        if core::mem::size_of::<F>() == 0 {
+            // Zero sized futures don't require any storage
+            self.record(Reused::ZeroSized);
+            return zero_sized_future(fut);
+        }
+
+        if self.max_reuses == Some(self.reuses) {
+            return self.allocate(fut);
+        }
+
+        debug_assert!(!self.recycled.is_null(), "No storage to replace");
+        RecyclableFutureHeader::acquire_unchecked(self.recycled);
+        RecyclableFutureHeader::write_payload(self.recycled, fut);
+        self.reuses += 1;
+        self.record(Reused::Recycled);
+        DynamicFuture::new(
+            self.recycled as *const (),
+            recyclable_future_vtable::<F, T, A>(),
+        )
+    }
+
+    /// Transforms the passed stream into a `DynamicStream`.
+    ///
+    /// The stream is stored in the same storage which is used for futures, and
+    /// the operation will reuse memory from a previous `allocate` or
+    /// `allocate_stream` call if possible. If that storage can not be used,
+    /// the stream is stored in separate storage which is obtained from the
+    /// allocator `A`. Zero sized streams are not stored at all.
+    #[cfg(feature = "futures")]
+    #[track_caller]
+    pub fn allocate_stream<'a, S, T>(&mut self, stream: S) -> DynamicStream<'a, T>
+    where
+        S: Stream<Item = T> + 'a,
+        A: 'a,
+    {
+        if core::mem::size_of::<S>() == 0 {
+            // Zero sized streams don't require any storage, and boxing them
+            // does not allocate
+            self.record(Reused::ZeroSized);
+            return box_stream(stream);
+        }
+
+        match self.try_store(stream) {
+            Ok(Stored::InSlot(header, reused)) => {
+                self.record(reused);
+                unsafe {
+                    DynamicStream::new(header as *const (), recyclable_stream_vtable::<S, T, A>())
+                }
+            }
+            // The storage can not be used for this stream, or new storage can
+            // not be allocated - e.g. because the size of the header and the
+            // stream overflows. Allocate a fresh stream, which does not
+            // require a header.
+            Ok(Stored::Rejected(stream)) | Err(stream) => {
+                match try_box_stream_in(stream, self.alloc.clone()) {
+                    Ok(stream) => {
+                        self.record(Reused::BoxedFallback);
+                        stream
+                    }
+                    Err(stream) => handle_alloc_error(Layout::for_value(&stream)),
+                }
+            }
+        }
+    }
+
+    /// Allocates storage for futures of type `F` ahead of time.
+    ///
+    /// This works like [`RecyclableFutureAllocator::reserve`] for the layout of `F`.
+    pub fn reserve_for<F>(&mut self) {
+        self.reserve(Layout::new::<F>())
+    }
+
+    /// Allocates storage for futures of `layout` ahead of time, so that the
+    /// next allocation of such a future can reuse the storage instead of
+    /// allocating.
+    ///
+    /// If the retained storage can already be used for futures of `layout`,
+    /// this is a no-op. Otherwise the retained storage is replaced - by storage
+    /// which fits both the previous futures and `layout` for a growing
+    /// allocator, and by storage for `layout` otherwise. Storage which is still
+    /// in use by a future is freed once the future is dropped. No storage is
+    /// reserved if it would exceed the byte budget of the allocator, or for
+    /// zero sized layouts - since zero sized futures never use storage.
+    pub fn reserve(&mut self, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+        unsafe {
+            let capacity = if self.recycled.is_null() {
+                layout
+            } else if self.growing {
+                if (*self.recycled).fits_layout(layout) {
+                    return;
+                }
+                (*self.recycled).grown_layout(layout)
+            } else {
+                if (*self.recycled).matches_layout(layout) {
+                    return;
+                }
+                layout
+            };
+            // Storage whose size overflows can not be reserved
+            if self.exceeds_budget(capacity)
+                || RecyclableFutureHeader::<A>::layout_for(capacity).is_err()
+            {
+                return;
+            }
+
+            // The storage is not used by any future yet. Therefore only the
+            // reference from the allocator is accounted for.
+            let header = match RecyclableFutureHeader::allocate(capacity, 1, self.alloc.clone()) {
+                Ok(header) => header,
+                Err(()) => handle_alloc_error(capacity),
+            };
+            self.replace_storage(header);
+        }
+    }
+
+    /// Returns whether the next allocation of a future of type `F` would reuse
+    /// the storage which is retained by this allocator.
+    ///
+    /// This is the case if the storage is compatible with the layout of `F` -
+    /// it matches it exactly, or fits it for a growing allocator - and is no
+    /// longer in use by a future. Zero sized futures never reuse storage.
+    ///
+    /// The result is only advisory: A future which still uses the storage might
+    /// be dropped concurrently on another thread, in which case the storage
+    /// becomes available right after this method returned `false`.
+    pub fn can_reuse_for<F>(&self) -> bool {
+        let layout = Layout::new::<F>();
+        if layout.size() == 0 || self.recycled.is_null() || self.max_reuses == Some(self.reuses) {
+            return false;
+        }
+
+        // Only the header is accessed here. The payload might be in use by
+        // a future.
+        let header = unsafe { &*self.recycled };
+        let compatible = if self.growing {
+            header.fits_layout(layout)
+        } else {
+            header.matches_layout(layout)
+        };
+        compatible && header.refcount.load(Ordering::Relaxed) == FREE_REFCOUNT
+    }
+
+    /// Returns the layout of the futures which fit into the storage that is
+    /// retained by this allocator, or `None` if no storage is retained.
+    ///
+    /// The layout does not include the header in front of the futures. Together
+    /// with [`future_layout`] this allows to predict whether a future will reuse
+    /// the storage or be boxed: A non-growing allocator requires futures whose
+    /// layout matches exactly, while a growing allocator reuses the storage for
+    /// all futures which fit into it.
+    pub fn cached_layout(&self) -> Option<Layout> {
+        if self.recycled.is_null() {
+            return None;
+        }
+        // Only the header is accessed here. The payload might be in use by
+        // a future.
+        Some(unsafe { (*self.recycled).capacity() })
+    }
+
+    /// Returns the refcount of the storage which is retained by this allocator,
+    /// or `None` if no storage is retained.
+    ///
+    /// The refcount is 1 while the storage is only referenced by the allocator,
+    /// and 2 while it is also in use by a future. The value is read with
+    /// `Relaxed` ordering, and might be outdated if the future is dropped
+    /// concurrently on another thread.
+    #[cfg(feature = "unstable-internals")]
+    pub fn current_refcount(&self) -> Option<usize> {
+        if self.recycled.is_null() {
+            return None;
+        }
+        let refcount = unsafe { (*self.recycled).refcount.load(Ordering::Relaxed) };
+        Some(refcount as usize)
+    }
+
+    /// Frees the storage which is retained by this allocator.
+    ///
+    /// This is only possible if the storage is no longer in use by a future.
+    /// Returns `true` if the storage was freed, and `false` if no storage was
+    /// retained or if it is still in use. In the latter case the storage is
+    /// kept.
+    pub fn clear(&mut self) -> bool {
+        if self.recycled.is_null() {
+            return false;
+        }
+
+        unsafe {
+            // Futures which were returned by this allocator only ever decrement
+            // the refcount. A refcount of 1 therefore means that the storage
+            // can no longer be accessed by any future.
+            if (*self.recycled).refcount.load(Ordering::Acquire) != FREE_REFCOUNT {
+                return false;
+            }
+
+            RecyclableFutureHeader::deallocate(self.recycled as *mut RecyclableFutureHeader<A>);
+        }
+        self.recycled = core::ptr::null();
+        self.reuses = 0;
+        true
+    }
+
+    /// Returns statistics about how the storage for the futures which were
+    /// allocated by this allocator was obtained.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> RecyclerStats {
+        self.stats
+    }
+
+    #[cfg_attr(not(feature = "stats"), allow(unused_variables))]
+    fn record(&mut self, reused: Reused) {
+        #[cfg(feature = "stats")]
+        self.stats.record(reused);
+        #[cfg(feature = "metrics")]
+        emit_metrics(reused);
+    }
+
+    #[track_caller]
+    fn try_allocate_inner<'a, F, T>(&mut self, fut: F) -> Result<(DynamicFuture<'a, T>, Reused), F>
+    where
+        F: Future<Output = T> + 'a,
+        A: 'a,
     {
+        if core::mem::size_of::<F>() == 0 {
+            // Zero sized futures don't require any storage
+            return Ok((zero_sized_future(fut), Reused::ZeroSized));
+        }
+
+        match self.try_store(fut) {
+            Ok(Stored::InSlot(header, reused)) => {
+                let fut = unsafe {
+                    DynamicFuture::new(header as *const (), recyclable_future_vtable::<F, T, A>())
+                };
+                Ok((fut, reused))
+            }
+            // The storage can not be used for this future, or new storage can
+            // not be allocated - e.g. because the size of the header and the
+            // future overflows. Allocate a fresh future, which does not
+            // require a header.
+            Ok(Stored::Rejected(fut)) | Err(fut) => Ok((
+                try_box_future_in(fut, self.alloc.clone())?,
+                Reused::BoxedFallback,
+            )),
+        }
+    }
+
+    /// Replaces the storage which is retained by this allocator with `header`.
+    /// If `header` is null, no storage is retained afterwards.
+    ///
+    /// The previous storage is freed once it is no longer in use by a future.
+    unsafe fn replace_storage(&mut self, header: *const RecyclableFutureHeader<A>) {
+        if !self.recycled.is_null() {
+            release_recycler_reference(self.recycled);
+        }
+        self.recycled = header;
+        self.reuses = 0;
+    }
+
+    /// Returns whether storage which can hold payloads of up to the `capacity`
+    /// layout exceeds the byte budget of the allocator
+    fn exceeds_budget(&self, capacity: Layout) -> bool {
+        match self.byte_budget {
+            Some(budget) => match RecyclableFutureHeader::<A>::layout_for(capacity) {
+                Ok((layout, _)) => layout.size() > budget,
+                Err(()) => true,
+            },
+            None => false,
+        }
+    }
+
+    /// Moves `payload` into fresh storage which can hold payloads of up to the
+    /// `capacity` layout, and retains this storage instead of the current one.
+    ///
+    /// If the storage would exceed the byte budget, the current storage is
+    /// released and the payload is handed back via `Stored::Rejected`.
+    unsafe fn try_store_in_new_storage<P>(
+        &mut self,
+        payload: P,
+        capacity: Layout,
+    ) -> Result<Stored<P, A>, P> {
+        if self.exceeds_budget(capacity) {
+            self.replace_storage(core::ptr::null());
+            return Ok(Stored::Rejected(payload));
+        }
+
+        // Since we retain a reference to this storage it needs to have
+        // a refcount of 2
+        let header = RecyclableFutureHeader::new_with_payload(
+            payload,
+            capacity,
+            RETAINED_REFCOUNT,
+            self.alloc.clone(),
+        )?;
+        self.replace_storage(header);
+        Ok(Stored::InSlot(header, Reused::FreshAllocation))
+    }
+
+    /// Moves `payload` into the storage which is retained by this allocator.
+    ///
+    /// If the storage can not be used for the payload - because it is still in
+    /// use or because its layout is not compatible - the payload is handed back
+    /// via `Stored::Rejected`. If new storage would be required but can not be
+    /// allocated, the payload is handed back as an error.
+    #[track_caller]
+    fn try_store<P>(&mut self, payload: P) -> Result<Stored<P, A>, P> {
         unsafe {
+            let layout = Layout::for_value(&payload);
             if self.recycled.is_null() {
-                // Since we retain a reference to this future it needs to have
-                // a refcount of 2
-                let fut = new_recyclable_future(fut, 2);
-                self.recycled = fut.ptr() as *const RecyclableFutureHeader;
-                return fut;
+                let capacity = if self.growing {
+                    match self.growth_policy.capacity_for::<A>(layout) {
+                        Some(capacity) => capacity,
+                        None => return Ok(Stored::Rejected(payload)),
+                    }
+                } else {
+                    layout
+                };
+                return self.try_store_in_new_storage(payload, capacity);
             }
 
             // Check whether the layout is compatible with the layout of the
             // backing storage.
-            // We don't worry about the alignment - since the alignment of the
-            // header should fit everything else.
-            if (*self.recycled).size != Layout::for_value(&fut).size() {
-                return box_future(fut);
-            }
-
-            // If the current futures storage is no longer in use we can reuse
-            // it for the next future.
-            // Otherwise we will allocate a detached future
-            match (*self.recycled).refcount.compare_exchange(
-                1,
-                2,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => {
-                    std::ptr::write((*self.recycled).payload_addr_mut(), fut);
-                    let header = self.recycled;
-                    DynamicFuture::new(header as *const (), recyclable_future_vtable::<F, T>())
-                }
-                Err(2) => {
-                    // The future is still in use.
-                    // Allocate a fresh future
-                    box_future(fut)
+            if self.growing {
+                if !(*self.recycled).fits_layout(layout) {
+                    // Replace the storage with a bigger one. If the old storage
+                    // is still in use by a future, it will be freed once the future
+                    // is dropped.
+                    let grown = (*self.recycled).grown_layout(layout);
+                    let capacity = match self.growth_policy.capacity_for::<A>(grown) {
+                        Some(capacity) => capacity,
+                        // Keep the current storage for the futures which fit
+                        None => return Ok(Stored::Rejected(payload)),
+                    };
+                    return self.try_store_in_new_storage(payload, capacity);
                 }
-                Err(refcount) => panic!("Invalid future refcount of {}", refcount),
+            } else if !(*self.recycled).matches_layout(layout) {
+                return Ok(Stored::Rejected(payload));
+            }
+
+            if self.max_reuses == Some(self.reuses) {
+                // The storage had been reused often enough. Replace it with
+                // fresh storage of the same capacity.
+                let capacity = (*self.recycled).grown_layout(layout);
+                return self.try_store_in_new_storage(payload, capacity);
+            }
+
+            // If the current storage is no longer in use we can reuse
+            // it for the next payload.
+            if RecyclableFutureHeader::try_acquire(self.recycled) {
+                RecyclableFutureHeader::write_payload(self.recycled, payload);
+                self.reuses += 1;
+                Ok(Stored::InSlot(self.recycled, Reused::Recycled))
+            } else {
+                Ok(Stored::Rejected(payload))
             }
         }
     }
 }
 
-unsafe fn drop_recyclable_future<F>(ptr: *const ()) {
-    let header = ptr as *const RecyclableFutureHeader;
-    // Call the `drop` on the `Future` stored inside the header
-    let data: *mut F = (*header).payload_addr_mut::<F>();
-    std::ptr::drop_in_place(data);
+/// The result of moving a payload into the storage of a recycler
+enum Stored<P, A> {
+    /// The payload was moved into the storage which is referenced by the header
+    InSlot(*const RecyclableFutureHeader<A>, Reused),
+    /// The storage can not be used for the payload
+    Rejected(P),
+}
 
-    // Decrement the refcount and free storage if not utilized anymore
-    if (*header).refcount.fetch_sub(1, Ordering::Release) == 1 {
-        // Deallocate header and storage
-        (*(header as *mut RecyclableFutureHeader)).deallocate();
+/// Describes how the storage of a growing [`RecyclableFutureAllocator`] grows
+/// if a future does not fit into it.
+///
+/// The policy is configured via [`RecyclableFutureAllocator::with_growth_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrowthPolicy {
+    /// The storage grows to exactly fit both the previous futures and the new
+    /// future
+    Exact,
+    /// The size of the storage is rounded up to the next power of two. This
+    /// reduces the amount of reallocations if the futures grow gradually.
+    NextPowerOfTwo,
+    /// The storage grows exactly, but never beyond the given amount of bytes.
+    /// Like the budget of [`RecyclableFutureAllocator::with_byte_budget`], the
+    /// amount includes the bookkeeping data in front of the future. Futures
+    /// which would require bigger storage are boxed, and the current storage
+    /// is retained for the futures which fit into it.
+    Capped(usize),
+}
+
+impl GrowthPolicy {
+    /// Returns the capacity of the storage for payloads of up to `required`,
+    /// or `None` if such payloads should be boxed.
+    ///
+    /// The capacity is stored behind the header of storage from the allocator
+    /// `A`, which counts towards the cap.
+    fn capacity_for<A: Allocator>(self, required: Layout) -> Option<Layout> {
+        match self {
+            GrowthPolicy::Exact => Some(required),
+            GrowthPolicy::NextPowerOfTwo => Some(
+                required
+                    .size()
+                    .checked_next_power_of_two()
+                    .and_then(|size| Layout::from_size_align(size, required.align()).ok())
+                    // Fall back to the exact size if rounding up overflows
+                    .unwrap_or(required),
+            ),
+            GrowthPolicy::Capped(max) => match RecyclableFutureHeader::<A>::layout_for(required) {
+                Ok((layout, _)) if layout.size() <= max => Some(required),
+                _ => None,
+            },
+        }
     }
 }
 
-unsafe fn poll_recyclable_future<T, F: Future<Output = T>>(
-    ptr: *const (),
-    cx: &mut Context<'_>,
-) -> Poll<T> {
-    let header = ptr as *const RecyclableFutureHeader;
-    let fut: &mut F = &mut *((*header).payload_addr_mut::<F>());
-    let pinned = Pin::new_unchecked(fut);
-    pinned.poll(cx)
+/// Describes how the storage for a future which was returned by
+/// [`RecyclableFutureAllocator::allocate_tracked`] was obtained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reused {
+    /// The storage of a previously allocated future was reused
+    Recycled,
+    /// New storage was allocated, and is retained by the allocator in order to
+    /// reuse it for following allocations.
+    FreshAllocation,
+    /// The storage of the allocator could not be used for the future - either
+    /// because it was still in use or because the layout did not match.
+    /// The future was allocated via `box_future_in`, or the stream was stored
+    /// in storage which is not retained by the allocator.
+    BoxedFallback,
+    /// The future is zero sized and did not require any storage
+    ZeroSized,
+}
+
+/// Statistics about the allocations which were performed by a
+/// [`RecyclableFutureAllocator`].
+#[cfg(feature = "stats")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RecyclerStats {
+    /// The total amount of allocations
+    pub allocations: usize,
+    /// The amount of allocations which reused the storage of a previous future
+    pub recycled: usize,
+    /// The amount of allocations which allocated new storage for the allocator
+    pub fresh_allocations: usize,
+    /// The amount of allocations which fell back to `box_future_in`
+    pub boxed_fallbacks: usize,
+    /// The amount of allocations of zero sized futures, which did not require
+    /// any storage
+    pub zero_sized: usize,
 }
 
-fn recyclable_future_vtable<'a, F: Future<Output = T> + 'a, T>() -> &'a DynamicFutureVtable<T> {
-    &DynamicFutureVtable {
-        drop_fn: drop_recyclable_future::<F>,
-        poll_fn: poll_recyclable_future::<T, F>,
+#[cfg(feature = "stats")]
+impl RecyclerStats {
+    fn record(&mut self, reused: Reused) {
+        self.allocations += 1;
+        match reused {
+            Reused::Recycled => self.recycled += 1,
+            Reused::FreshAllocation => self.fresh_allocations += 1,
+            Reused::BoxedFallback => self.boxed_fallbacks += 1,
+            Reused::ZeroSized => self.zero_sized += 1,
+        }
     }
 }
 
-/// Creates a fresh recyclable future by allocating storage for it on the heap
-pub fn new_recyclable_future<'a, F, T>(fut: F, initial_refcount: usize) -> DynamicFuture<'a, T>
-where
-    F: Future<Output = T> + 'a,
-{
-    unsafe {
-        let header =
-            RecyclableFutureHeader::allocate(Layout::for_value(&fut), initial_refcount).unwrap();
-        std::ptr::write((*header).payload_addr_mut(), fut);
-        DynamicFuture::new(header as *const (), recyclable_future_vtable::<F, T>())
+/// Returns the layout of the future `fut`.
+///
+/// This is the layout which is compared against the storage of an allocator -
+/// see [`RecyclableFutureAllocator::cached_layout`]. It allows to query the
+/// layout of futures whose type can not be named, like `async` blocks.
+pub fn future_layout<F: Future>(fut: &F) -> Layout {
+    Layout::for_value(fut)
+}
+
+/// Increments the counter of the `metrics` facade which corresponds to how the
+/// storage for a future was obtained.
+///
+/// The counters are `recycler.reuse`, `recycler.fresh_alloc` and
+/// `recycler.boxed_fallback`. Zero sized futures are not counted.
+#[cfg(feature = "metrics")]
+fn emit_metrics(reused: Reused) {
+    match reused {
+        Reused::Recycled => metrics::counter!("recycler.reuse").increment(1),
+        Reused::FreshAllocation => metrics::counter!("recycler.fresh_alloc").increment(1),
+        Reused::BoxedFallback => metrics::counter!("recycler.boxed_fallback").increment(1),
+        Reused::ZeroSized => {}
     }
 }
 
-/// A header stored in front of recyclable `Future`s on the heap.
+/// Describes the recyclable storage which backs a future.
 ///
-/// The location of a heap allocated Future can be determined by the location
-/// of its header.
-#[derive(Debug)]
-struct RecyclableFutureHeader {
-    /// The amount of active references to this memory location.
-    /// Only up to 2 references can exist:
-    /// 1. The reference from the `Future`
-    /// 2. The reference from the `RecyclableFutureAllocator`
-    refcount: AtomicUsize,
-    /// The size of the `Future` which is stored behind the header according
-    /// to its `Layout`
-    size: usize,
+/// This allows tooling - e.g. heap analyzers - to locate the future inside the
+/// storage. The storage starts with a header, which is followed by the future
+/// at `payload_offset`.
+///
+/// The header has a stable layout. Its fields can be read at the offsets which
+/// are published as the associated constants of this type. The offsets apply
+/// to storage of the global allocator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecyclableFutureInfo {
+    /// The size of the header in front of the future
+    pub header_size: usize,
+    /// The offset of the future from the start of the storage. This is bigger
+    /// than `header_size` if the future is aligned to more than the header.
+    pub payload_offset: usize,
+    /// The size which is available for the future. This can be bigger than the
+    /// size of the future if the storage is reused for smaller futures.
+    pub payload_size: usize,
+    /// The amount of references to the storage. This is 2 while the storage is
+    /// used by a future and retained by an allocator. Storage which was created
+    /// via `new_recyclable_future_with_refcount` might have more references.
+    pub refcount: usize,
 }
 
-impl RecyclableFutureHeader {
-    /// Allocates space for a `RecyclableFutureHeader` and a payload which requires
-    /// the space of `data_layout` on the heap.
-    unsafe fn allocate(
-        data_layout: Layout,
-        initial_refcount: usize,
-    ) -> Result<*mut RecyclableFutureHeader, ()> {
-        // We shouldn't have any alignment issues, since `RecyclableFutureHeader`
-        // is aligned to `usize` - which should cover what everything else needs.
-        // But let's do a debug check.
-        // Not having to store the alignment will save 8 bytes here.
-        debug_assert!(
-            Layout::new::<RecyclableFutureHeader>().align() >= data_layout.align()
-                && Layout::new::<RecyclableFutureHeader>().align() % data_layout.align() == 0
-        );
+impl RecyclableFutureInfo {
+    /// The offset of the refcount - a `u8` - from the start of the header
+    pub const REFCOUNT_OFFSET: usize = core::mem::offset_of!(RecyclableFutureHeader, refcount);
+    /// The offset of the base 2 logarithm of the alignment of the payload - a
+    /// `u8` - from the start of the header
+    pub const ALIGN_LOG2_OFFSET: usize = core::mem::offset_of!(RecyclableFutureHeader, align_log2);
+    /// The offset of the size which is available for the payload - a `usize` -
+    /// from the start of the header
+    pub const SIZE_OFFSET: usize = core::mem::offset_of!(RecyclableFutureHeader, size);
 
-        let combined_layout = RecyclableFutureHeader::layout_for_size(data_layout.size())?;
-        let alloc_res = std::alloc::alloc(combined_layout) as *mut RecyclableFutureHeader;
-        if alloc_res.is_null() {
-            return Err(());
+    /// Reads the information about the recyclable storage which backs `fut`.
+    ///
+    /// # Safety
+    ///
+    /// `fut` must be backed by recyclable storage which was obtained from the
+    /// global allocator. This is the case for futures which were returned by a
+    /// `RecyclableFutureAllocator<Global>` that reported
+    /// [`Reused::Recycled`] or [`Reused::FreshAllocation`] for them. The
+    /// fallback of allocators to boxed futures is not backed by recyclable
+    /// storage.
+    pub unsafe fn of<T>(fut: &DynamicFuture<'_, T>) -> Self {
+        let header = fut.ptr() as *const RecyclableFutureHeader;
+        RecyclableFutureInfo {
+            header_size: core::mem::size_of::<RecyclableFutureHeader>(),
+            payload_offset: RecyclableFutureHeader::<Global>::payload_offset((*header).align()),
+            payload_size: (*header).size,
+            refcount: (*header).refcount.load(Ordering::Relaxed) as usize,
         }
+    }
+}
 
-        let result = &mut *alloc_res;
-        // Storing the initial refcount is not required to be atomic since
-        // the value is not visible to other threads at this time.
-        result.refcount = AtomicUsize::new(initial_refcount);
-        result.size = data_layout.size();
+#[cfg(feature = "unstable-internals")]
+impl<'a, T> DynamicFuture<'a, T> {
+    /// Returns a mutable reference to the future of type `F` which is stored in
+    /// the recyclable storage that backs this `DynamicFuture`.
+    ///
+    /// This allows to inspect or modify the state of the future between polls,
+    /// e.g. for debugging.
+    ///
+    /// # Safety
+    ///
+    /// The `DynamicFuture` must be backed by recyclable storage which was
+    /// obtained from the global allocator - see [`RecyclableFutureInfo::of`] -
+    /// and the future which is stored in it must be of type `F`. Since the
+    /// future is pinned, it must not be moved out of the returned reference.
+    pub unsafe fn payload_mut<F>(&mut self) -> &mut F {
+        let header = self.ptr() as *const RecyclableFutureHeader;
+        &mut *RecyclableFutureHeader::payload_addr_mut::<F>(header)
+    }
+}
 
-        Ok(alloc_res)
+/// Tries to move the future into the storage which is referenced by `header`.
+///
+/// This requires the storage no longer to be in use by a previous future.
+/// If the storage can not be reused, the future is handed back to the caller.
+///
+/// The caller must hold a reference on the storage, and must have checked that
+/// the future fits into the storage.
+#[track_caller]
+pub(crate) unsafe fn reuse_recyclable_future<'a, F, T, A>(
+    header: *const RecyclableFutureHeader<A>,
+    fut: F,
+) -> Result<DynamicFuture<'a, T>, F>
+where
+    F: Future<Output = T> + 'a,
+    A: Allocator + 'a,
+{
+    // If the current futures storage is no longer in use we can reuse
+    // it for the next future.
+    if !RecyclableFutureHeader::try_acquire(header) {
+        return Err(fut);
+    }
+
+    RecyclableFutureHeader::write_payload(header, fut);
+    Ok(DynamicFuture::new(
+        header as *const (),
+        recyclable_future_vtable::<F, T, A>(),
+    ))
+}
+
+/// Releases the reference which a recycler holds on the storage which is
+/// referenced by `header`, and frees the storage if no future uses it anymore.
+pub(crate) unsafe fn release_recycler_reference<A: Allocator>(
+    header: *const RecyclableFutureHeader<A>,
+) {
+    RecyclableFutureHeader::release(header);
+}
+
+// A payload in recyclable storage holds a reference on the storage.
+//
+// The reference is released once the payload had been dropped - also if
+// dropping the payload panics. The storage can then be reused for other
+// payloads, since its previous content is never accessed again. A payload whose
+// `poll` had panicked is still a valid value, and is dropped like any other
+// payload.
+impl<P, A: Allocator> PayloadStorage<P> for RecyclableFutureHeader<A> {
+    unsafe fn payload(ptr: *const ()) -> *mut P {
+        Self::payload_addr_mut::<P>(ptr as *const Self)
     }
 
-    fn layout_for_size(data_size: usize) -> Result<Layout, ()> {
-        let layout = Layout::new::<RecyclableFutureHeader>();
-        let total_size = layout.size().checked_add(data_size).ok_or(())?;
-        let combined_layout = Layout::from_size_align(total_size, layout.align()).map_err(|e| {
-            eprintln!("Layout error: {}", e);
-            ()
-        })?;
-        Ok(combined_layout)
+    unsafe fn release(ptr: *const ()) {
+        // Decrement the refcount and free storage if not utilized anymore
+        Self::release(ptr as *const Self);
     }
 
-    unsafe fn deallocate(&mut self) {
-        if let Ok(layout) = RecyclableFutureHeader::layout_for_size(self.size) {
-            std::alloc::dealloc(self as *mut RecyclableFutureHeader as *mut u8, layout);
-        }
+    /// Returns the size of the recyclable storage, which might be bigger than
+    /// the size of the payload if the storage is reused for smaller payloads.
+    unsafe fn size(ptr: *const ()) -> usize {
+        (*(ptr as *const Self)).size
     }
 
-    /// Returns the address of the payload section which is allocated behind
-    /// the header.
-    unsafe fn payload_addr<T>(&self) -> *const T {
-        let mut end_addr = self as *const RecyclableFutureHeader as usize;
-        end_addr += std::mem::size_of::<RecyclableFutureHeader>();
-        end_addr as *const T
+    #[cfg_attr(not(feature = "assert-unmoved"), allow(unused_variables))]
+    unsafe fn before_poll(ptr: *const (), payload: *mut P) {
+        #[cfg(feature = "assert-unmoved")]
+        Self::assert_unmoved(ptr as *const Self, payload as *const ());
     }
 
-    /// Returns the address of the payload section which is allocated behind
-    /// the header.
-    unsafe fn payload_addr_mut<T>(&self) -> *mut T {
-        self.payload_addr::<T>() as *mut T
+    #[cfg(debug_assertions)]
+    unsafe fn polling<'s>(ptr: *const ()) -> Option<&'s core::cell::Cell<bool>> {
+        Some(&(*(ptr as *const Self)).polling)
+    }
+}
+
+fn recyclable_future_vtable<'a, F, T, A>() -> &'a DynamicFutureVtable<T>
+where
+    F: Future<Output = T> + 'a,
+    A: Allocator + 'a,
+{
+    stored_future_vtable::<F, T, RecyclableFutureHeader<A>>()
+}
+
+#[cfg(feature = "futures")]
+fn recyclable_stream_vtable<'a, S, T, A>() -> &'a DynamicStreamVtable<T>
+where
+    S: Stream<Item = T> + 'a,
+    A: Allocator + 'a,
+{
+    stored_stream_vtable::<S, T, RecyclableFutureHeader<A>>()
+}
+
+/// Creates a fresh recyclable future by allocating storage which can hold
+/// payloads of up to the `capacity` layout via `alloc`.
+///
+/// If the storage can not be allocated the future is handed back to the caller.
+pub(crate) fn try_new_recyclable_future<'a, F, T, A>(
+    fut: F,
+    capacity: Layout,
+    initial_refcount: u8,
+    alloc: A,
+) -> Result<DynamicFuture<'a, T>, F>
+where
+    F: Future<Output = T> + 'a,
+    A: Allocator + 'a,
+{
+    unsafe {
+        let header =
+            RecyclableFutureHeader::new_with_payload(fut, capacity, initial_refcount, alloc)?;
+        Ok(DynamicFuture::new(
+            header as *const (),
+            recyclable_future_vtable::<F, T, A>(),
+        ))
+    }
+}
+
+/// Creates a fresh recyclable future whose storage is retained by a recycler,
+/// and returns it together with the header of the storage.
+///
+/// If the storage can not be allocated - e.g. because the size of the header
+/// and the future overflows - the future is boxed instead, since boxing it
+/// does not require a header. No header is returned in this case.
+pub(crate) fn new_retained_recyclable_future<'a, F, T, A>(
+    fut: F,
+    capacity: Layout,
+    alloc: A,
+) -> (
+    DynamicFuture<'a, T>,
+    Option<*const RecyclableFutureHeader<A>>,
+)
+where
+    F: Future<Output = T> + 'a,
+    A: Allocator + Clone + 'a,
+{
+    // Since the recycler retains a reference to the storage it needs to have
+    // a refcount of 2
+    match try_new_recyclable_future(fut, capacity, RETAINED_REFCOUNT, alloc.clone()) {
+        Ok(fut) => {
+            let header = fut.ptr() as *const RecyclableFutureHeader<A>;
+            (fut, Some(header))
+        }
+        Err(fut) => (crate::box_future_in(fut, alloc), None),
+    }
+}
+
+/// Stores `fut` in fresh recyclable storage of the global allocator, whose
+/// refcount starts out at `refcount`.
+///
+/// The returned future holds one of the references, and releases it once it
+/// is dropped. The remaining `refcount - 1` references belong to the caller,
+/// which can use them to build handles that share the storage with the
+/// future. Each of them must be released via [`release_recyclable_reference`].
+/// While more than one reference exists the storage counts as being in use.
+/// It is freed once all references - including the one of the future - had
+/// been released. References which are never released leak the storage.
+///
+/// If the storage can not be allocated the future is handed back to the caller.
+///
+/// # Panics
+///
+/// Panics if `refcount` is 0, since the future always holds a reference.
+#[cfg(feature = "unstable-internals")]
+pub fn new_recyclable_future_with_refcount<'a, F, T>(
+    fut: F,
+    refcount: u8,
+) -> Result<DynamicFuture<'a, T>, F>
+where
+    F: Future<Output = T> + 'a,
+{
+    assert!(
+        refcount != 0,
+        "Recyclable storage requires a refcount of at least 1"
+    );
+    let layout = Layout::for_value(&fut);
+    try_new_recyclable_future(fut, layout, refcount, Global)
+}
+
+/// Releases a reference on the recyclable storage at `storage`, which had been
+/// handed out by [`new_recyclable_future_with_refcount`].
+///
+/// The storage is freed if this was the last reference.
+///
+/// # Safety
+///
+/// `storage` must be the pointer of a future which was returned by
+/// [`new_recyclable_future_with_refcount`] - see [`DynamicFuture::ptr`] - and
+/// the caller must own one of the additional references on its storage. The
+/// storage must no longer be accessed via this reference afterwards.
+#[cfg(feature = "unstable-internals")]
+pub unsafe fn release_recyclable_reference(storage: *const ()) {
+    RecyclableFutureHeader::<Global>::release(storage as *const RecyclableFutureHeader);
+}
+
+/// Panics because `operation` found recyclable storage with an invalid
+/// refcount.
+///
+/// This reports the location of the call into the allocator which performed
+/// the operation, since the functions on the way are `#[track_caller]`.
+#[cold]
+#[track_caller]
+fn invalid_refcount(operation: &str, refcount: u8) -> ! {
+    panic!(
+        "Invalid future refcount of {} while {}",
+        refcount, operation
+    )
+}
+
+/// The refcount of storage which is only referenced by the holder of the last
+/// reference - typically the allocator which retains it. The storage is free
+/// for a new payload, while any higher refcount means that it is in use.
+const FREE_REFCOUNT: u8 = 1;
+
+/// The refcount of fresh storage which is retained by an allocator and used
+/// by the first payload
+pub(crate) const RETAINED_REFCOUNT: u8 = FREE_REFCOUNT + 1;
+
+/// A header stored in front of recyclable `Future`s on the heap.
+///
+/// The location of a heap allocated Future can be determined by the location
+/// of its header.
+///
+/// The header is `repr(C)`, so that external tooling can read its fields at the
+/// offsets which are published by [`RecyclableFutureInfo`].
+#[derive(Debug)]
+#[repr(C)]
+pub(crate) struct RecyclableFutureHeader<A = Global> {
+    /// The amount of active references to this memory location.
+    /// The references which are held by this crate are:
+    /// 1. The reference from the `Future`
+    /// 2. The reference from the `RecyclableFutureAllocator`
+    ///
+    /// Storage which is created via `new_recyclable_future_with_refcount` can
+    /// start out with additional references, which are held by the creator.
+    /// The storage is only reused for a new payload if the refcount is exactly
+    /// [`FREE_REFCOUNT`], which means the holder of the last reference has
+    /// exclusive access. Any higher refcount is treated as the storage being in
+    /// use. The storage is freed once the refcount drops to 0.
+    ///
+    /// Since there are only ever a few references a single byte is sufficient.
+    refcount: AtomicU8,
+    /// The base 2 logarithm of the alignment of the storage behind the header.
+    ///
+    /// Storing the logarithm instead of the alignment allows to place it next
+    /// to the refcount, which keeps the header at 2 words.
+    align_log2: u8,
+    /// Whether the current payload is being polled, which is used to detect
+    /// reentrant polls.
+    ///
+    /// This is only accessed by the holder of the payload. It occupies the
+    /// padding behind `align_log2`, and therefore does not grow the header.
+    #[cfg(debug_assertions)]
+    polling: core::cell::Cell<bool>,
+    /// The size of the storage behind the header. This is the size of the
+    /// `Future` which is stored behind the header according to its `Layout`,
+    /// unless the storage is reused for smaller futures.
+    size: usize,
+    /// The allocator which provided the storage, and which is used to free it
+    alloc: A,
+    /// The address at which the current payload was polled for the first time,
+    /// or 0 if it had not been polled yet.
+    ///
+    /// This is only accessed by the holder of the payload.
+    #[cfg(feature = "assert-unmoved")]
+    polled_at: core::cell::Cell<usize>,
+}
+
+// The header for the global allocator should only occupy 2 words in front of
+// the payload. The atomics of loom and the address which is tracked by the
+// `assert-unmoved` feature require additional space.
+#[cfg(not(any(loom, feature = "assert-unmoved")))]
+const _: () =
+    assert!(core::mem::size_of::<RecyclableFutureHeader>() == 2 * core::mem::size_of::<usize>());
+
+impl<A: Allocator> RecyclableFutureHeader<A> {
+    /// Allocates space for a `RecyclableFutureHeader` and a payload which requires
+    /// the space of `data_layout` via `alloc`.
+    unsafe fn allocate(
+        data_layout: Layout,
+        initial_refcount: u8,
+        alloc: A,
+    ) -> Result<*mut RecyclableFutureHeader<A>, ()> {
+        let (combined_layout, payload_offset) = Self::layout_for(data_layout)?;
+        debug_assert_eq!(payload_offset, Self::payload_offset(data_layout.align()));
+
+        let alloc_res = alloc.allocate(combined_layout) as *mut RecyclableFutureHeader<A>;
+        if alloc_res.is_null() {
+            return Err(());
+        }
+
+        // Storing the initial refcount is not required to be atomic since
+        // the value is not visible to other threads at this time.
+        core::ptr::write(
+            alloc_res,
+            RecyclableFutureHeader {
+                refcount: AtomicU8::new(initial_refcount),
+                // Alignments are powers of two, which are below 2^64
+                align_log2: data_layout.align().trailing_zeros() as u8,
+                #[cfg(debug_assertions)]
+                polling: core::cell::Cell::new(false),
+                size: data_layout.size(),
+                alloc,
+                #[cfg(feature = "assert-unmoved")]
+                polled_at: core::cell::Cell::new(0),
+            },
+        );
+
+        Ok(alloc_res)
+    }
+
+    /// Allocates storage which can hold payloads of up to the `capacity` layout
+    /// via `alloc`, and moves `payload` into it.
+    ///
+    /// If the storage can not be allocated the payload is handed back to the caller.
+    unsafe fn new_with_payload<P>(
+        payload: P,
+        capacity: Layout,
+        initial_refcount: u8,
+        alloc: A,
+    ) -> Result<*const RecyclableFutureHeader<A>, P> {
+        let header = match Self::allocate(capacity, initial_refcount, alloc) {
+            Ok(header) => header,
+            Err(()) => return Err(payload),
+        };
+        Self::write_payload(header, payload);
+        Ok(header)
+    }
+
+    /// Tries to acquire storage which is no longer in use by a previous payload,
+    /// in order to store a new payload inside it.
+    ///
+    /// The caller must hold a reference on the storage.
+    #[track_caller]
+    unsafe fn try_acquire(header: *const RecyclableFutureHeader<A>) -> bool {
+        // Acquiring the storage adds the reference of the new payload
+        match (*header).refcount.compare_exchange(
+            FREE_REFCOUNT,
+            FREE_REFCOUNT + 1,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => true,
+            // The storage is still in use.
+            Err(refcount) if refcount > FREE_REFCOUNT => false,
+            // The caller holds a reference, which rules out a refcount of 0.
+            Err(refcount) => {
+                if cfg!(debug_assertions) {
+                    invalid_refcount("reusing recyclable storage for a new future", refcount);
+                }
+                false
+            }
+        }
+    }
+
+    /// Acquires storage which the caller knows to be no longer in use by a
+    /// previous payload, without an atomic read-modify-write operation.
+    ///
+    /// The caller must hold a reference on the storage, and the release of the
+    /// previous payload must happen before this call.
+    #[track_caller]
+    unsafe fn acquire_unchecked(header: *const RecyclableFutureHeader<A>) {
+        if cfg!(debug_assertions) {
+            let refcount = (*header).refcount.load(Ordering::Relaxed);
+            if refcount != FREE_REFCOUNT {
+                invalid_refcount("replacing the future in recyclable storage", refcount);
+            }
+        }
+        (*header)
+            .refcount
+            .store(FREE_REFCOUNT + 1, Ordering::Relaxed);
+    }
+
+    /// Releases a reference on the storage, and frees the storage if it was
+    /// the last reference.
+    ///
+    /// The refcount follows the same protocol as `Arc`: Every decrement is a
+    /// `Release` operation, which makes all accesses to the storage through
+    /// this reference visible to the holder of the last reference. The holder
+    /// of the last reference synchronizes with those via an `Acquire` fence
+    /// before freeing the storage. Reusing the storage for a new payload
+    /// synchronizes the same way via the `Acquire` in `try_acquire`.
+    unsafe fn release(header: *const RecyclableFutureHeader<A>) {
+        if (*header).refcount.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+
+        fence(Ordering::Acquire);
+        // Deallocate header and storage
+        RecyclableFutureHeader::deallocate(header as *mut RecyclableFutureHeader<A>);
+    }
+
+    /// Moves `payload` into the storage.
+    ///
+    /// The storage must not be in use by another payload, and the payload must
+    /// fit into it.
+    unsafe fn write_payload<P>(header: *const RecyclableFutureHeader<A>, payload: P) {
+        debug_assert!((*header).fits_layout(Layout::for_value(&payload)));
+        core::ptr::write(Self::payload_addr_mut(header), payload);
+        #[cfg(feature = "assert-unmoved")]
+        (*header).polled_at.set(0);
+    }
+
+    /// Panics if the payload is polled at `addr`, after it had been polled at
+    /// another address before.
+    ///
+    /// The payload must never move after it had been polled for the first
+    /// time. The check catches code which e.g. derives the payload address
+    /// differently between polls.
+    #[cfg(feature = "assert-unmoved")]
+    unsafe fn assert_unmoved(header: *const RecyclableFutureHeader<A>, addr: *const ()) {
+        let addr = addr as usize;
+        let polled_at = (*header).polled_at.get();
+        if polled_at == 0 {
+            (*header).polled_at.set(addr);
+        } else {
+            assert_eq!(
+                polled_at, addr,
+                "Recyclable payload moved after it had been polled"
+            );
+        }
+    }
+
+    /// Returns the alignment of the storage behind the header
+    fn align(&self) -> usize {
+        1 << self.align_log2
+    }
+
+    /// Returns the layout of the payloads which fit into the storage
+    fn capacity(&self) -> Layout {
+        // The size and alignment had been taken from a valid layout
+        unsafe { Layout::from_size_align_unchecked(self.size, self.align()) }
+    }
+
+    /// Returns whether the storage is no longer in use by a payload.
+    ///
+    /// The refcount is read with `Relaxed` ordering. The result therefore only
+    /// allows to decide whether the storage should be replaced, but does not
+    /// synchronize with the release of the previous payload.
+    pub(crate) fn is_free(&self) -> bool {
+        self.refcount.load(Ordering::Relaxed) == FREE_REFCOUNT
+    }
+
+    /// Returns whether the storage exactly matches a payload of `data_layout`
+    pub(crate) fn matches_layout(&self, data_layout: Layout) -> bool {
+        self.size == data_layout.size() && self.align() == data_layout.align()
+    }
+
+    /// Returns whether a payload of `data_layout` fits into the storage
+    pub(crate) fn fits_layout(&self, data_layout: Layout) -> bool {
+        self.size >= data_layout.size() && self.align() >= data_layout.align()
+    }
+
+    /// Returns the smallest layout which fits both the current storage and a
+    /// payload of `data_layout`
+    fn grown_layout(&self, data_layout: Layout) -> Layout {
+        let size = self.size.max(data_layout.size());
+        let align = self.align().max(data_layout.align());
+        // Both alignments are valid powers of two, and the size will not overflow
+        // when padded to the alignment since both storage and payload already
+        // satisfy this.
+        unsafe { Layout::from_size_align_unchecked(size, align) }
+    }
+
+    /// Returns the `Layout` of an allocation which stores the header followed by
+    /// a payload of `data_layout`, as well as the offset of the payload inside
+    /// this allocation.
+    ///
+    /// Returns an error instead of panicking if the size of the allocation
+    /// overflows.
+    fn layout_for(data_layout: Layout) -> Result<(Layout, usize), ()> {
+        let (combined_layout, payload_offset) =
+            Layout::new::<Self>().extend(data_layout).map_err(|_| ())?;
+        Ok((combined_layout.pad_to_align(), payload_offset))
+    }
+
+    /// Returns the offset of a payload with the given alignment from the start
+    /// of the header. The payload is placed behind the header, and padding is
+    /// inserted in front of it if its alignment is bigger than the alignment
+    /// of the header.
+    fn payload_offset(data_align: usize) -> usize {
+        let header_size = core::mem::size_of::<Self>();
+        (header_size + data_align - 1) & !(data_align - 1)
+    }
+
+    /// Frees the storage which is referenced by `header`.
+    ///
+    /// The pointer needs to be derived from the pointer which was returned by
+    /// the allocator, in order to be valid for the whole storage.
+    unsafe fn deallocate(header: *mut Self) {
+        let data_layout = Layout::from_size_align_unchecked((*header).size, (*header).align());
+        // Move the allocator out of the storage before it gets freed
+        let alloc = core::ptr::read(core::ptr::addr_of!((*header).alloc));
+        if let Ok((layout, _)) = Self::layout_for(data_layout) {
+            alloc.deallocate(header as *mut u8, layout);
+        }
+    }
+
+    /// Returns the address of the payload section which is allocated behind
+    /// the header.
+    ///
+    /// The address is derived from `header` instead of from a reference to the
+    /// header, since such a reference would only be valid for the header itself.
+    unsafe fn payload_addr<T>(header: *const Self) -> *const T {
+        let offset = Self::payload_offset((*header).align());
+        (header as *const u8).add(offset) as *const T
+    }
+
+    /// Returns the address of the payload section which is allocated behind
+    /// the header.
+    unsafe fn payload_addr_mut<T>(header: *const Self) -> *mut T {
+        Self::payload_addr::<T>(header) as *mut T
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        poll_once, yield_now, yield_value, CountingAllocator, DropCounter, FailingAllocator,
+    };
+    use core::{cell::Cell, task::Poll};
+
+    #[repr(align(64))]
+    struct Aligned([u8; 64]);
+
+    #[test]
+    fn over_aligned_futures_are_stored_aligned() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        for expected in [Reused::FreshAllocation, Reused::Recycled] {
+            let aligned = Aligned([1; 64]);
+            let (mut fut, reused) = recycler.allocate_tracked(async move {
+                let aligned = &aligned;
+                yield_now().await;
+                (aligned as *const Aligned as usize % 64, aligned.0[63])
+            });
+            assert_eq!(expected, reused);
+
+            let payload = unsafe {
+                RecyclableFutureHeader::<Global>::payload_addr::<u8>(
+                    fut.ptr() as *const RecyclableFutureHeader
+                )
+            };
+            assert_eq!(0, payload as usize % 64);
+
+            assert!(poll_once(&mut fut).is_pending());
+            assert_eq!(Poll::Ready((0, 1)), poll_once(&mut fut));
+        }
+        assert_eq!(64, recycler.cached_layout().unwrap().align());
+    }
+
+    /// A future which holds a value of type `T`, and resolves immediately
+    struct ReadyWith<T>(T);
+
+    impl<T> Future for ReadyWith<T> {
+        type Output = ();
+
+        fn poll(self: core::pin::Pin<&mut Self>, _cx: &mut core::task::Context<'_>) -> Poll<()> {
+            Poll::Ready(())
+        }
+    }
+
+    #[test]
+    fn futures_with_different_alignment_are_not_recycled() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        drop(recycler.allocate(ReadyWith(Aligned([0; 64]))));
+
+        // Same size as the previous future, but a smaller alignment
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 64]));
+        assert_eq!(Reused::BoxedFallback, reused);
+        drop(fut);
+
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith(Aligned([0; 64])));
+        assert_eq!(Reused::Recycled, reused);
+        drop(fut);
+    }
+
+    #[test]
+    fn growing_storage_keeps_the_biggest_alignment() {
+        let mut recycler = RecyclableFutureAllocator::growing();
+        drop(recycler.allocate(ReadyWith([0u8; 128])));
+        assert_eq!(Some(Layout::new::<[u8; 128]>()), recycler.cached_layout());
+
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith(Aligned([0; 64])));
+        assert_eq!(Reused::FreshAllocation, reused);
+        assert_eq!(
+            Some(Layout::from_size_align(128, 64).unwrap()),
+            recycler.cached_layout()
+        );
+        drop(fut);
+
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 128]));
+        assert_eq!(Reused::Recycled, reused);
+        drop(fut);
+    }
+
+    #[test]
+    fn growing_storage_is_reused_for_smaller_futures() {
+        let mut recycler = RecyclableFutureAllocator::growing();
+        let mut capacities = alloc::vec::Vec::new();
+        for round in 0..4 {
+            let (fut, small) = recycler.allocate_tracked(ReadyWith([0u8; 16]));
+            drop(fut);
+            capacities.push(recycler.cached_layout().unwrap().size());
+            let (fut, large) = recycler.allocate_tracked(ReadyWith([0u8; 256]));
+            drop(fut);
+            capacities.push(recycler.cached_layout().unwrap().size());
+
+            if round == 0 {
+                assert_eq!(Reused::FreshAllocation, small);
+                assert_eq!(Reused::FreshAllocation, large);
+            } else {
+                assert_eq!(Reused::Recycled, small);
+                assert_eq!(Reused::Recycled, large);
+            }
+        }
+        assert_eq!([16, 256, 256, 256, 256, 256, 256, 256], capacities[..]);
+    }
+
+    #[test]
+    fn non_growing_storage_is_not_reused_for_smaller_futures() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        drop(recycler.allocate(ReadyWith([0u8; 256])));
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 16]));
+        assert_eq!(Reused::BoxedFallback, reused);
+        drop(fut);
+        assert_eq!(Some(Layout::new::<[u8; 256]>()), recycler.cached_layout());
+    }
+
+    #[test]
+    fn try_allocate_hands_back_the_future_on_failure() {
+        let drops = Cell::new(0);
+        let mut recycler = RecyclableFutureAllocator::new_in(FailingAllocator);
+        let counter = DropCounter(&drops);
+        let fut = async move {
+            let _counter = counter;
+            7
+        };
+        let mut fut = match recycler.try_allocate(fut) {
+            Ok(_) => panic!("The allocation should fail"),
+            Err(fut) => fut,
+        };
+        assert_eq!(0, drops.get());
+        assert!(recycler.cached_layout().is_none());
+
+        // The future is intact, and can still be polled to completion
+        let fut = unsafe { core::pin::Pin::new_unchecked(&mut fut) };
+        let mut cx = core::task::Context::from_waker(futures::task::noop_waker_ref());
+        assert_eq!(Poll::Ready(7), fut.poll(&mut cx));
+        assert_eq!(1, drops.get());
+    }
+
+    #[test]
+    fn try_allocate_succeeds_with_the_global_allocator() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        for _ in 0..2 {
+            let mut fut = recycler
+                .try_allocate(async { 3 })
+                .unwrap_or_else(|_| panic!("The allocation failed"));
+            assert_eq!(Poll::Ready(3), poll_once(&mut fut));
+        }
+    }
+
+    #[test]
+    fn try_allocate_stores_zero_sized_futures_without_allocating() {
+        let mut recycler = RecyclableFutureAllocator::new_in(FailingAllocator);
+        let mut fut = recycler
+            .try_allocate(ReadyWith(()))
+            .unwrap_or_else(|_| panic!("Zero sized futures require no storage"));
+        assert_eq!(Poll::Ready(()), poll_once(&mut fut));
+    }
+
+    #[test]
+    fn storage_is_obtained_from_and_returned_to_the_allocator() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        for _ in 0..3 {
+            let mut fut = recycler.allocate(yield_value(1));
+            assert!(poll_once(&mut fut).is_pending());
+            assert_eq!(Poll::Ready(1), poll_once(&mut fut));
+        }
+        assert_eq!(1, alloc.allocations());
+        assert_eq!(0, alloc.deallocations());
+
+        // The second future is boxed via the allocator, since the storage is
+        // still in use
+        let first = recycler.allocate(yield_value(1));
+        let second = recycler.allocate(yield_value(2));
+        assert_eq!(2, alloc.allocations());
+        drop(second);
+        assert_eq!(1, alloc.deallocations());
+
+        // The storage is freed by the future, which outlives the allocator
+        drop(recycler);
+        assert_eq!(1, alloc.deallocations());
+        drop(first);
+        assert_eq!(2, alloc.allocations());
+        assert_eq!(2, alloc.deallocations());
+    }
+
+    #[test]
+    fn replaced_storage_is_returned_to_the_allocator() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::growing_in(alloc.clone());
+        drop(recycler.allocate(ReadyWith([0u8; 16])));
+        drop(recycler.allocate(ReadyWith([0u8; 64])));
+        assert_eq!(2, alloc.allocations());
+        assert_eq!(1, alloc.deallocations());
+        assert!(recycler.clear());
+        assert_eq!(2, alloc.deallocations());
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn streams_reuse_the_storage_of_futures() {
+        use futures::{stream, StreamExt};
+
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::growing_in(alloc.clone());
+        drop(recycler.allocate(ReadyWith([0u64; 32])));
+        for _ in 0..3 {
+            let counter = [0u8; 8];
+            let stream = recycler.allocate_stream(stream::iter(counter.iter().map(|_| 1u32)));
+            let sum = futures::executor::block_on(stream.fold(0, |sum, i| async move { sum + i }));
+            assert_eq!(8, sum);
+        }
+        assert_eq!(1, alloc.allocations());
+    }
+
+    #[cfg(not(async_trait_experiments_single_threaded))]
+    #[test]
+    fn allocator_can_be_sent_to_other_threads() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        futures::executor::block_on(recycler.allocate(yield_value(1)));
+
+        let mut recycler = std::thread::spawn(move || {
+            let (fut, reused) = recycler.allocate_tracked(yield_value(2));
+            assert_eq!(Reused::Recycled, reused);
+            assert_eq!(2, futures::executor::block_on(fut));
+            recycler
+        })
+        .join()
+        .unwrap();
+
+        let (fut, reused) = recycler.allocate_tracked(yield_value(3));
+        assert_eq!(Reused::Recycled, reused);
+        assert_eq!(3, futures::executor::block_on(fut));
+    }
+
+    #[test]
+    fn debug_output_contains_the_cached_size() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let empty = alloc::format!("{:?}", recycler);
+        assert!(empty.contains("cached: false"), "{}", empty);
+
+        let fut = recycler.allocate(ReadyWith([0u8; 48]));
+        let in_use = alloc::format!("{:?}", recycler);
+        assert!(in_use.contains("size: 48"), "{}", in_use);
+        assert!(in_use.contains("refcount: 2"), "{}", in_use);
+        drop(fut);
+
+        let free = alloc::format!("{:?}", recycler);
+        assert!(free.contains("cached: true"), "{}", free);
+        assert!(free.contains("refcount: 1"), "{}", free);
+    }
+
+    #[test]
+    fn clear_frees_storage_which_is_not_in_use() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        assert!(!recycler.clear());
+
+        futures::executor::block_on(recycler.allocate(yield_value(1)));
+        assert!(recycler.clear());
+        assert_eq!(1, alloc.deallocations());
+        assert_eq!(None, recycler.cached_layout());
+
+        let (fut, reused) = recycler.allocate_tracked(yield_value(2));
+        assert_eq!(Reused::FreshAllocation, reused);
+        drop(fut);
+    }
+
+    #[test]
+    fn clear_keeps_storage_which_is_in_use() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        let fut = recycler.allocate(yield_value(1));
+        assert!(!recycler.clear());
+        assert_eq!(0, alloc.deallocations());
+        drop(fut);
+
+        let (fut, reused) = recycler.allocate_tracked(yield_value(2));
+        assert_eq!(Reused::Recycled, reused);
+        drop(fut);
+    }
+
+    #[test]
+    fn storage_with_a_higher_refcount_counts_as_in_use() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        drop(recycler.allocate(yield_value(1)));
+
+        // Simulate an additional reference which is held temporarily
+        let header = recycler.recycled;
+        unsafe { (*header).refcount.fetch_add(2, Ordering::Relaxed) };
+        let (fut, reused) = recycler.allocate_tracked(yield_value(2));
+        assert_eq!(Reused::BoxedFallback, reused);
+        assert_eq!(2, futures::executor::block_on(fut));
+        assert!(!recycler.clear());
+
+        unsafe { (*header).refcount.fetch_sub(2, Ordering::Relaxed) };
+        let (fut, reused) = recycler.allocate_tracked(yield_value(3));
+        assert_eq!(Reused::Recycled, reused);
+        assert_eq!(3, futures::executor::block_on(fut));
+    }
+
+    #[test]
+    fn allocate_poll_drop_and_reuse() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        for round in 0..3u32 {
+            let buffer = [round; 4];
+            let (mut fut, reused) = recycler.allocate_tracked(async move {
+                let buffer = &buffer;
+                yield_now().await;
+                buffer.iter().sum::<u32>()
+            });
+            let expected = if round == 0 {
+                Reused::FreshAllocation
+            } else {
+                Reused::Recycled
+            };
+            assert_eq!(expected, reused);
+            assert!(poll_once(&mut fut).is_pending());
+            assert_eq!(Poll::Ready(4 * round), poll_once(&mut fut));
+        }
+
+        // A pending future is dropped in place, and its storage is reused
+        let mut recycler = RecyclableFutureAllocator::new();
+        let mut fut = recycler.allocate(yield_value(1));
+        assert!(poll_once(&mut fut).is_pending());
+        drop(fut);
+        let (fut, reused) = recycler.allocate_tracked(yield_value(2));
+        assert_eq!(Reused::Recycled, reused);
+        drop(recycler);
+        assert_eq!(2, futures::executor::block_on(fut));
+    }
+
+    #[test]
+    fn storage_is_replaced_after_max_reuses() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone()).with_max_reuses(3);
+        let mut results = alloc::vec::Vec::new();
+        for _ in 0..8 {
+            let (fut, reused) = recycler.allocate_tracked(yield_value(1));
+            assert_eq!(1, futures::executor::block_on(fut));
+            results.push(reused);
+        }
+        use Reused::{FreshAllocation as F, Recycled as R};
+        assert_eq!(alloc::vec![F, R, R, R, F, R, R, R], results);
+        assert_eq!(2, alloc.allocations());
+        assert_eq!(1, alloc.deallocations());
+    }
+
+    #[test]
+    fn storage_which_reached_max_reuses_is_freed_once_it_is_no_longer_in_use() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone()).with_max_reuses(0);
+        let first = recycler.allocate(yield_value(1));
+        let (second, reused) = recycler.allocate_tracked(yield_value(2));
+        assert_eq!(Reused::FreshAllocation, reused);
+        assert_eq!(2, alloc.allocations());
+        assert_eq!(0, alloc.deallocations());
+
+        drop(first);
+        assert_eq!(1, alloc.deallocations());
+        drop(second);
+        assert_eq!(1, alloc.deallocations());
+    }
+
+    /// A future which panics when it is polled, or when it is dropped
+    struct Panicking {
+        in_poll: bool,
+        in_drop: bool,
+    }
+
+    impl Future for Panicking {
+        type Output = ();
+
+        fn poll(self: core::pin::Pin<&mut Self>, _cx: &mut core::task::Context<'_>) -> Poll<()> {
+            if self.in_poll {
+                panic!("poll panicked");
+            }
+            Poll::Ready(())
+        }
+    }
+
+    impl Drop for Panicking {
+        fn drop(&mut self) {
+            if self.in_drop {
+                panic!("drop panicked");
+            }
+        }
+    }
+
+    #[test]
+    fn storage_is_reusable_after_poll_panicked() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        let mut fut = recycler.allocate(Panicking {
+            in_poll: true,
+            in_drop: false,
+        });
+        assert!(catch_unwind(AssertUnwindSafe(|| poll_once(&mut fut))).is_err());
+        drop(fut);
+
+        let (mut fut, reused) = recycler.allocate_tracked(Panicking {
+            in_poll: false,
+            in_drop: false,
+        });
+        assert_eq!(Reused::Recycled, reused);
+        assert_eq!(Poll::Ready(()), poll_once(&mut fut));
+        drop(fut);
+        drop(recycler);
+        assert_eq!(1, alloc.allocations());
+        assert_eq!(1, alloc.deallocations());
+    }
+
+    #[test]
+    fn storage_is_reusable_after_drop_panicked() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        let fut = recycler.allocate(Panicking {
+            in_poll: false,
+            in_drop: true,
+        });
+        assert!(catch_unwind(AssertUnwindSafe(|| drop(fut))).is_err());
+
+        let (fut, reused) = recycler.allocate_tracked(Panicking {
+            in_poll: false,
+            in_drop: false,
+        });
+        assert_eq!(Reused::Recycled, reused);
+        drop(fut);
+        drop(recycler);
+        assert_eq!(1, alloc.allocations());
+        assert_eq!(1, alloc.deallocations());
+    }
+
+    #[test]
+    fn self_referential_futures_stay_in_place() {
+        let mut recycler = RecyclableFutureAllocator::growing();
+        for round in 0u8..3 {
+            let data = [round; 32];
+            let mut fut = recycler.allocate_pinned(async move {
+                let borrowed = &data;
+                let before = borrowed as *const [u8; 32];
+                yield_now().await;
+                // The reference into the future is still valid after it was
+                // resumed
+                assert_eq!(before, borrowed as *const [u8; 32]);
+                borrowed[31]
+            });
+            assert!(poll_once(&mut fut).is_pending());
+
+            // The second future either replaces the storage or gets boxed,
+            // while the first future still uses the storage
+            let big = [0u8; 256];
+            let mut other = recycler.allocate_pinned(async move {
+                let borrowed = &big;
+                yield_now().await;
+                borrowed[255]
+            });
+            assert!(poll_once(&mut other).is_pending());
+
+            assert_eq!(Poll::Ready(round), poll_once(&mut fut));
+            assert_eq!(Poll::Ready(0), poll_once(&mut other));
+        }
+    }
+
+    #[test]
+    fn header_stores_the_alignment_in_a_single_byte() {
+        // `assert-unmoved` adds the address of the first poll to the header
+        #[cfg(not(feature = "assert-unmoved"))]
+        assert_eq!(
+            2 * core::mem::size_of::<usize>(),
+            core::mem::size_of::<RecyclableFutureHeader>()
+        );
+        for align in [1, 2, 8, 64, 4096] {
+            let layout = Layout::from_size_align(24, align).unwrap();
+            unsafe {
+                let header = RecyclableFutureHeader::allocate(layout, 1, Global).unwrap();
+                assert_eq!(align, (*header).align());
+                assert!((*header).matches_layout(layout));
+                assert_eq!(
+                    0,
+                    header as usize % align.max(core::mem::align_of::<usize>())
+                );
+                release_recycler_reference(header);
+            }
+        }
+    }
+
+    #[test]
+    fn storage_is_reused_with_the_narrow_refcount() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let refcount = |recycler: &RecyclableFutureAllocator| unsafe {
+            (*recycler.recycled).refcount.load(Ordering::Relaxed)
+        };
+        for expected in [Reused::FreshAllocation, Reused::Recycled, Reused::Recycled] {
+            let (mut fut, reused) = recycler.allocate_tracked(yield_value(1));
+            assert_eq!(expected, reused);
+            assert_eq!(RETAINED_REFCOUNT, refcount(&recycler));
+            assert!(poll_once(&mut fut).is_pending());
+            assert_eq!(Poll::Ready(1), poll_once(&mut fut));
+            drop(fut);
+            assert_eq!(FREE_REFCOUNT, refcount(&recycler));
+        }
+    }
+
+    #[test]
+    fn futures_beyond_the_byte_budget_are_boxed() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone()).with_byte_budget(64);
+        for _ in 0..2 {
+            let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 128]));
+            assert_eq!(Reused::BoxedFallback, reused);
+            drop(fut);
+            assert_eq!(None, recycler.cached_layout());
+        }
+
+        // Futures within the budget are still recycled
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 16]));
+        assert_eq!(Reused::FreshAllocation, reused);
+        drop(fut);
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 16]));
+        assert_eq!(Reused::Recycled, reused);
+        drop(fut);
+        assert_eq!(3, alloc.allocations());
+        assert_eq!(2, alloc.deallocations());
+    }
+
+    #[test]
+    fn growing_beyond_the_byte_budget_releases_the_storage() {
+        let alloc = CountingAllocator::default();
+        let mut recycler =
+            RecyclableFutureAllocator::growing_in(alloc.clone()).with_byte_budget(64);
+        drop(recycler.allocate(ReadyWith([0u8; 16])));
+        assert!(recycler.cached_layout().is_some());
+
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 128]));
+        assert_eq!(Reused::BoxedFallback, reused);
+        drop(fut);
+        assert_eq!(None, recycler.cached_layout());
+        assert_eq!(2, alloc.allocations());
+        assert_eq!(2, alloc.deallocations());
+    }
+
+    #[test]
+    fn byte_budget_includes_the_header() {
+        let header = core::mem::size_of::<RecyclableFutureHeader>();
+        let mut recycler = RecyclableFutureAllocator::new().with_byte_budget(header + 16);
+        let (_fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 16]));
+        assert_eq!(Reused::FreshAllocation, reused);
+
+        let mut recycler = RecyclableFutureAllocator::new().with_byte_budget(header + 15);
+        let (_fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 16]));
+        assert_eq!(Reused::BoxedFallback, reused);
+    }
+
+    /// An async trait whose method resolves to data which is borrowed from
+    /// the object
+    trait Named {
+        fn name<'a>(&'a mut self, suffix: &'a str) -> DynamicFuture<'a, &'a str>;
+    }
+
+    struct NamedImpl {
+        name: alloc::string::String,
+        recycler: RecyclableFutureAllocator,
+    }
+
+    impl Named for NamedImpl {
+        fn name<'a>(&'a mut self, suffix: &'a str) -> DynamicFuture<'a, &'a str> {
+            let name = &self.name;
+            self.recycler.allocate(async move {
+                yield_now().await;
+                if name.is_empty() {
+                    suffix
+                } else {
+                    name.as_str()
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn futures_resolve_to_data_which_is_borrowed_from_the_object() {
+        let mut named = NamedImpl {
+            name: "recycled".into(),
+            recycler: RecyclableFutureAllocator::new(),
+        };
+        let suffix = alloc::string::String::from("suffix");
+        let obj: &mut dyn Named = &mut named;
+        assert_eq!("recycled", futures::executor::block_on(obj.name(&suffix)));
+        assert_eq!("recycled", futures::executor::block_on(obj.name(&suffix)));
+
+        named.name.clear();
+        assert_eq!("suffix", futures::executor::block_on(named.name(&suffix)));
+    }
+
+    #[test]
+    fn allocate_map_fuses_the_closure_into_the_storage() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        for value in 0..3 {
+            let fut = recycler.allocate_map(yield_value(value), |value| value * 2);
+            assert_eq!(value * 2, futures::executor::block_on(fut));
+        }
+        // The combinator does not require an allocation of its own
+        assert_eq!(1, alloc.allocations());
+
+        let calls = Cell::new(0);
+        let mut fut = recycler.allocate_map(yield_value(1), |_| calls.set(calls.get() + 1));
+        assert!(poll_once(&mut fut).is_pending());
+        drop(fut);
+        assert_eq!(0, calls.get());
+    }
+
+    #[cfg(feature = "assert-unmoved")]
+    #[test]
+    fn unmoved_payloads_pass_the_check() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        for value in 0..3 {
+            let mut fut = recycler.allocate(yield_value(value));
+            assert!(poll_once(&mut fut).is_pending());
+            assert_eq!(Poll::Ready(value), poll_once(&mut fut));
+        }
+    }
+
+    #[cfg(feature = "assert-unmoved")]
+    #[test]
+    #[should_panic(expected = "Recyclable payload moved after it had been polled")]
+    fn moved_payloads_fail_the_check() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let mut fut = recycler.allocate(yield_value(1));
+        assert!(poll_once(&mut fut).is_pending());
+        unsafe {
+            let header = fut.ptr() as *const RecyclableFutureHeader;
+            let addr = RecyclableFutureHeader::payload_addr::<u8>(header);
+            RecyclableFutureHeader::assert_unmoved(header, addr.add(1) as *const ());
+        }
+    }
+
+    #[test]
+    fn can_reuse_for_predicts_reuse() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        assert!(!recycler.can_reuse_for::<ReadyWith<[u8; 16]>>());
+        drop(recycler.allocate(ReadyWith([0u8; 16])));
+        assert!(recycler.can_reuse_for::<ReadyWith<[u8; 16]>>());
+        assert!(!recycler.can_reuse_for::<ReadyWith<[u8; 8]>>());
+        assert!(!recycler.can_reuse_for::<ReadyWith<()>>());
+
+        let fut = recycler.allocate(ReadyWith([0u8; 16]));
+        assert!(!recycler.can_reuse_for::<ReadyWith<[u8; 16]>>());
+        drop(fut);
+        assert!(recycler.can_reuse_for::<ReadyWith<[u8; 16]>>());
+        let (_fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 16]));
+        assert_eq!(Reused::Recycled, reused);
+    }
+
+    #[test]
+    fn can_reuse_for_accepts_smaller_futures_for_growing_allocators() {
+        let mut recycler = RecyclableFutureAllocator::growing();
+        drop(recycler.allocate(ReadyWith([0u8; 16])));
+        assert!(recycler.can_reuse_for::<ReadyWith<[u8; 8]>>());
+        assert!(!recycler.can_reuse_for::<ReadyWith<[u8; 32]>>());
+        assert!(!recycler.can_reuse_for::<ReadyWith<Aligned>>());
+    }
+
+    #[test]
+    fn allocator_can_be_dropped_before_its_futures() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let mut fut = recycler.allocate(async move {
+            let _counter = counter;
+            yield_value(3).await
+        });
+        drop(recycler);
+        assert_eq!(0, alloc.deallocations());
+
+        // The future is still valid, and frees the storage once it is dropped
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready(3), poll_once(&mut fut));
+        assert_eq!(0, alloc.deallocations());
+        drop(fut);
+        assert_eq!(1, drops.get());
+        assert_eq!(1, alloc.allocations());
+        assert_eq!(1, alloc.deallocations());
+    }
+
+    #[test]
+    fn futures_in_flight_outlive_the_allocator() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        let mut fut = recycler.allocate(yield_value(4));
+        assert!(poll_once(&mut fut).is_pending());
+        drop(recycler);
+        assert_eq!(Poll::Ready(4), poll_once(&mut fut));
+        assert_eq!(0, alloc.deallocations());
+        drop(fut);
+        assert_eq!(1, alloc.deallocations());
+    }
+
+    #[test]
+    fn with_layout_preallocates_storage_for_futures_which_fit() {
+        let alloc = CountingAllocator::default();
+        let layout = Layout::new::<[u64; 8]>();
+        let mut recycler = RecyclableFutureAllocator::with_layout_in(layout, alloc.clone());
+        assert_eq!(1, alloc.allocations());
+        assert_eq!(Some(layout), recycler.cached_layout());
+
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u64; 8]));
+        assert_eq!(Reused::Recycled, reused);
+        drop(fut);
+        let (fut, reused) = recycler.allocate_tracked(yield_value(1));
+        assert_eq!(Reused::Recycled, reused);
+        assert_eq!(1, futures::executor::block_on(fut));
+        assert_eq!(1, alloc.allocations());
+
+        let recycler = RecyclableFutureAllocator::with_layout(layout);
+        assert_eq!(Some(layout), recycler.cached_layout());
+    }
+
+    #[test]
+    fn info_describes_the_storage_of_the_future() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let fut = recycler.allocate(ReadyWith([0u64; 4]));
+        let info = unsafe { RecyclableFutureInfo::of(&fut) };
+        let header_size = core::mem::size_of::<RecyclableFutureHeader>();
+        assert_eq!(
+            RecyclableFutureInfo {
+                header_size,
+                payload_offset: header_size,
+                payload_size: 32,
+                refcount: RETAINED_REFCOUNT as usize,
+            },
+            info
+        );
+
+        // The fields can be read at the published offsets
+        let storage = fut.ptr() as *const u8;
+        unsafe {
+            assert_eq!(
+                RETAINED_REFCOUNT,
+                *storage.add(RecyclableFutureInfo::REFCOUNT_OFFSET)
+            );
+            assert_eq!(3, *storage.add(RecyclableFutureInfo::ALIGN_LOG2_OFFSET));
+            assert_eq!(
+                32,
+                (storage.add(RecyclableFutureInfo::SIZE_OFFSET) as *const usize).read()
+            );
+        }
+
+        let mut recycler = RecyclableFutureAllocator::new();
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith(Aligned([0; 64])));
+        assert_eq!(Reused::FreshAllocation, reused);
+        let info = unsafe { RecyclableFutureInfo::of(&fut) };
+        assert_eq!(64, info.payload_offset);
+        assert_eq!(64, info.payload_size);
+    }
+
+    #[test]
+    fn layout_for_reports_overflowing_sizes() {
+        let huge = Layout::from_size_align(isize::MAX as usize & !7, 8).unwrap();
+        assert!(RecyclableFutureHeader::<Global>::layout_for(huge).is_err());
+
+        let (layout, offset) =
+            RecyclableFutureHeader::<Global>::layout_for(Layout::new::<u64>()).unwrap();
+        assert_eq!(core::mem::size_of::<RecyclableFutureHeader>(), offset);
+        assert_eq!(offset + 8, layout.size());
+    }
+
+    #[test]
+    fn overflowing_storage_is_not_reserved() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        recycler.reserve(Layout::from_size_align(isize::MAX as usize & !7, 8).unwrap());
+        assert!(recycler.cached_layout().is_none());
+        assert_eq!(0, alloc.allocations());
+    }
+
+    /// An allocator which fails allocations of more than 32 bytes
+    #[derive(Clone, Copy)]
+    struct SmallAllocator;
+
+    unsafe impl Allocator for SmallAllocator {
+        fn allocate(&self, layout: Layout) -> *mut u8 {
+            if layout.size() > 32 {
+                return core::ptr::null_mut();
+            }
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn futures_are_boxed_if_storage_can_not_be_allocated() {
+        // The storage for the future needs room for the header as well, and
+        // can not be allocated. Boxing the future only needs its own size.
+        let mut recycler = RecyclableFutureAllocator::new_in(SmallAllocator);
+        for _ in 0..2 {
+            let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 32]));
+            assert_eq!(Reused::BoxedFallback, reused);
+            assert!(recycler.cached_layout().is_none());
+            drop(fut);
+        }
+
+        let mut fut = match recycler.try_allocate(ReadyWith([0u8; 32])) {
+            Ok(fut) => fut,
+            Err(_) => panic!("Boxing the future should succeed"),
+        };
+        assert_eq!(Poll::Ready(()), poll_once(&mut fut));
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn streams_are_boxed_if_storage_can_not_be_allocated() {
+        use futures::StreamExt;
+
+        // Like for futures, only the stream without the header fits
+        let mut recycler = RecyclableFutureAllocator::new_in(SmallAllocator);
+        for _ in 0..2 {
+            let stream = recycler.allocate_stream(futures::stream::iter([7u8; 8]));
+            assert!(recycler.cached_layout().is_none());
+            let sum = futures::executor::block_on(stream.fold(0, |sum, i| async move { sum + i }));
+            assert_eq!(56, sum);
+        }
+    }
+
+    /// A future which computes its output from the data it holds. The layout
+    /// does not depend on the output type.
+    struct Compute<T>([usize; 3], fn([usize; 3]) -> T);
+
+    impl<T> Future for Compute<T> {
+        type Output = T;
+
+        fn poll(self: core::pin::Pin<&mut Self>, _cx: &mut core::task::Context<'_>) -> Poll<T> {
+            Poll::Ready((self.1)(self.0))
+        }
+    }
+
+    #[test]
+    fn storage_is_shared_by_futures_with_different_output_types() {
+        fn sum(data: [usize; 3]) -> u32 {
+            data.iter().sum::<usize>() as u32
+        }
+        fn describe(data: [usize; 3]) -> alloc::string::String {
+            alloc::format!("{:?}", data)
+        }
+        assert_eq!(
+            Layout::new::<Compute<u32>>(),
+            Layout::new::<Compute<alloc::string::String>>()
+        );
+
+        let mut recycler = RecyclableFutureAllocator::new();
+        for round in 0..2 {
+            let (mut fut, reused) = recycler.allocate_tracked(Compute([1, 2, 3], sum));
+            let expected = if round == 0 {
+                Reused::FreshAllocation
+            } else {
+                Reused::Recycled
+            };
+            assert_eq!(expected, reused);
+            let storage = fut.ptr();
+            assert_eq!(Poll::Ready(6), poll_once(&mut fut));
+            drop(fut);
+
+            let (mut fut, reused) = recycler.allocate_tracked(Compute([4, 5, 6], describe));
+            assert_eq!(Reused::Recycled, reused);
+            assert_eq!(storage, fut.ptr());
+            assert_eq!(Poll::Ready("[4, 5, 6]".into()), poll_once(&mut fut));
+        }
+    }
+
+    /// A future which counts its polls, and completes once the count reaches
+    /// `target`
+    #[cfg(feature = "unstable-internals")]
+    struct CountPolls {
+        polls: u32,
+        target: u32,
+    }
+
+    #[cfg(feature = "unstable-internals")]
+    impl Future for CountPolls {
+        type Output = u32;
+
+        fn poll(
+            mut self: core::pin::Pin<&mut Self>,
+            _cx: &mut core::task::Context<'_>,
+        ) -> Poll<u32> {
+            self.polls += 1;
+            if self.polls >= self.target {
+                Poll::Ready(self.polls)
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    #[cfg(feature = "unstable-internals")]
+    #[test]
+    fn payload_mut_accesses_the_stored_future() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let (mut fut, reused) = recycler.allocate_tracked(CountPolls {
+            polls: 0,
+            target: 10,
+        });
+        assert_eq!(Reused::FreshAllocation, reused);
+
+        assert!(poll_once(&mut fut).is_pending());
+        assert!(poll_once(&mut fut).is_pending());
+        let payload = unsafe { fut.payload_mut::<CountPolls>() };
+        assert_eq!(2, payload.polls);
+
+        // The next poll observes the modified state
+        payload.polls = 9;
+        assert_eq!(Poll::Ready(10), poll_once(&mut fut));
+    }
+
+    #[test]
+    fn scoped_futures_reuse_the_storage_of_the_allocator() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let value = 4u32;
+        let outputs = recycler.scope(|scope| {
+            let mut outputs = alloc::vec::Vec::new();
+            for _ in 0..3 {
+                // The futures may borrow data which outlives the scope
+                let value = &value;
+                let mut fut = scope.allocate(async move {
+                    yield_now().await;
+                    *value
+                });
+                let storage = fut.ptr() as usize;
+                assert!(poll_once(&mut fut).is_pending());
+                if let Poll::Ready(output) = poll_once(&mut fut) {
+                    outputs.push((storage, output));
+                }
+            }
+            outputs
+        });
+        assert_eq!(3, outputs.len());
+        for (storage, output) in &outputs {
+            assert_eq!(outputs[0].0, *storage);
+            assert_eq!(4, *output);
+        }
+        assert!(recycler.cached_layout().is_some());
+    }
+
+    #[test]
+    fn replace_writes_the_future_into_the_retained_storage() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let (mut fut, reused) = recycler.allocate_tracked(yield_value(0));
+        assert_eq!(Reused::FreshAllocation, reused);
+        let storage = fut.ptr();
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready(0), poll_once(&mut fut));
+        drop(fut);
+
+        for value in 1..4 {
+            // The previous future of the same type was dropped
+            let mut fut = unsafe { recycler.replace(yield_value(value)) };
+            assert_eq!(storage, fut.ptr());
+            assert!(poll_once(&mut fut).is_pending());
+            assert_eq!(Poll::Ready(value), poll_once(&mut fut));
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "Invalid future refcount")]
+    fn replace_checks_that_the_storage_is_free_in_debug_builds() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        let _first = recycler.allocate(yield_value(1));
+        let _second = unsafe { recycler.replace(yield_value(2)) };
+    }
+
+    #[test]
+    fn allocate_either_stores_both_branches_in_one_slot() {
+        fn branch(
+            small: bool,
+            value: u32,
+        ) -> crate::Either<impl Future<Output = u32>, impl Future<Output = u32>> {
+            if small {
+                crate::Either::Left(yield_value(value))
+            } else {
+                let data = [value; 16];
+                crate::Either::Right(async move {
+                    yield_now().await;
+                    data[15] * 2
+                })
+            }
+        }
+
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        for i in 0..20 {
+            let fut = recycler.allocate_either(branch(i % 2 == 0, i));
+            let expected = if i % 2 == 0 { i } else { i * 2 };
+            assert_eq!(expected, futures::executor::block_on(fut));
+        }
+        assert_eq!(1, alloc.allocations());
+    }
+
+    #[cfg(feature = "unstable-internals")]
+    #[test]
+    fn current_refcount_reports_whether_the_storage_is_in_use() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        assert_eq!(None, recycler.current_refcount());
+        let mut fut = recycler.allocate(yield_value(1));
+        assert_eq!(Some(2), recycler.current_refcount());
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready(1), poll_once(&mut fut));
+        drop(fut);
+        assert_eq!(Some(1), recycler.current_refcount());
+        assert!(recycler.clear());
+        assert_eq!(None, recycler.current_refcount());
+    }
+
+    #[test]
+    fn header_fields_are_published_at_stable_offsets() {
+        // The refcount and the alignment share the first word, and the size
+        // occupies the second one
+        assert_eq!(0, RecyclableFutureInfo::REFCOUNT_OFFSET);
+        assert_eq!(1, RecyclableFutureInfo::ALIGN_LOG2_OFFSET);
+        assert_eq!(
+            core::mem::size_of::<usize>(),
+            RecyclableFutureInfo::SIZE_OFFSET
+        );
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn invalid_refcounts_are_reported_at_the_allocate_call() {
+        std::thread_local! {
+            static PANIC_LINE: Cell<Option<u32>> = const { Cell::new(None) };
+        }
+
+        let mut recycler = RecyclableFutureAllocator::new();
+        drop(recycler.allocate(yield_value(1)));
+        // Corrupt the refcount of the free storage
+        unsafe { (*recycler.recycled).refcount.store(0, Ordering::Relaxed) };
+
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(alloc::boxed::Box::new(|info| {
+            PANIC_LINE.with(|line| line.set(info.location().map(|location| location.line())));
+        }));
+        let allocate_line = line!() + 2;
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            drop(recycler.allocate(yield_value(2)));
+        }));
+        std::panic::set_hook(previous_hook);
+
+        let message = res.unwrap_err();
+        let message = message.downcast_ref::<alloc::string::String>().unwrap();
+        assert!(message.contains("Invalid future refcount of 0"));
+        assert_eq!(Some(allocate_line), PANIC_LINE.with(|line| line.get()));
+
+        // Restore the refcount, so that the storage can be freed
+        unsafe { (*recycler.recycled).refcount.store(1, Ordering::Relaxed) };
+    }
+
+    #[test]
+    fn exact_growth_fits_the_biggest_future() {
+        let mut recycler =
+            RecyclableFutureAllocator::growing().with_growth_policy(GrowthPolicy::Exact);
+        drop(recycler.allocate(ReadyWith([0u8; 16])));
+        assert_eq!(Some(Layout::new::<[u8; 16]>()), recycler.cached_layout());
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 40]));
+        assert_eq!(Reused::FreshAllocation, reused);
+        drop(fut);
+        assert_eq!(Some(Layout::new::<[u8; 40]>()), recycler.cached_layout());
+    }
+
+    #[test]
+    fn power_of_two_growth_rounds_up_the_size() {
+        let mut recycler =
+            RecyclableFutureAllocator::growing().with_growth_policy(GrowthPolicy::NextPowerOfTwo);
+        drop(recycler.allocate(ReadyWith([0u8; 40])));
+        assert_eq!(Some(Layout::new::<[u8; 64]>()), recycler.cached_layout());
+
+        // Futures up to the rounded up size reuse the storage
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 50]));
+        assert_eq!(Reused::Recycled, reused);
+        drop(fut);
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 70]));
+        assert_eq!(Reused::FreshAllocation, reused);
+        drop(fut);
+        assert_eq!(Some(Layout::new::<[u8; 128]>()), recycler.cached_layout());
+    }
+
+    #[test]
+    fn capped_growth_includes_the_header() {
+        let header = core::mem::size_of::<RecyclableFutureHeader>();
+        let mut recycler = RecyclableFutureAllocator::growing()
+            .with_growth_policy(GrowthPolicy::Capped(header + 32));
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 16]));
+        assert_eq!(Reused::FreshAllocation, reused);
+        drop(fut);
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 32]));
+        assert_eq!(Reused::FreshAllocation, reused);
+        drop(fut);
+
+        // The storage for this future would exceed the cap, and the current
+        // storage is retained
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 33]));
+        assert_eq!(Reused::BoxedFallback, reused);
+        drop(fut);
+        assert_eq!(Some(Layout::new::<[u8; 32]>()), recycler.cached_layout());
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 16]));
+        assert_eq!(Reused::Recycled, reused);
+        drop(fut);
+
+        // The cap also applies to the first storage
+        let mut recycler = RecyclableFutureAllocator::growing()
+            .with_growth_policy(GrowthPolicy::Capped(header + 31));
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith([0u8; 32]));
+        assert_eq!(Reused::BoxedFallback, reused);
+        drop(fut);
+        assert_eq!(None, recycler.cached_layout());
+    }
+
+    /// A `metrics` recorder which collects the counters of the recycler
+    #[cfg(feature = "metrics")]
+    #[derive(Default)]
+    struct CounterRecorder(
+        std::sync::Mutex<
+            alloc::vec::Vec<(
+                alloc::string::String,
+                alloc::sync::Arc<core::sync::atomic::AtomicU64>,
+            )>,
+        >,
+    );
+
+    #[cfg(feature = "metrics")]
+    impl CounterRecorder {
+        fn get(&self, name: &str) -> u64 {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(counter, _)| counter == name)
+                .map_or(0, |(_, value)| {
+                    value.load(core::sync::atomic::Ordering::Relaxed)
+                })
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    impl metrics::Recorder for CounterRecorder {
+        fn describe_counter(
+            &self,
+            _: metrics::KeyName,
+            _: Option<metrics::Unit>,
+            _: metrics::SharedString,
+        ) {
+        }
+
+        fn describe_gauge(
+            &self,
+            _: metrics::KeyName,
+            _: Option<metrics::Unit>,
+            _: metrics::SharedString,
+        ) {
+        }
+
+        fn describe_histogram(
+            &self,
+            _: metrics::KeyName,
+            _: Option<metrics::Unit>,
+            _: metrics::SharedString,
+        ) {
+        }
+
+        fn register_counter(
+            &self,
+            key: &metrics::Key,
+            _: &metrics::Metadata<'_>,
+        ) -> metrics::Counter {
+            let mut counters = self.0.lock().unwrap();
+            let value = match counters.iter().find(|(name, _)| name == key.name()) {
+                Some((_, value)) => value.clone(),
+                None => {
+                    let value = alloc::sync::Arc::new(core::sync::atomic::AtomicU64::new(0));
+                    counters.push((key.name().into(), value.clone()));
+                    value
+                }
+            };
+            metrics::Counter::from_arc(value)
+        }
+
+        fn register_gauge(&self, _: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Gauge {
+            metrics::Gauge::noop()
+        }
+
+        fn register_histogram(
+            &self,
+            _: &metrics::Key,
+            _: &metrics::Metadata<'_>,
+        ) -> metrics::Histogram {
+            metrics::Histogram::noop()
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn allocations_are_counted_via_metrics() {
+        let recorder = CounterRecorder::default();
+        metrics::with_local_recorder(&recorder, || {
+            let mut recycler = RecyclableFutureAllocator::new();
+            for value in 0..4 {
+                futures::executor::block_on(recycler.allocate(yield_value(value)));
+            }
+            let busy = recycler.allocate(yield_value(4));
+            drop(recycler.allocate(yield_value(5)));
+            drop(recycler.allocate(ReadyWith(())));
+            drop(busy);
+        });
+        assert_eq!(4, recorder.get("recycler.reuse"));
+        assert_eq!(1, recorder.get("recycler.fresh_alloc"));
+        assert_eq!(1, recorder.get("recycler.boxed_fallback"));
+    }
+
+    #[test]
+    fn cached_layout_matches_the_layout_of_the_cached_future() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        assert_eq!(None, recycler.cached_layout());
+
+        let fut = ReadyWith(Aligned([0; 64]));
+        let layout = future_layout(&fut);
+        assert_eq!(64, layout.align());
+        drop(recycler.allocate(fut));
+        assert_eq!(Some(layout), recycler.cached_layout());
+
+        // The layout is reported while the storage is in use
+        let fut = recycler.allocate(ReadyWith(Aligned([1; 64])));
+        assert_eq!(Some(layout), recycler.cached_layout());
+        drop(fut);
+    }
+
+    #[test]
+    fn cached_layout_tracks_the_storage_of_growing_allocators() {
+        let mut recycler = RecyclableFutureAllocator::growing();
+        let small = ReadyWith(0u32);
+        let small_layout = future_layout(&small);
+        drop(recycler.allocate(small));
+        assert_eq!(Some(small_layout), recycler.cached_layout());
+
+        let big = ReadyWith([0u64; 8]);
+        let big_layout = future_layout(&big);
+        drop(recycler.allocate(big));
+        assert_eq!(Some(big_layout), recycler.cached_layout());
+
+        // Smaller futures reuse the grown storage
+        drop(recycler.allocate(ReadyWith(1u32)));
+        assert_eq!(Some(big_layout), recycler.cached_layout());
+
+        let reserved = Layout::from_size_align(256, 16).unwrap();
+        recycler.reserve(reserved);
+        assert_eq!(Some(reserved), recycler.cached_layout());
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn zero_sized_streams_are_not_stored() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Once;
+
+        impl Stream for Once {
+            type Item = u32;
+
+            fn poll_next(
+                self: core::pin::Pin<&mut Self>,
+                _cx: &mut core::task::Context<'_>,
+            ) -> Poll<Option<u32>> {
+                Poll::Ready(None)
+            }
+        }
+
+        impl Drop for Once {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone());
+        for round in 0..3 {
+            let mut stream = recycler.allocate_stream(Once);
+            assert_eq!(None, futures::executor::block_on(stream.next()));
+            drop(stream);
+            assert_eq!(round + 1, DROPS.load(Ordering::Relaxed));
+        }
+        assert_eq!(0, alloc.allocations());
+        assert_eq!(None, recycler.cached_layout());
+
+        // Zero sized streams don't touch storage which is in use
+        let fut = recycler.allocate(ReadyWith(1u32));
+        drop(recycler.allocate_stream(futures::stream::empty::<u32>()));
+        assert_eq!(1, alloc.allocations());
+        drop(fut);
+        let (fut, reused) = recycler.allocate_tracked(ReadyWith(2u32));
+        assert_eq!(Reused::Recycled, reused);
+        drop(fut);
+    }
+
+    #[cfg(feature = "unstable-internals")]
+    #[test]
+    fn storage_with_extra_references_is_freed_by_the_last_reference() {
+        let drops = Cell::new(0);
+
+        // The future releases the last reference
+        let fut = new_recyclable_future_with_refcount(
+            {
+                let counter = DropCounter(&drops);
+                async move {
+                    let _counter = counter;
+                    yield_now().await;
+                    1u32
+                }
+            },
+            3,
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(3, unsafe { RecyclableFutureInfo::of(&fut) }.refcount);
+        let storage = fut.ptr();
+        unsafe { release_recyclable_reference(storage) };
+        assert_eq!(2, unsafe { RecyclableFutureInfo::of(&fut) }.refcount);
+        unsafe { release_recyclable_reference(storage) };
+        assert_eq!(1, futures::executor::block_on(fut));
+        assert_eq!(1, drops.get());
+
+        // A handle releases the last reference
+        let mut fut = new_recyclable_future_with_refcount(yield_value(2), 2)
+            .ok()
+            .unwrap();
+        assert!(poll_once(&mut fut).is_pending());
+        let storage = fut.ptr();
+        drop(fut);
+        unsafe { release_recyclable_reference(storage) };
+
+        // A single reference belongs to the future
+        let fut = new_recyclable_future_with_refcount(yield_value(3), 1)
+            .ok()
+            .unwrap();
+        assert_eq!(1, unsafe { RecyclableFutureInfo::of(&fut) }.refcount);
+        assert_eq!(3, futures::executor::block_on(fut));
+    }
+
+    #[cfg(feature = "unstable-internals")]
+    #[test]
+    #[should_panic(expected = "Recyclable storage requires a refcount of at least 1")]
+    fn storage_without_references_is_rejected() {
+        let _ = new_recyclable_future_with_refcount(yield_value(1), 0);
+    }
+
+    #[test]
+    fn replace_replaces_the_storage_after_max_reuses() {
+        let alloc = CountingAllocator::default();
+        let mut recycler = RecyclableFutureAllocator::new_in(alloc.clone()).with_max_reuses(2);
+        assert_eq!(
+            1,
+            futures::executor::block_on(recycler.allocate(yield_value(1)))
+        );
+        for value in 2..7 {
+            let fut = unsafe { recycler.replace(yield_value(value)) };
+            assert_eq!(value, futures::executor::block_on(fut));
+        }
+        // The storage was replaced once, for the fourth future
+        assert_eq!(2, alloc.allocations());
+        assert_eq!(1, alloc.deallocations());
+    }
+}
+
+// Run via `RUSTFLAGS="--cfg loom" cargo test --release --lib loom_tests`
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use core::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+    use loom::{cell::UnsafeCell, sync::Arc, thread};
+
+    /// Allows to move a value which is not `Send` into a thread of the model
+    struct AssertSend<T>(T);
+
+    unsafe impl<T> Send for AssertSend<T> {}
+
+    /// Memory which is written by the payload when it is dropped, and read when
+    /// its storage is freed. Loom reports a data race if freeing the storage
+    /// does not synchronize with dropping the payload.
+    type Tracked = Arc<UnsafeCell<usize>>;
+
+    /// A future which completes immediately, and which writes to the tracked
+    /// memory once it is dropped
+    struct Ready(Tracked);
+
+    impl Future for Ready {
+        type Output = u8;
+
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<u8> {
+            Poll::Ready(1)
+        }
+    }
+
+    impl Drop for Ready {
+        fn drop(&mut self) {
+            self.0.with_mut(|value| unsafe { *value += 1 });
+        }
+    }
+
+    /// An allocator which reads the tracked memory before freeing storage
+    #[derive(Clone)]
+    struct TrackingAllocator(Tracked);
+
+    unsafe impl Allocator for TrackingAllocator {
+        fn allocate(&self, layout: Layout) -> *mut u8 {
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+            self.0.with(|value| *value);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    fn new_recycler() -> (RecyclableFutureAllocator<TrackingAllocator>, Tracked) {
+        let tracked = Arc::new(UnsafeCell::new(0));
+        let recycler = RecyclableFutureAllocator::new_in(TrackingAllocator(tracked.clone()));
+        (recycler, tracked)
+    }
+
+    #[test]
+    fn future_and_allocator_are_dropped_concurrently() {
+        loom::model(|| {
+            let (mut recycler, tracked) = new_recycler();
+            let fut = AssertSend(recycler.allocate(Ready(tracked)));
+            let handle = thread::spawn(move || drop(fut));
+            drop(recycler);
+            handle.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn future_completes_on_another_thread_while_the_allocator_is_dropped() {
+        loom::model(|| {
+            let (mut recycler, tracked) = new_recycler();
+            let fut = AssertSend(recycler.allocate(Ready(tracked)));
+            let handle = thread::spawn(move || {
+                let mut fut = fut;
+                let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+                assert_eq!(Poll::Ready(1), fut.0.poll_unpin(&mut cx));
+            });
+            drop(recycler);
+            handle.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn storage_is_reused_while_the_future_is_dropped_on_another_thread() {
+        loom::model(|| {
+            let (mut recycler, tracked) = new_recycler();
+            let fut = AssertSend(recycler.allocate(Ready(tracked.clone())));
+            let handle = thread::spawn(move || drop(fut));
+
+            let other = Arc::new(UnsafeCell::new(0));
+            let (fut, reused) = recycler.allocate_tracked(Ready(other));
+            if reused == Reused::Recycled {
+                // Reusing the storage synchronizes with the drop of the
+                // previous future
+                tracked.with(|value| assert_eq!(1, unsafe { *value }));
+            } else {
+                assert_eq!(Reused::BoxedFallback, reused);
+            }
+            handle.join().unwrap();
+            drop(fut);
+        });
+    }
+
+    #[test]
+    fn storage_is_cleared_while_the_future_is_dropped_on_another_thread() {
+        loom::model(|| {
+            let (mut recycler, tracked) = new_recycler();
+            let fut = AssertSend(recycler.allocate(Ready(tracked)));
+            let handle = thread::spawn(move || drop(fut));
+            recycler.clear();
+            handle.join().unwrap();
+        });
     }
 }
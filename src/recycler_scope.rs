@@ -0,0 +1,39 @@
+use crate::{Allocator, DynamicFuture, Global, RecyclableFutureAllocator};
+use core::{future::Future, marker::PhantomData};
+
+/// Allocates futures via a `RecyclableFutureAllocator` which can not escape a
+/// scope.
+///
+/// It is created via [`RecyclableFutureAllocator::scope`]. All futures which
+/// are returned by it are bound to the lifetime `'s` of the scope, and may
+/// borrow anything which outlives the scope (`'env`). Since the closure which
+/// is passed to `scope` must work for any `'s`, neither the futures nor
+/// anything which contains them can be part of its return value.
+pub struct RecyclerScope<'s, 'env: 's, A: Allocator = Global> {
+    recycler: &'env mut RecyclableFutureAllocator<A>,
+    /// Makes the scope invariant over `'s`, so that it can not be converted
+    /// into a scope of a longer lifetime which would allow futures to escape
+    _scope: PhantomData<fn(&'s ()) -> &'s ()>,
+}
+
+impl<'s, 'env, A: Allocator> RecyclerScope<'s, 'env, A> {
+    pub(crate) fn new(recycler: &'env mut RecyclableFutureAllocator<A>) -> Self {
+        Self {
+            recycler,
+            _scope: PhantomData,
+        }
+    }
+}
+
+impl<'s, 'env, A: Allocator + Clone + 's> RecyclerScope<'s, 'env, A> {
+    /// Transforms the passed future into a `DynamicFuture` which is bound to
+    /// this scope.
+    ///
+    /// See [`RecyclableFutureAllocator::allocate`].
+    pub fn allocate<F, T>(&mut self, fut: F) -> DynamicFuture<'s, T>
+    where
+        F: Future<Output = T> + 's,
+    {
+        self.recycler.allocate(fut)
+    }
+}
@@ -0,0 +1,142 @@
+use crate::{DynamicFuture, RecyclableFutureAllocator};
+use alloc::rc::Rc;
+use core::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A `RecyclableFutureAllocator` which is shared via reference counting.
+///
+/// Cloning a `SharedRecycler` returns another handle to the same allocator.
+/// Futures which are allocated via the handle own a handle themselves, and
+/// are therefore not bound to the lifetime of an object which embeds the
+/// allocator. This allows to hand `'static` futures to a spawner - e.g. one
+/// for `!Send` futures like `spawn_local` - while the following allocations
+/// through any of the handles still reuse the storage of the allocator.
+#[derive(Clone, Default)]
+pub struct SharedRecycler {
+    inner: Rc<RefCell<RecyclableFutureAllocator>>,
+}
+
+impl SharedRecycler {
+    /// Creates a handle to a new `RecyclableFutureAllocator`
+    pub fn new() -> Self {
+        Self::from(RecyclableFutureAllocator::new())
+    }
+
+    /// Transforms the passed future into an `OwnedRecycledFuture`.
+    ///
+    /// The future is allocated via the shared `RecyclableFutureAllocator`,
+    /// and reuses its storage if possible.
+    pub fn allocate<F, T>(&self, fut: F) -> OwnedRecycledFuture<T>
+    where
+        F: Future<Output = T> + 'static,
+        T: 'static,
+    {
+        // Allocating does not run any code of the future, which could try to
+        // access the allocator again.
+        let fut = self.inner.borrow_mut().allocate(fut);
+        OwnedRecycledFuture {
+            fut,
+            recycler: self.clone(),
+        }
+    }
+}
+
+impl From<RecyclableFutureAllocator> for SharedRecycler {
+    fn from(recycler: RecyclableFutureAllocator) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(recycler)),
+        }
+    }
+}
+
+/// A future which was allocated via a `SharedRecycler`, and which keeps the
+/// shared allocator alive.
+///
+/// The allocator is dropped once the last `SharedRecycler` handle and the last
+/// future which was allocated via it are dropped.
+#[must_use = "futures do nothing unless polled"]
+pub struct OwnedRecycledFuture<T: 'static> {
+    // The future is declared first, so that it is dropped before the handle
+    fut: DynamicFuture<'static, T>,
+    recycler: SharedRecycler,
+}
+
+impl<T: 'static> OwnedRecycledFuture<T> {
+    /// Returns the handle to the allocator which allocated this future
+    pub fn recycler(&self) -> &SharedRecycler {
+        &self.recycler
+    }
+}
+
+impl<T: 'static> Future for OwnedRecycledFuture<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        self.get_mut().fut.poll_unpin(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::yield_value;
+    use alloc::vec::Vec;
+    use core::cell::Cell;
+    use futures::{executor::LocalPool, task::LocalSpawnExt};
+
+    #[test]
+    fn spawned_futures_reuse_the_storage() {
+        let mut pool = LocalPool::new();
+        let spawner = pool.spawner();
+        let recycler = SharedRecycler::new();
+        let handles = [recycler.clone(), recycler.clone(), recycler];
+        let sum = Rc::new(Cell::new(0));
+
+        let mut storage = Vec::new();
+        for round in 0..3 {
+            for handle in handles.iter() {
+                let fut = handle.allocate(yield_value(round));
+                storage.push(fut.fut.ptr());
+                let sum = sum.clone();
+                spawner
+                    .spawn_local(async move { sum.set(sum.get() + fut.await) })
+                    .unwrap();
+                pool.run();
+            }
+        }
+        assert_eq!(3 * (1 + 2), sum.get());
+        // The storage is reused once the previous future completed
+        assert!(storage.iter().all(|ptr| *ptr == storage[0]));
+    }
+
+    #[test]
+    fn spawned_futures_keep_the_allocator_alive() {
+        let mut pool = LocalPool::new();
+        let recycler = SharedRecycler::new();
+        let allocator = Rc::downgrade(&recycler.inner);
+        let fut = recycler.clone().allocate(yield_value(1));
+        drop(recycler);
+
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        let output = Rc::new(Cell::new(None));
+        let result = output.clone();
+        pool.spawner()
+            .spawn_local(async move {
+                receiver.await.unwrap();
+                result.set(Some(fut.await));
+            })
+            .unwrap();
+
+        // The spawned future still owns a handle to the allocator
+        pool.run_until_stalled();
+        assert!(allocator.upgrade().is_some());
+        sender.send(()).unwrap();
+        pool.run();
+        assert_eq!(Some(1), output.get());
+        assert!(allocator.upgrade().is_none());
+    }
+}
@@ -0,0 +1,177 @@
+//! A `Future` which stores small futures inline instead of on the heap.
+
+use crate::{box_future, vtable_for, DynamicFuture, DynamicFutureVtable};
+use core::{
+    future::Future,
+    marker::PhantomPinned,
+    mem::{align_of, size_of, MaybeUninit},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Inline storage for a type erased future of up to `N` bytes.
+///
+/// The alignment needs to match [`SmallDynamicFuture::MAX_ALIGN`].
+#[repr(C, align(16))]
+struct InlineStorage<const N: usize>([MaybeUninit<u8>; N]);
+
+enum Repr<'a, T, const N: usize> {
+    /// The future is stored inside `storage` and is driven through `vtable`
+    Inline {
+        storage: InlineStorage<N>,
+        vtable: &'a DynamicFutureVtable<T>,
+    },
+    /// The future did not fit into the inline storage and is stored on the heap
+    Heap(DynamicFuture<'a, T>),
+}
+
+/// A dynamically dispatched `Future` which stores futures of up to `N` bytes
+/// inline.
+///
+/// Futures whose size is at most `N` bytes and whose alignment is at most
+/// [`SmallDynamicFuture::MAX_ALIGN`] are stored in a buffer inside the
+/// `SmallDynamicFuture`, and thereby don't require a heap allocation. Bigger
+/// futures are stored on the heap via [`box_future`].
+///
+/// Since an inline future must not move after it has been polled, this
+/// `Future` is `!Unpin` and needs to be pinned before it can be polled.
+#[must_use = "futures do nothing unless polled"]
+pub struct SmallDynamicFuture<'a, T, const N: usize> {
+    repr: Repr<'a, T, N>,
+    _pin: PhantomPinned,
+}
+
+impl<'a, T, const N: usize> SmallDynamicFuture<'a, T, N> {
+    /// The maximum alignment of futures which can be stored inline
+    pub const MAX_ALIGN: usize = 16;
+
+    /// Creates a new `SmallDynamicFuture`.
+    ///
+    /// The future is stored inline if it fits into the inline storage, and on
+    /// the heap otherwise.
+    pub fn new<F>(fut: F) -> Self
+    where
+        F: Future<Output = T> + 'a,
+    {
+        let repr = if size_of::<F>() <= N && align_of::<F>() <= Self::MAX_ALIGN {
+            let mut storage = InlineStorage([MaybeUninit::uninit(); N]);
+            // The future has not been polled yet, and can therefore still be
+            // moved together with the storage.
+            unsafe {
+                core::ptr::write(storage.0.as_mut_ptr() as *mut F, fut);
+            }
+            Repr::Inline {
+                storage,
+                vtable: vtable_for::<F, T>(),
+            }
+        } else {
+            Repr::Heap(box_future(fut))
+        };
+
+        Self {
+            repr,
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// Returns whether the future is stored inline
+    pub fn is_inline(&self) -> bool {
+        matches!(self.repr, Repr::Inline { .. })
+    }
+}
+
+impl<'a, T, const N: usize> Drop for SmallDynamicFuture<'a, T, N> {
+    fn drop(&mut self) {
+        if let Repr::Inline { storage, vtable } = &mut self.repr {
+            // Delegate destruction of the inline `Future` to the vtable
+            unsafe {
+                (vtable.drop_fn)(storage.0.as_mut_ptr() as *const ());
+            }
+        }
+    }
+}
+
+impl<'a, T, const N: usize> Future for SmallDynamicFuture<'a, T, N> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // The inline storage is never moved out of the pinned `Future`
+        let this = unsafe { self.get_unchecked_mut() };
+        match &mut this.repr {
+            Repr::Inline { storage, vtable } => unsafe {
+                (vtable.poll_fn)(storage.0.as_mut_ptr() as *const (), cx)
+            },
+            Repr::Heap(fut) => fut.poll_unpin(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{yield_now, DropCounter};
+    use core::cell::Cell;
+    use futures::executor::block_on;
+
+    #[test]
+    fn small_futures_are_stored_inline() {
+        let value = [3u8; 8];
+        let fut = SmallDynamicFuture::<_, 64>::new(async move {
+            yield_now().await;
+            value[7]
+        });
+        assert!(fut.is_inline());
+        assert_eq!(3, block_on(fut));
+    }
+
+    #[test]
+    fn big_futures_are_stored_on_the_heap() {
+        let value = [4u8; 128];
+        let fut = SmallDynamicFuture::<_, 64>::new(async move {
+            yield_now().await;
+            value[127]
+        });
+        assert!(!fut.is_inline());
+        assert_eq!(4, block_on(fut));
+    }
+
+    #[repr(align(32))]
+    struct OverAligned(u8);
+
+    #[test]
+    fn over_aligned_futures_are_stored_on_the_heap() {
+        let value = OverAligned(5);
+        let fut = SmallDynamicFuture::<_, 64>::new(async move { value.0 });
+        assert!(!fut.is_inline());
+        assert_eq!(5, block_on(fut));
+    }
+
+    /// Polls `fut` once as a `SmallDynamicFuture`, and returns whether it was
+    /// stored inline
+    fn poll_and_drop<F: Future>(fut: F) -> bool {
+        let mut fut = core::pin::pin!(SmallDynamicFuture::<_, 64>::new(fut));
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+        fut.is_inline()
+    }
+
+    #[test]
+    fn pending_futures_are_dropped_in_both_representations() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        assert!(poll_and_drop(async move {
+            let _counter = counter;
+            yield_now().await;
+        }));
+        assert_eq!(1, drops.get());
+
+        let counter = DropCounter(&drops);
+        let padding = [0u8; 128];
+        assert!(!poll_and_drop(async move {
+            let _counter = counter;
+            yield_now().await;
+            padding[0]
+        }));
+        assert_eq!(2, drops.get());
+    }
+}
@@ -0,0 +1,448 @@
+//! Polling and dropping of payloads which are stored behind the pointer of a
+//! `DynamicFuture`, `DynamicStream` or `DynamicSink`.
+//!
+//! The vtables of this crate only differ in how the payload is located from
+//! the pointer, and in how the storage is released once the payload had been
+//! dropped. These differences are described by implementations of
+//! `PayloadStorage`, and the vtable functions are generic over them.
+//!
+//! The vtables are returned as references to struct literals. Since the
+//! literals only consist of function pointers, they are constant expressions
+//! which the compiler promotes to `static`s - one per monomorphization. A
+//! literal which could not be promoted would be a temporary of the function,
+//! and returning a reference to it would fail to compile. The references are
+//! therefore valid for any lifetime, and creating a `DynamicFuture` does not
+//! allocate a vtable. However Rust does not guarantee that a promoted constant
+//! has a unique address: Copies of the same vtable might exist in different
+//! codegen units or crates.
+
+use crate::{dynamic_future::poll_future_at, DynamicFutureVtable};
+#[cfg(feature = "futures")]
+use crate::{DynamicSinkVtable, DynamicStreamVtable};
+#[cfg(debug_assertions)]
+use core::cell::Cell;
+#[cfg(feature = "futures")]
+use core::pin::Pin;
+use core::{
+    future::Future,
+    marker::PhantomData,
+    task::{Context, Poll},
+};
+#[cfg(feature = "futures")]
+use futures_core::Stream;
+#[cfg(feature = "futures")]
+use futures_sink::Sink;
+
+/// Describes the storage of a payload of type `P`, which is referenced by the
+/// pointer of a `DynamicFuture`, `DynamicStream` or `DynamicSink`.
+pub(crate) trait PayloadStorage<P> {
+    /// Returns the location of the payload inside the storage which is
+    /// referenced by `ptr`
+    unsafe fn payload(ptr: *const ()) -> *mut P;
+
+    /// Releases the storage which is referenced by `ptr`, after the payload
+    /// had been dropped
+    unsafe fn release(ptr: *const ());
+
+    /// Returns the size in bytes of the storage which is available for the
+    /// payload
+    unsafe fn size(_ptr: *const ()) -> usize {
+        core::mem::size_of::<P>()
+    }
+
+    /// Gets called before the payload at `payload` is polled
+    unsafe fn before_poll(_ptr: *const (), _payload: *mut P) {}
+
+    /// Returns the flag which tracks whether the payload is currently being
+    /// polled, if the storage provides one
+    #[cfg(debug_assertions)]
+    unsafe fn polling<'s>(_ptr: *const ()) -> Option<&'s Cell<bool>> {
+        None
+    }
+}
+
+/// Detects reentrant polls of a payload in debug builds.
+///
+/// The guard marks the payload as being polled while it is alive, and panics
+/// if the payload is already marked. A payload which gets polled again from
+/// within its own `poll` would otherwise be accessed via two mutable
+/// references at the same time. The mark is also removed if `poll` panics.
+#[cfg(debug_assertions)]
+pub(crate) struct PollGuard<'f>(&'f Cell<bool>);
+
+#[cfg(debug_assertions)]
+impl<'f> PollGuard<'f> {
+    pub(crate) fn enter(polling: &'f Cell<bool>) -> Self {
+        assert!(
+            !polling.replace(true),
+            "DynamicFuture was polled reentrantly from within its own poll"
+        );
+        PollGuard(polling)
+    }
+}
+
+#[cfg(debug_assertions)]
+impl<'f> Drop for PollGuard<'f> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
+
+/// A payload which is stored at the location the pointer refers to, and whose
+/// storage is owned by someone else
+pub(crate) struct InPlace;
+
+impl<P> PayloadStorage<P> for InPlace {
+    unsafe fn payload(ptr: *const ()) -> *mut P {
+        ptr as *const P as *mut P
+    }
+
+    unsafe fn release(_ptr: *const ()) {}
+}
+
+/// Polls the `Future` which is stored in the storage `S`
+pub(crate) unsafe fn poll_stored_future<T, F, S>(ptr: *const (), cx: &mut Context<'_>) -> Poll<T>
+where
+    F: Future<Output = T>,
+    S: PayloadStorage<F>,
+{
+    let fut = S::payload(ptr);
+    S::before_poll(ptr, fut);
+    #[cfg(debug_assertions)]
+    let _guard = S::polling(ptr).map(PollGuard::enter);
+    poll_future_at::<T, F>(fut as *const (), cx)
+}
+
+/// Polls the `Stream` which is stored in the storage `S`
+#[cfg(feature = "futures")]
+pub(crate) unsafe fn poll_next_stored_stream<T, St, S>(
+    ptr: *const (),
+    cx: &mut Context<'_>,
+) -> Poll<Option<T>>
+where
+    St: Stream<Item = T>,
+    S: PayloadStorage<St>,
+{
+    let stream = S::payload(ptr);
+    S::before_poll(ptr, stream);
+    #[cfg(debug_assertions)]
+    let _guard = S::polling(ptr).map(PollGuard::enter);
+    Pin::new_unchecked(&mut *stream).poll_next(cx)
+}
+
+/// Returns the bounds on the remaining length of the `Stream` which is stored in
+/// the storage `S`
+#[cfg(feature = "futures")]
+pub(crate) unsafe fn stored_stream_size_hint<St, S>(ptr: *const ()) -> (usize, Option<usize>)
+where
+    St: Stream,
+    S: PayloadStorage<St>,
+{
+    (*S::payload(ptr)).size_hint()
+}
+
+/// Returns the `Sink` which is stored in the storage `S`, pinned at its
+/// location
+#[cfg(feature = "futures")]
+unsafe fn pinned_stored_sink<'s, Item, Si, S>(ptr: *const ()) -> Pin<&'s mut Si>
+where
+    Si: Sink<Item>,
+    S: PayloadStorage<Si>,
+{
+    let sink = S::payload(ptr);
+    S::before_poll(ptr, sink);
+    Pin::new_unchecked(&mut *sink)
+}
+
+/// Prepares the `Sink` which is stored in the storage `S` to receive a value
+#[cfg(feature = "futures")]
+unsafe fn poll_ready_stored_sink<Item, E, Si, S>(
+    ptr: *const (),
+    cx: &mut Context<'_>,
+) -> Poll<Result<(), E>>
+where
+    Si: Sink<Item, Error = E>,
+    S: PayloadStorage<Si>,
+{
+    pinned_stored_sink::<Item, Si, S>(ptr).poll_ready(cx)
+}
+
+/// Sends a value to the `Sink` which is stored in the storage `S`
+#[cfg(feature = "futures")]
+unsafe fn start_send_stored_sink<Item, E, Si, S>(ptr: *const (), item: Item) -> Result<(), E>
+where
+    Si: Sink<Item, Error = E>,
+    S: PayloadStorage<Si>,
+{
+    pinned_stored_sink::<Item, Si, S>(ptr).start_send(item)
+}
+
+/// Flushes the `Sink` which is stored in the storage `S`
+#[cfg(feature = "futures")]
+unsafe fn poll_flush_stored_sink<Item, E, Si, S>(
+    ptr: *const (),
+    cx: &mut Context<'_>,
+) -> Poll<Result<(), E>>
+where
+    Si: Sink<Item, Error = E>,
+    S: PayloadStorage<Si>,
+{
+    pinned_stored_sink::<Item, Si, S>(ptr).poll_flush(cx)
+}
+
+/// Closes the `Sink` which is stored in the storage `S`
+#[cfg(feature = "futures")]
+unsafe fn poll_close_stored_sink<Item, E, Si, S>(
+    ptr: *const (),
+    cx: &mut Context<'_>,
+) -> Poll<Result<(), E>>
+where
+    Si: Sink<Item, Error = E>,
+    S: PayloadStorage<Si>,
+{
+    pinned_stored_sink::<Item, Si, S>(ptr).poll_close(cx)
+}
+
+/// Releases the storage `S` once it is dropped
+struct ReleaseGuard<P, S: PayloadStorage<P>> {
+    ptr: *const (),
+    _phantom: PhantomData<fn(P, S)>,
+}
+
+impl<P, S: PayloadStorage<P>> Drop for ReleaseGuard<P, S> {
+    fn drop(&mut self) {
+        unsafe {
+            S::release(self.ptr);
+        }
+    }
+}
+
+/// Drops the payload which is stored in the storage `S`, and releases the
+/// storage afterwards.
+///
+/// The storage is also released if dropping the payload panics, since the
+/// payload is never accessed again.
+pub(crate) unsafe fn drop_stored_payload<P, S: PayloadStorage<P>>(ptr: *const ()) {
+    let _guard = ReleaseGuard::<P, S> {
+        ptr,
+        _phantom: PhantomData,
+    };
+    core::ptr::drop_in_place(S::payload(ptr));
+}
+
+/// Returns the size of the storage `S`
+pub(crate) unsafe fn stored_payload_size<P, S: PayloadStorage<P>>(ptr: *const ()) -> usize {
+    S::size(ptr)
+}
+
+/// Returns the vtable for a `Future` of type `F` which is stored in the
+/// storage `S`
+pub(crate) fn stored_future_vtable<'a, F, T, S>() -> &'a DynamicFutureVtable<T>
+where
+    F: Future<Output = T> + 'a,
+    S: PayloadStorage<F> + 'a,
+{
+    &DynamicFutureVtable {
+        drop_fn: drop_stored_payload::<F, S>,
+        poll_fn: poll_stored_future::<T, F, S>,
+        size_fn: Some(stored_payload_size::<F, S>),
+        type_id_fn: None,
+    }
+}
+
+/// Returns the vtable for a `Stream` of type `St` which is stored in the
+/// storage `S`
+#[cfg(feature = "futures")]
+pub(crate) fn stored_stream_vtable<'a, St, T, S>() -> &'a DynamicStreamVtable<T>
+where
+    St: Stream<Item = T> + 'a,
+    S: PayloadStorage<St> + 'a,
+{
+    &DynamicStreamVtable {
+        drop_fn: drop_stored_payload::<St, S>,
+        poll_next_fn: poll_next_stored_stream::<T, St, S>,
+        remaining_hint_fn: Some(stored_stream_size_hint::<St, S>),
+    }
+}
+
+/// Returns the vtable for a `Sink` of type `Si` which is stored in the storage
+/// `S`
+#[cfg(feature = "futures")]
+pub(crate) fn stored_sink_vtable<'a, Si, Item, E, S>() -> &'a DynamicSinkVtable<Item, E>
+where
+    Si: Sink<Item, Error = E> + 'a,
+    S: PayloadStorage<Si> + 'a,
+{
+    &DynamicSinkVtable {
+        poll_ready_fn: poll_ready_stored_sink::<Item, E, Si, S>,
+        start_send_fn: start_send_stored_sink::<Item, E, Si, S>,
+        poll_flush_fn: poll_flush_stored_sink::<Item, E, Si, S>,
+        poll_close_fn: poll_close_stored_sink::<Item, E, Si, S>,
+        drop_fn: drop_stored_payload::<Si, S>,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{poll_once, yield_now, DropCounter, PanicOnDrop},
+        DynamicFuture,
+    };
+    use core::{
+        cell::{Cell, UnsafeCell},
+        mem::ManuallyDrop,
+    };
+
+    /// Storage which places the payload after two counters, that record the
+    /// calls of the storage functions.
+    ///
+    /// The payload is accessed via a shared reference to the slot, and is
+    /// therefore placed in an `UnsafeCell`.
+    struct Slot<P> {
+        before_polls: Cell<usize>,
+        releases: Cell<usize>,
+        payload: UnsafeCell<P>,
+    }
+
+    struct InSlot;
+
+    impl<P> PayloadStorage<P> for InSlot {
+        unsafe fn payload(ptr: *const ()) -> *mut P {
+            (*(ptr as *const Slot<P>)).payload.get()
+        }
+
+        unsafe fn release(ptr: *const ()) {
+            let releases = &(*(ptr as *const Slot<P>)).releases;
+            releases.set(releases.get() + 1);
+        }
+
+        unsafe fn before_poll(ptr: *const (), payload: *mut P) {
+            assert_eq!(Self::payload(ptr), payload);
+            let before_polls = &(*(ptr as *const Slot<P>)).before_polls;
+            before_polls.set(before_polls.get() + 1);
+        }
+    }
+
+    /// Returns a `DynamicFuture` for the payload of `slot`. The payload is
+    /// dropped with the future.
+    fn slot_future<'a, F: Future + 'a>(
+        slot: &'a ManuallyDrop<Slot<F>>,
+    ) -> DynamicFuture<'a, F::Output> {
+        unsafe {
+            DynamicFuture::new(
+                &**slot as *const Slot<F> as *const (),
+                stored_future_vtable::<F, F::Output, InSlot>(),
+            )
+        }
+    }
+
+    #[test]
+    fn payload_is_located_polled_and_released_via_the_storage() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let slot = ManuallyDrop::new(Slot {
+            before_polls: Cell::new(0),
+            releases: Cell::new(0),
+            payload: UnsafeCell::new(async move {
+                let _counter = counter;
+                yield_now().await;
+                5u32
+            }),
+        });
+
+        let mut fut = slot_future(&slot);
+        assert!(poll_once(&mut fut).is_pending());
+        assert_eq!(Poll::Ready(5), poll_once(&mut fut));
+        assert_eq!(2, slot.before_polls.get());
+        assert_eq!(0, slot.releases.get());
+
+        drop(fut);
+        assert_eq!(1, drops.get());
+        assert_eq!(1, slot.releases.get());
+    }
+
+    #[test]
+    fn storage_is_released_if_dropping_the_payload_panics() {
+        let slot = ManuallyDrop::new(Slot {
+            before_polls: Cell::new(0),
+            releases: Cell::new(0),
+            payload: UnsafeCell::new(PanicOnDrop),
+        });
+        let fut = slot_future(&slot);
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || drop(fut)));
+        assert!(res.is_err());
+        assert_eq!(1, slot.releases.get());
+    }
+
+    /// The storage and the poll function of a type erased `Future`
+    #[cfg(debug_assertions)]
+    type Target = Option<(
+        *const (),
+        unsafe fn(*const (), &mut Context<'_>) -> Poll<u32>,
+    )>;
+
+    #[cfg(debug_assertions)]
+    std::thread_local! {
+        /// The `Future` which is polled by `Reentrant`
+        static TARGET: Cell<Target> = const { Cell::new(None) };
+    }
+
+    /// A `Future` which polls the `Future` in `TARGET` from within its own
+    /// `poll`, as long as `TARGET` is set. Resolves to its value otherwise.
+    ///
+    /// The `Future` is not zero sized, since zero sized `Future`s are not
+    /// stored in storage which could detect reentrant polls.
+    #[cfg(debug_assertions)]
+    struct Reentrant(u32);
+
+    #[cfg(debug_assertions)]
+    impl Future for Reentrant {
+        type Output = u32;
+
+        fn poll(self: core::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u32> {
+            match TARGET.with(|target| target.get()) {
+                Some((ptr, poll_fn)) => unsafe { poll_fn(ptr, cx) },
+                None => Poll::Ready(self.0),
+            }
+        }
+    }
+
+    /// Polls `fut` reentrantly, and returns the panic message of the guard
+    #[cfg(debug_assertions)]
+    fn reentrant_poll_panic(mut fut: DynamicFuture<'_, u32>) -> &'static str {
+        let target = (fut.ptr(), fut.vtable().poll_fn);
+        TARGET.with(|t| t.set(Some(target)));
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| poll_once(&mut fut)))
+            .unwrap_err();
+
+        // The guard released the mark while unwinding, and the future can
+        // still be polled
+        TARGET.with(|t| t.set(None));
+        assert_eq!(Poll::Ready(1), poll_once(&mut fut));
+        *panic.downcast::<&'static str>().unwrap()
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn reentrant_polls_panic() {
+        const MESSAGE: &str = "DynamicFuture was polled reentrantly from within its own poll";
+
+        assert_eq!(
+            MESSAGE,
+            reentrant_poll_panic(crate::box_future(Reentrant(1)))
+        );
+        let fut = crate::try_box_future(Reentrant(1)).ok().unwrap();
+        assert_eq!(MESSAGE, reentrant_poll_panic(fut));
+        assert_eq!(
+            MESSAGE,
+            reentrant_poll_panic(crate::typed_box_future(Reentrant(1)))
+        );
+
+        let mut recycler = crate::RecyclableFutureAllocator::new();
+        assert_eq!(
+            MESSAGE,
+            reentrant_poll_panic(recycler.allocate(Reentrant(1)))
+        );
+    }
+}
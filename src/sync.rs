@@ -0,0 +1,153 @@
+//! Synchronization primitives which are used by the crate.
+//!
+//! When building with `--cfg loom` these are replaced by the primitives of the
+//! [loom](https://docs.rs/loom) model checker, which allows to exhaustively
+//! check the refcount protocol of recyclable storage.
+//!
+//! When building with `--cfg async_trait_experiments_single_threaded` the
+//! atomics are replaced by `Cell`s, which provide the same methods without
+//! atomic operations. This is a `cfg` flag instead of a feature, since it
+//! removes the `Send` implementations of the crate: A feature which is enabled
+//! by one crate in a dependency graph would break other crates which send
+//! allocators or their futures to other threads.
+
+#[cfg(all(not(loom), not(async_trait_experiments_single_threaded)))]
+pub(crate) use core::sync::atomic::{fence, AtomicBool, AtomicU8, AtomicUsize, Ordering};
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{fence, AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+#[cfg(all(not(loom), async_trait_experiments_single_threaded))]
+pub(crate) use self::single_threaded::{fence, AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+#[cfg(all(not(loom), async_trait_experiments_single_threaded))]
+#[allow(dead_code)]
+mod single_threaded {
+    use core::cell::Cell;
+    pub(crate) use core::sync::atomic::Ordering;
+
+    /// Does nothing, since there are no other threads to synchronize with
+    pub(crate) fn fence(_order: Ordering) {}
+
+    macro_rules! cell_atomic {
+        ($name:ident, $ty:ty) => {
+            /// A non-atomic replacement for the atomic type of the same name.
+            ///
+            /// It is `!Sync`, and the orderings which are passed to its
+            /// methods are ignored.
+            #[derive(Debug, Default)]
+            pub(crate) struct $name(Cell<$ty>);
+
+            impl $name {
+                pub(crate) const fn new(value: $ty) -> Self {
+                    Self(Cell::new(value))
+                }
+
+                pub(crate) fn load(&self, _order: Ordering) -> $ty {
+                    self.0.get()
+                }
+
+                pub(crate) fn store(&self, value: $ty, _order: Ordering) {
+                    self.0.set(value)
+                }
+
+                pub(crate) fn compare_exchange(
+                    &self,
+                    current: $ty,
+                    new: $ty,
+                    _success: Ordering,
+                    _failure: Ordering,
+                ) -> Result<$ty, $ty> {
+                    let value = self.0.get();
+                    if value == current {
+                        self.0.set(new);
+                        Ok(value)
+                    } else {
+                        Err(value)
+                    }
+                }
+            }
+        };
+        ($name:ident, $ty:ty, integer) => {
+            cell_atomic!($name, $ty);
+
+            impl $name {
+                pub(crate) fn fetch_add(&self, value: $ty, _order: Ordering) -> $ty {
+                    let previous = self.0.get();
+                    self.0.set(previous.wrapping_add(value));
+                    previous
+                }
+
+                pub(crate) fn fetch_sub(&self, value: $ty, _order: Ordering) -> $ty {
+                    let previous = self.0.get();
+                    self.0.set(previous.wrapping_sub(value));
+                    previous
+                }
+            }
+        };
+    }
+
+    cell_atomic!(AtomicBool, bool);
+    cell_atomic!(AtomicU8, u8, integer);
+    cell_atomic!(AtomicUsize, usize, integer);
+}
+
+#[cfg(all(test, not(loom), async_trait_experiments_single_threaded))]
+mod tests {
+    use super::*;
+    use crate::{test_utils::yield_now, RecyclableFutureAllocator, Reused};
+
+    #[test]
+    fn cells_provide_the_methods_of_atomics() {
+        let value = AtomicUsize::new(2);
+        assert_eq!(2, value.fetch_add(3, Ordering::Relaxed));
+        assert_eq!(5, value.fetch_sub(4, Ordering::Release));
+        assert_eq!(
+            Err(1),
+            value.compare_exchange(2, 7, Ordering::Acquire, Ordering::Relaxed)
+        );
+        assert_eq!(
+            Ok(1),
+            value.compare_exchange(1, 7, Ordering::Acquire, Ordering::Relaxed)
+        );
+        assert_eq!(7, value.load(Ordering::Relaxed));
+        value.store(0, Ordering::Relaxed);
+        assert_eq!(0, value.fetch_sub(1, Ordering::Relaxed));
+        assert_eq!(usize::MAX, value.load(Ordering::Relaxed));
+
+        let flag = AtomicBool::default();
+        assert_eq!(
+            Ok(false),
+            flag.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        );
+        assert_eq!(
+            Err(true),
+            flag.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        );
+    }
+
+    #[test]
+    fn storage_is_recycled_with_non_atomic_refcounts() {
+        let mut recycler = RecyclableFutureAllocator::new();
+        for round in 0..3u32 {
+            let (fut, reused) = recycler.allocate_tracked(async move {
+                yield_now().await;
+                round
+            });
+            let expected = if round == 0 {
+                Reused::FreshAllocation
+            } else {
+                Reused::Recycled
+            };
+            assert_eq!(expected, reused);
+
+            // Storage which is still in use is not reused
+            let (other, reused) = recycler.allocate_tracked(async move {
+                yield_now().await;
+                round
+            });
+            assert_eq!(Reused::BoxedFallback, reused);
+            assert_eq!(round, futures::executor::block_on(other));
+            assert_eq!(round, futures::executor::block_on(fut));
+        }
+    }
+}
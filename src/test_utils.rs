@@ -0,0 +1,130 @@
+//! Helpers which are shared by the unit tests of the crate.
+
+use crate::{Allocator, Global};
+use alloc::{alloc::Layout, rc::Rc};
+use core::{
+    cell::Cell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Polls `fut` once with a waker which does nothing
+pub(crate) fn poll_once<F: Future + Unpin>(fut: &mut F) -> Poll<F::Output> {
+    let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+    Pin::new(fut).poll(&mut cx)
+}
+
+/// A future which returns `Pending` once, and wakes itself before doing so
+#[derive(Debug, Default)]
+pub(crate) struct Yield {
+    yielded: bool,
+}
+
+impl Future for Yield {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            Poll::Ready(())
+        } else {
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+/// Returns a future which yields once before completing
+pub(crate) fn yield_now() -> Yield {
+    Yield::default()
+}
+
+/// Returns `value` after yielding once
+pub(crate) async fn yield_value(value: u32) -> u32 {
+    yield_now().await;
+    value
+}
+
+/// The amount of calls into a `CountingAllocator` and its clones
+#[derive(Debug, Default)]
+pub(crate) struct Counts {
+    allocations: Cell<usize>,
+    deallocations: Cell<usize>,
+}
+
+/// An allocator which forwards to the global allocator, and counts the calls
+/// into it. Clones share the counters.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct CountingAllocator(Rc<Counts>);
+
+impl CountingAllocator {
+    pub(crate) fn allocations(&self) -> usize {
+        self.0.allocations.get()
+    }
+
+    pub(crate) fn deallocations(&self) -> usize {
+        self.0.deallocations.get()
+    }
+}
+
+unsafe impl Allocator for CountingAllocator {
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        self.0.allocations.set(self.0.allocations.get() + 1);
+        Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        self.0.deallocations.set(self.0.deallocations.get() + 1);
+        Global.deallocate(ptr, layout)
+    }
+}
+
+/// An allocator which fails every allocation
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct FailingAllocator;
+
+unsafe impl Allocator for FailingAllocator {
+    fn allocate(&self, _layout: Layout) -> *mut u8 {
+        core::ptr::null_mut()
+    }
+
+    unsafe fn deallocate(&self, _ptr: *mut u8, _layout: Layout) {
+        unreachable!("Nothing was allocated");
+    }
+}
+
+/// Increments a counter once it is dropped
+#[derive(Debug)]
+pub(crate) struct DropCounter<'a>(pub(crate) &'a Cell<usize>);
+
+impl Drop for DropCounter<'_> {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+/// Returns `value` after yielding once. `_counter` is dropped along with the
+/// future.
+pub(crate) async fn counted_value(_counter: DropCounter<'_>, value: u32) -> u32 {
+    yield_now().await;
+    value
+}
+
+/// A future which completes immediately, and panics when it is dropped
+#[derive(Debug)]
+pub(crate) struct PanicOnDrop;
+
+impl Future for PanicOnDrop {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        Poll::Ready(())
+    }
+}
+
+impl Drop for PanicOnDrop {
+    fn drop(&mut self) {
+        panic!("drop panicked");
+    }
+}
@@ -0,0 +1,131 @@
+//! A pool of recyclable storage which is shared by all futures that are
+//! allocated on the same thread.
+
+use crate::{box_future, DynamicFuture, MultiSlotRecycler};
+use core::{cell::RefCell, future::Future};
+
+/// The maximum amount of slots which are retained by the pool of each thread
+const POOL_SLOTS: usize = 64;
+
+std::thread_local! {
+    static POOL: RefCell<MultiSlotRecycler> = RefCell::new(MultiSlotRecycler::new(POOL_SLOTS));
+}
+
+/// Transforms the passed future into a `DynamicFuture`, using storage from a
+/// pool which is shared by all futures that are allocated on the current thread.
+///
+/// In comparison to embedding a `RecyclableFutureAllocator` into each object,
+/// the pool allows to recycle storage between many objects which return futures
+/// of the same layout. The pool reuses the storage of any future of the same
+/// layout which is no longer in use, and retains storage for up to 64 futures.
+/// If no storage can be reused and the pool is full, the future is boxed.
+///
+/// Storage is only reused for futures of exactly the same layout, and is never
+/// evicted from the pool. Once the pool retains storage for 64 different
+/// layouts, futures of any other layout are therefore boxed for the remaining
+/// lifetime of the thread.
+///
+/// Every future holds a reference on its storage. The storage therefore stays
+/// valid until the future is dropped - even if the pool of the thread is
+/// destroyed before.
+pub fn thread_local_recycler<'a, F, T>(fut: F) -> DynamicFuture<'a, T>
+where
+    F: Future<Output = T> + 'a,
+{
+    let mut fut = Some(fut);
+    let res = POOL.try_with(|pool| {
+        // The pool can't be borrowed if the allocation is performed while the
+        // pool is accessed - e.g. from within a destructor.
+        pool.try_borrow_mut()
+            .ok()
+            .map(|mut pool| pool.allocate(fut.take().unwrap()))
+    });
+
+    match res {
+        Ok(Some(fut)) => fut,
+        // The pool is not accessible - e.g. because the thread is shutting down
+        _ => box_future(fut.take().unwrap()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{poll_once, yield_value};
+    use core::task::Poll;
+
+    #[test]
+    fn storage_is_shared_between_calls() {
+        async fn padded<const N: usize>(value: u32) -> u32 {
+            let padding = [value as u8; N];
+            yield_value(value).await + padding.len() as u32
+        }
+
+        let mut ptrs = std::vec::Vec::new();
+        for round in 0..300 {
+            let value = round as u32;
+            let (fut, expected) = match round % 3 {
+                0 => (thread_local_recycler(yield_value(value)), value),
+                1 => (thread_local_recycler(padded::<64>(value)), value + 64),
+                _ => (thread_local_recycler(padded::<512>(value)), value + 512),
+            };
+            if !ptrs.contains(&fut.ptr()) {
+                ptrs.push(fut.ptr());
+            }
+            assert_eq!(expected, futures::executor::block_on(fut));
+        }
+        // Every layout reuses the storage of its first future
+        assert_eq!(3, ptrs.len());
+    }
+
+    #[test]
+    fn futures_which_are_in_flight_use_separate_storage() {
+        let mut first = thread_local_recycler(yield_value(1));
+        let mut second = thread_local_recycler(yield_value(2));
+        assert_ne!(first.ptr(), second.ptr());
+        assert!(poll_once(&mut first).is_pending());
+        assert!(poll_once(&mut second).is_pending());
+        assert_eq!(Poll::Ready(2), poll_once(&mut second));
+        assert_eq!(Poll::Ready(1), poll_once(&mut first));
+    }
+
+    #[test]
+    fn futures_outlive_the_pool_of_their_thread() {
+        use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        static POOL_WAS_DESTROYED: AtomicBool = AtomicBool::new(false);
+
+        struct CountDrop;
+
+        impl Drop for CountDrop {
+            fn drop(&mut self) {
+                POOL_WAS_DESTROYED.store(POOL.try_with(|_| ()).is_err(), Ordering::Relaxed);
+                DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        std::thread_local! {
+            static LATE: core::cell::RefCell<Option<DynamicFuture<'static, u32>>> =
+                const { core::cell::RefCell::new(None) };
+        }
+
+        std::thread::spawn(|| {
+            // The destructor of `LATE` is registered before the one of the
+            // pool. It therefore drops the future after the pool was destroyed.
+            LATE.with(|_| {});
+            let counter = CountDrop;
+            let fut = thread_local_recycler(async move {
+                let _counter = counter;
+                yield_value(1).await
+            });
+            LATE.with(|late| *late.borrow_mut() = Some(fut));
+            assert_eq!(0, DROPS.load(Ordering::Relaxed));
+        })
+        .join()
+        .unwrap();
+        // The future was dropped together with the thread, after its pool
+        assert_eq!(1, DROPS.load(Ordering::Relaxed));
+        assert!(POOL_WAS_DESTROYED.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,8 @@
+#![deny(unused_must_use)]
+
+use async_trait_experiments::RecyclableFutureAllocator;
+
+fn main() {
+    let mut recycler = RecyclableFutureAllocator::new();
+    recycler.allocate(async { 1 });
+}
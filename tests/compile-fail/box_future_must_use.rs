@@ -0,0 +1,7 @@
+#![deny(unused_must_use)]
+
+use async_trait_experiments::box_future;
+
+fn main() {
+    box_future(async { 1 });
+}
@@ -0,0 +1,11 @@
+use async_trait_experiments::box_future_send;
+use std::rc::Rc;
+
+async fn hold_rc(rc: Rc<u32>) -> u32 {
+    async {}.await;
+    *rc
+}
+
+fn main() {
+    let _fut = box_future_send(hold_rc(Rc::new(1)));
+}
@@ -0,0 +1,8 @@
+use async_trait_experiments::{box_future, DynamicFuture};
+
+fn assert_send<T: Send>(_: T) {}
+
+fn main() {
+    let fut: DynamicFuture<'static, u32> = box_future(async { 1 });
+    assert_send(fut);
+}
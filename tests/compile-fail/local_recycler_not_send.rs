@@ -0,0 +1,7 @@
+use async_trait_experiments::LocalRecyclableFutureAllocator;
+
+fn assert_send<T: Send>(_: T) {}
+
+fn main() {
+    assert_send(LocalRecyclableFutureAllocator::new());
+}
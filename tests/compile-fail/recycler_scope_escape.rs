@@ -0,0 +1,7 @@
+use async_trait_experiments::RecyclableFutureAllocator;
+
+fn main() {
+    let mut recycler = RecyclableFutureAllocator::new();
+    let fut = recycler.scope(|scope| scope.allocate(async { 1 }));
+    drop(fut);
+}
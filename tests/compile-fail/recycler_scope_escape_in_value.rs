@@ -0,0 +1,7 @@
+use async_trait_experiments::RecyclableFutureAllocator;
+
+fn main() {
+    let mut recycler = RecyclableFutureAllocator::new();
+    let futures = recycler.scope(|scope| vec![scope.allocate(async { 1 })]);
+    drop(futures);
+}